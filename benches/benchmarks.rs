@@ -13,6 +13,7 @@ fn create_test_keys(count: usize) -> Vec<ApiKeyInfo> {
             models: vec!["gpt-3.5-turbo".to_string(), "others".to_string()],
             latency: Some(Duration::from_millis(50 + i as u64 * 10)),
             health_score: 1.0 - (i as f64 * 0.1),
+            ..Default::default()
         })
         .collect()
 }
@@ -20,7 +21,7 @@ fn create_test_keys(count: usize) -> Vec<ApiKeyInfo> {
 fn bench_key_selection(c: &mut Criterion) {
     let mut group = c.benchmark_group("key_selection");
 
-    for key_count in [5, 10, 25, 50, 100].iter() {
+    for key_count in [5, 10, 25, 50, 100, 500].iter() {
         let keys = create_test_keys(*key_count);
         let pool = KeyPool::new(keys, "round_robin");
 
@@ -38,6 +39,15 @@ fn bench_key_selection(c: &mut Criterion) {
             key_count,
             |b, _| b.iter(|| black_box(pool.get_key_for_model("gpt-3.5-turbo"))),
         );
+
+        for strategy in ["least_outstanding", "power_of_two_choices"] {
+            let keys = create_test_keys(*key_count);
+            let pool = KeyPool::new(keys, strategy);
+
+            group.bench_with_input(BenchmarkId::new(strategy, key_count), key_count, |b, _| {
+                b.iter(|| black_box(pool.get_key_for_model("gpt-3.5-turbo")))
+            });
+        }
     }
 
     group.finish();
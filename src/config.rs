@@ -1,6 +1,9 @@
+use crate::proxy::models::{ModelMatch, ModelMatcher};
 use anyhow::{Context, Result};
-use secrecy::SecretString;
+use once_cell::sync::OnceCell;
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -15,6 +18,28 @@ pub struct Config {
     pub rate_limit: RateLimitConfig,
     #[serde(default)]
     pub observability: ObservabilityConfig,
+    #[serde(default)]
+    pub models: ModelsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub coalesce: CoalesceConfig,
+    #[serde(default)]
+    pub batching: BatchingConfig,
+    #[serde(default)]
+    pub admission: AdmissionConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    #[serde(default)]
+    pub probes: ProbesConfig,
+    #[serde(default)]
+    pub headers: HeadersConfig,
+    #[serde(default)]
+    pub transforms: TransformsConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -47,8 +72,15 @@ pub struct UpstreamConfig {
 pub struct KeysConfig {
     #[serde(default = "default_rotation_strategy")]
     pub rotation_strategy: String,
+    /// Consecutive failures after which a key is taken out of rotation
     #[serde(default = "default_unhealthy_penalty")]
     pub unhealthy_penalty: u32,
+    /// How long an unhealthy key stays out before it is tried again
+    #[serde(default = "default_unhealthy_cooldown")]
+    pub unhealthy_cooldown_ms: u64,
+    /// Cooldown after a 429 that carries no `Retry-After`
+    #[serde(default = "default_rate_limit_cooldown")]
+    pub rate_limit_cooldown_ms: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -67,6 +99,311 @@ pub struct ObservabilityConfig {
     pub metrics_bind: String,
     #[serde(default = "default_tracing_level")]
     pub tracing_level: String,
+    /// OTLP/HTTP collector base URL, e.g. `http://tempo:4318`; traces are exported when set
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of exported traces
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Share of new traces exported, from 0.0 to 1.0; sampled client traces are always kept
+    #[serde(default = "default_trace_sample_ratio")]
+    pub trace_sample_ratio: f64,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Log one `access_log` line per finished request
+    #[serde(default = "default_true")]
+    pub access_log: bool,
+    /// Header identifying the calling client in access logs
+    #[serde(default = "default_client_header")]
+    pub client_header: String,
+}
+
+/// Layout of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Single line with span context
+    #[default]
+    Full,
+    /// Single line, span context shortened
+    Compact,
+    /// Multi-line, for reading locally
+    Pretty,
+    /// One JSON object per line, event fields at the top level
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// `memory` (LRU) or `disk`
+    #[serde(default = "default_cache_backend")]
+    pub backend: String,
+    #[serde(default = "default_cache_ttl")]
+    pub ttl_seconds: u64,
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_cache_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+    #[serde(default = "default_cache_max_total_bytes")]
+    pub max_total_bytes: usize,
+    #[serde(default = "default_cache_disk_path")]
+    pub disk_path: String,
+    /// Only cache requests that sample deterministically (`temperature: 0`, embeddings)
+    #[serde(default = "default_true")]
+    pub deterministic_only: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct CoalesceConfig {
+    /// Paths whose identical in-flight requests share one upstream call
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchingConfig {
+    /// Merge concurrent `/v1/embeddings` calls into one upstream request
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long the first request of a batch waits for others to join
+    #[serde(default = "default_batch_window")]
+    pub window_ms: u64,
+    /// Inputs per upstream request; a full batch is sent immediately
+    #[serde(default = "default_batch_max_inputs")]
+    pub max_inputs: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdmissionConfig {
+    /// Queue requests in priority classes once `max_concurrent` are in flight
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_admission_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Queued requests are shed with 503 after waiting this long
+    #[serde(default = "default_admission_max_wait")]
+    pub max_wait_ms: u64,
+    /// Request header naming a priority class; can only lower the client's class
+    #[serde(default = "default_priority_header")]
+    pub priority_header: String,
+    /// Request header identifying the client for fair sharing
+    #[serde(default = "default_client_header")]
+    pub client_header: String,
+    #[serde(default = "default_admission_class")]
+    pub default_class: String,
+    #[serde(default = "default_admission_classes")]
+    pub classes: HashMap<String, PriorityClassConfig>,
+    /// Client id to its class and fair-share weight
+    #[serde(default)]
+    pub clients: HashMap<String, AdmissionClientConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PriorityClassConfig {
+    /// Lower ranks are always admitted first
+    pub rank: u32,
+    #[serde(default = "default_class_max_queued")]
+    pub max_queued: usize,
+    /// Cap on this class's share of `max_concurrent`
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdmissionClientConfig {
+    #[serde(default)]
+    pub class: Option<String>,
+    #[serde(default = "default_client_weight")]
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoutingConfig {
+    /// Route requests of the same session to the same key while it is healthy
+    #[serde(default)]
+    pub sticky: bool,
+    /// Header carrying the session id; the body `user` field is used when absent
+    #[serde(default = "default_sticky_header")]
+    pub sticky_header: String,
+    #[serde(default)]
+    pub sticky_target: StickyTarget,
+}
+
+/// What a sticky session is pinned to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StickyTarget {
+    /// A single API key
+    #[default]
+    Key,
+    /// An upstream url; any key for that url may serve the session
+    Url,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProbesConfig {
+    /// Periodically send an authenticated request with every key
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_probe_interval")]
+    pub interval_ms: u64,
+    /// Random extra delay per probe so keys are not probed in lockstep
+    #[serde(default = "default_probe_jitter")]
+    pub jitter_ms: u64,
+    #[serde(default = "default_probe_timeout")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_probe_method")]
+    pub method: String,
+    /// OpenAI-style path, rewritten per provider like regular traffic
+    #[serde(default = "default_probe_path")]
+    pub path: String,
+    /// JSON body, e.g. a one-token completion when `path` is `/v1/chat/completions`
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct HeadersConfig {
+    /// Client headers forwarded upstream; empty forwards all. Names, or prefixes ending in `*`
+    #[serde(default)]
+    pub request_allow: Vec<String>,
+    /// Client headers never forwarded upstream
+    #[serde(default)]
+    pub request_deny: Vec<String>,
+    /// Upstream headers returned to the client; empty returns all
+    #[serde(default)]
+    pub response_allow: Vec<String>,
+    /// Upstream headers never returned to the client
+    #[serde(default)]
+    pub response_deny: Vec<String>,
+    /// Add `x-kcp-attempts`, the key id and status of every upstream attempt, to responses
+    #[serde(default)]
+    pub attempts_header: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditConfig {
+    /// Record request and response bodies as JSONL
+    #[serde(default)]
+    pub enabled: bool,
+    /// Directory the rotating `audit-*.jsonl` files are written to
+    #[serde(default = "default_audit_dir")]
+    pub dir: String,
+    /// Start a new file once the current one reaches this size
+    #[serde(default = "default_audit_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Oldest files beyond this count are deleted
+    #[serde(default = "default_audit_max_files")]
+    pub max_files: usize,
+    /// Bodies are recorded up to this size and marked truncated past it
+    #[serde(default = "default_audit_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Records waiting to be written; new records are dropped while it is full
+    #[serde(default = "default_audit_queue_size")]
+    pub queue_size: usize,
+    /// Share of requests recorded, from 0.0 to 1.0
+    #[serde(default = "default_audit_sample_rate")]
+    pub sample_rate: f64,
+    /// Per-route sample rates; paths, or prefixes ending in `*`
+    #[serde(default)]
+    pub paths: HashMap<String, f64>,
+    /// Per-client sample rates by `client_header` value; take precedence over `paths`
+    #[serde(default)]
+    pub clients: HashMap<String, f64>,
+    #[serde(default = "default_client_header")]
+    pub client_header: String,
+    /// Request headers recorded with their value masked, in addition to client credentials
+    #[serde(default)]
+    pub redact_headers: Vec<String>,
+    /// Regex masks applied to recorded bodies and header values
+    #[serde(default)]
+    pub pii_patterns: Vec<PiiPatternConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PiiPatternConfig {
+    pub pattern: String,
+    #[serde(default = "default_pii_replacement")]
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ValidationConfig {
+    /// Reject malformed chat, completion, embedding, image and speech requests locally
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TransformsConfig {
+    /// Request header identifying the client for `clients` scopes
+    #[serde(default = "default_client_header")]
+    pub client_header: String,
+    /// Applied in order to the parsed request body before it is forwarded
+    #[serde(default)]
+    pub request: Vec<RequestTransformConfig>,
+    /// Applied in order to successful JSON and SSE response bodies
+    #[serde(default)]
+    pub response: Vec<ResponseTransformConfig>,
+}
+
+/// Which requests a transform applies to; an empty list matches everything
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct TransformScopeConfig {
+    /// Request paths; an entry ending in `*` matches a prefix
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Resolved model names or patterns, as in a key's `models`
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct RequestTransformConfig {
+    #[serde(flatten)]
+    pub scope: TransformScopeConfig,
+    /// Top-level fields dropped from the body, e.g. `logit_bias`
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Top-level fields overwritten, e.g. `user`
+    #[serde(default)]
+    pub set: HashMap<String, serde_json::Value>,
+    /// Top-level fields added when the client left them out
+    #[serde(default)]
+    pub defaults: HashMap<String, serde_json::Value>,
+    /// Upper bound for `max_tokens` / `max_completion_tokens`, added to chat and completions
+    /// requests that send neither
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// System message prepended to `messages` that have no system or developer message
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct ResponseTransformConfig {
+    #[serde(flatten)]
+    pub scope: TransformScopeConfig,
+    /// Top-level fields dropped from the body or from each streamed chunk
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Top-level fields overwritten in the body or in each streamed chunk
+    #[serde(default)]
+    pub set: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct ModelsConfig {
+    /// Requested model name to the model name forwarded upstream
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Model to the models tried, in order, when it cannot be served
+    #[serde(default)]
+    pub fallbacks: HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -80,11 +417,114 @@ pub struct ApiKeyInfo {
     pub latency: Option<Duration>,
     #[serde(skip)]
     pub health_score: f64,
+    #[serde(skip)]
+    pub provider: Provider,
+    /// Priority tier for the `weighted` strategy; lower tiers are drained first
+    #[serde(default)]
+    pub tier: u32,
+    /// Share of traffic within its tier for the `weighted` strategy
+    #[serde(default = "default_key_weight")]
+    pub weight: u32,
+    /// Compiled `models`, built on first use; leave at its default when constructing keys
+    #[serde(skip)]
+    pub model_matcher: OnceCell<ModelMatcher>,
+    /// Static headers sent with every request for this key, e.g. `OpenAI-Organization`
+    #[serde(skip)]
+    pub extra_headers: reqwest::header::HeaderMap,
+    /// Model aliases for this key only, applied to the routed model before it is forwarded
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Position in the key pool, assigned by the pool; leave at its default when constructing keys
+    #[serde(skip)]
+    pub pool_index: usize,
+}
+
+/// Upstream API flavour a key talks to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Provider {
+    #[default]
+    OpenAI,
+    Azure(AzureSettings),
+    Gemini(GeminiSettings),
+}
+
+/// Azure OpenAI resource settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureSettings {
+    pub api_version: String,
+    /// Model name to deployment name; unmapped models are used as the deployment name
+    pub deployments: HashMap<String, String>,
+}
+
+/// Google Gemini (Generative Language API) settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeminiSettings {
+    pub auth: GeminiAuth,
+}
+
+/// How the Gemini API key is sent upstream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GeminiAuth {
+    /// `x-goog-api-key` request header
+    #[default]
+    Header,
+    /// `?key=` query parameter
+    Query,
+}
+
+impl AzureSettings {
+    pub fn deployment_for(&self, model: &str) -> String {
+        self.deployments
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+}
+
+impl Default for ApiKeyInfo {
+    fn default() -> Self {
+        Self {
+            key: SecretString::new(String::new()),
+            url: default_base_url(),
+            models: vec!["others".to_string()],
+            latency: None,
+            health_score: 1.0,
+            provider: Provider::default(),
+            tier: 0,
+            weight: default_key_weight(),
+            model_matcher: OnceCell::new(),
+            extra_headers: reqwest::header::HeaderMap::new(),
+            aliases: HashMap::new(),
+            pool_index: 0,
+        }
+    }
 }
 
 impl ApiKeyInfo {
+    #[allow(dead_code)]
     pub fn supports_model(&self, model: &str) -> bool {
-        self.models.iter().any(|m| m == model || m == "others")
+        self.model_match(model).is_some()
+    }
+
+    /// How specifically this key serves `model`, see [`ModelMatcher`]
+    pub fn model_match(&self, model: &str) -> Option<ModelMatch> {
+        self.matcher().matches(model)
+    }
+
+    /// Non-secret identifier for logs and headers: the last four characters of the key
+    pub fn key_id(&self) -> String {
+        let secret = self.key.expose_secret();
+        let tail: String = secret
+            .chars()
+            .skip(secret.chars().count().saturating_sub(4))
+            .collect();
+        format!("...{}", tail)
+    }
+
+    /// The compiled `models` list
+    pub fn matcher(&self) -> &ModelMatcher {
+        self.model_matcher
+            .get_or_init(|| ModelMatcher::parse_lossy(&self.models))
     }
 }
 
@@ -99,6 +539,86 @@ pub struct LegacyApiKeyInfo {
     pub key: String,
     pub url: String,
     pub models: Vec<String>,
+    /// `openai` (default), `azure` or `gemini`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Gemini key placement: `header` (default) or `query`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<String>,
+    #[serde(
+        default,
+        rename = "apiVersion",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub api_version: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub deployments: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// Extra headers sent upstream with this key, after client headers are filtered
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Routed model = model forwarded with this key, on top of `[models.aliases]`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+}
+
+impl TryFrom<LegacyApiKeyInfo> for ApiKeyInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(key_info: LegacyApiKeyInfo) -> Result<Self> {
+        let provider = match key_info.provider.as_deref() {
+            None | Some("openai") => Provider::OpenAI,
+            Some("azure") => Provider::Azure(AzureSettings {
+                api_version: key_info.api_version.with_context(|| {
+                    format!("Azure key for {} is missing apiVersion", key_info.url)
+                })?,
+                deployments: key_info.deployments,
+            }),
+            Some("gemini") => Provider::Gemini(GeminiSettings {
+                auth: match key_info.auth.as_deref() {
+                    None | Some("header") => GeminiAuth::Header,
+                    Some("query") => GeminiAuth::Query,
+                    Some(other) => {
+                        anyhow::bail!("Unknown Gemini auth '{}' for {}", other, key_info.url)
+                    }
+                },
+            }),
+            Some(other) => anyhow::bail!("Unknown provider '{}' for {}", other, key_info.url),
+        };
+        let model_matcher = ModelMatcher::parse(&key_info.models)
+            .with_context(|| format!("Invalid model pattern for {}", key_info.url))?;
+
+        let mut extra_headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &key_info.headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name '{}' for {}", name, key_info.url))?;
+            let value = reqwest::header::HeaderValue::from_str(value).with_context(|| {
+                format!("Invalid value for header '{}' for {}", name, key_info.url)
+            })?;
+            extra_headers.insert(name, value);
+        }
+
+        let weight = key_info.weight.unwrap_or_else(default_key_weight);
+        if weight == 0 {
+            anyhow::bail!("Key for {} must have a weight of at least 1", key_info.url);
+        }
+
+        Ok(ApiKeyInfo {
+            key: SecretString::new(key_info.key),
+            url: key_info.url,
+            models: key_info.models,
+            provider,
+            tier: key_info.tier.unwrap_or_default(),
+            weight,
+            model_matcher: OnceCell::with_value(model_matcher),
+            extra_headers,
+            aliases: key_info.aliases,
+            ..Default::default()
+        })
+    }
 }
 
 impl Default for ServerConfig {
@@ -129,6 +649,8 @@ impl Default for KeysConfig {
         Self {
             rotation_strategy: default_rotation_strategy(),
             unhealthy_penalty: default_unhealthy_penalty(),
+            unhealthy_cooldown_ms: default_unhealthy_cooldown(),
+            rate_limit_cooldown_ms: default_rate_limit_cooldown(),
         }
     }
 }
@@ -143,11 +665,118 @@ impl Default for RateLimitConfig {
     }
 }
 
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: default_cache_backend(),
+            ttl_seconds: default_cache_ttl(),
+            max_entries: default_cache_max_entries(),
+            max_entry_bytes: default_cache_max_entry_bytes(),
+            max_total_bytes: default_cache_max_total_bytes(),
+            disk_path: default_cache_disk_path(),
+            deterministic_only: default_true(),
+        }
+    }
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_ms: default_batch_window(),
+            max_inputs: default_batch_max_inputs(),
+        }
+    }
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_concurrent: default_admission_max_concurrent(),
+            max_wait_ms: default_admission_max_wait(),
+            priority_header: default_priority_header(),
+            client_header: default_client_header(),
+            default_class: default_admission_class(),
+            classes: default_admission_classes(),
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            sticky: false,
+            sticky_header: default_sticky_header(),
+            sticky_target: StickyTarget::default(),
+        }
+    }
+}
+
+impl Default for ProbesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: default_probe_interval(),
+            jitter_ms: default_probe_jitter(),
+            timeout_ms: default_probe_timeout(),
+            method: default_probe_method(),
+            path: default_probe_path(),
+            body: None,
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: default_audit_dir(),
+            max_file_bytes: default_audit_max_file_bytes(),
+            max_files: default_audit_max_files(),
+            max_body_bytes: default_audit_max_body_bytes(),
+            queue_size: default_audit_queue_size(),
+            sample_rate: default_audit_sample_rate(),
+            paths: HashMap::new(),
+            clients: HashMap::new(),
+            client_header: default_client_header(),
+            redact_headers: Vec::new(),
+            pii_patterns: Vec::new(),
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+        }
+    }
+}
+
+impl Default for TransformsConfig {
+    fn default() -> Self {
+        Self {
+            client_header: default_client_header(),
+            request: Vec::new(),
+            response: Vec::new(),
+        }
+    }
+}
+
 impl Default for ObservabilityConfig {
     fn default() -> Self {
         Self {
             metrics_bind: default_metrics_bind(),
             tracing_level: default_tracing_level(),
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+            trace_sample_ratio: default_trace_sample_ratio(),
+            log_format: LogFormat::default(),
+            access_log: default_true(),
+            client_header: default_client_header(),
         }
     }
 }
@@ -186,6 +815,12 @@ fn default_rotation_strategy() -> String {
 fn default_unhealthy_penalty() -> u32 {
     5
 }
+fn default_unhealthy_cooldown() -> u64 {
+    30_000
+}
+fn default_rate_limit_cooldown() -> u64 {
+    5_000
+}
 fn default_per_key_rps() -> u32 {
     3
 }
@@ -201,15 +836,126 @@ fn default_metrics_bind() -> String {
 fn default_tracing_level() -> String {
     "info".to_string()
 }
+fn default_service_name() -> String {
+    "key-cycle-proxy".to_string()
+}
+fn default_trace_sample_ratio() -> f64 {
+    1.0
+}
+fn default_cache_backend() -> String {
+    "memory".to_string()
+}
+fn default_cache_ttl() -> u64 {
+    3600
+}
+fn default_cache_max_entries() -> usize {
+    10_000
+}
+fn default_cache_max_entry_bytes() -> usize {
+    1_048_576
+}
+fn default_cache_max_total_bytes() -> usize {
+    268_435_456
+}
+fn default_cache_disk_path() -> String {
+    "cache".to_string()
+}
+fn default_batch_window() -> u64 {
+    10
+}
+fn default_batch_max_inputs() -> usize {
+    2048
+}
+fn default_admission_max_concurrent() -> usize {
+    64
+}
+fn default_admission_max_wait() -> u64 {
+    30_000
+}
+fn default_priority_header() -> String {
+    "x-kcp-priority".to_string()
+}
+fn default_client_header() -> String {
+    "x-kcp-client".to_string()
+}
+fn default_audit_dir() -> String {
+    "audit".to_string()
+}
+fn default_audit_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+fn default_audit_max_files() -> usize {
+    10
+}
+fn default_audit_max_body_bytes() -> usize {
+    1024 * 1024
+}
+fn default_audit_queue_size() -> usize {
+    1024
+}
+fn default_audit_sample_rate() -> f64 {
+    1.0
+}
+fn default_pii_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+fn default_admission_class() -> String {
+    "interactive".to_string()
+}
+fn default_admission_classes() -> HashMap<String, PriorityClassConfig> {
+    HashMap::from([
+        (
+            "interactive".to_string(),
+            PriorityClassConfig {
+                rank: 0,
+                max_queued: default_class_max_queued(),
+                max_in_flight: None,
+            },
+        ),
+        (
+            "batch".to_string(),
+            PriorityClassConfig {
+                rank: 1,
+                max_queued: default_class_max_queued(),
+                max_in_flight: None,
+            },
+        ),
+    ])
+}
+fn default_class_max_queued() -> usize {
+    1000
+}
+fn default_client_weight() -> u32 {
+    1
+}
+fn default_sticky_header() -> String {
+    "x-kcp-session".to_string()
+}
+fn default_key_weight() -> u32 {
+    1
+}
+fn default_probe_interval() -> u64 {
+    60_000
+}
+fn default_probe_jitter() -> u64 {
+    10_000
+}
+fn default_probe_timeout() -> u64 {
+    5_000
+}
+fn default_probe_method() -> String {
+    "GET".to_string()
+}
+fn default_probe_path() -> String {
+    "/v1/models".to_string()
+}
+fn default_true() -> bool {
+    true
+}
 
 pub fn load_config() -> Result<(Config, Vec<ApiKeyInfo>)> {
     // Load main config
-    let mut config = Config::default();
-
-    // Try to load from config file if it exists
-    if let Ok(config_str) = std::fs::read_to_string("config.toml") {
-        config = toml::from_str(&config_str).context("Failed to parse config.toml")?;
-    }
+    let config = load_server_config()?;
 
     // Load API keys from environment or legacy config.json
     let api_keys = load_api_keys()?;
@@ -217,6 +963,14 @@ pub fn load_config() -> Result<(Config, Vec<ApiKeyInfo>)> {
     Ok((config, api_keys))
 }
 
+/// Load config.toml only, falling back to defaults when it does not exist
+pub fn load_server_config() -> Result<Config> {
+    match std::fs::read_to_string("config.toml") {
+        Ok(config_str) => toml::from_str(&config_str).context("Failed to parse config.toml"),
+        Err(_) => Ok(Config::default()),
+    }
+}
+
 fn load_api_keys() -> Result<Vec<ApiKeyInfo>> {
     // First try environment variable
     if let Ok(keys_env) = std::env::var("OPENAI_KEYS") {
@@ -225,10 +979,7 @@ fn load_api_keys() -> Result<Vec<ApiKeyInfo>> {
             .into_iter()
             .map(|key| ApiKeyInfo {
                 key: SecretString::new(key.trim().to_string()),
-                url: default_base_url(),
-                models: vec!["others".to_string()],
-                latency: None,
-                health_score: 1.0,
+                ..Default::default()
             })
             .collect());
     }
@@ -238,17 +989,11 @@ fn load_api_keys() -> Result<Vec<ApiKeyInfo>> {
         let legacy_config: LegacyConfig =
             serde_json::from_str(&config_content).context("Failed to parse config.json")?;
 
-        return Ok(legacy_config
+        return legacy_config
             .api_keys
             .into_iter()
-            .map(|key_info| ApiKeyInfo {
-                key: SecretString::new(key_info.key),
-                url: key_info.url,
-                models: key_info.models,
-                latency: None,
-                health_score: 1.0,
-            })
-            .collect());
+            .map(ApiKeyInfo::try_from)
+            .collect();
     }
 
     anyhow::bail!("No API keys found. Set OPENAI_KEYS environment variable or create config.json");
@@ -273,6 +1018,38 @@ impl UpstreamConfig {
     }
 }
 
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_seconds)
+    }
+}
+
+impl BatchingConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+impl AdmissionConfig {
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_millis(self.max_wait_ms)
+    }
+}
+
+impl ProbesConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn jitter(&self) -> Duration {
+        Duration::from_millis(self.jitter_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
 impl ServerConfig {
     pub fn graceful_shutdown_duration(&self) -> Duration {
         Duration::from_secs(self.graceful_shutdown_seconds)
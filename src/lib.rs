@@ -1,5 +1,6 @@
 pub mod config;
 pub mod proxy;
 pub mod routes;
+pub mod telemetry;
 pub mod types;
 pub mod util;
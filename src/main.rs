@@ -1,19 +1,33 @@
 mod config;
 mod proxy;
 mod routes;
+mod telemetry;
 mod types;
 mod util;
 
-use crate::config::load_config;
+use crate::config::{load_config, load_server_config, ObservabilityConfig};
+use crate::proxy::access_log::AccessLog;
+use crate::proxy::admission::AdmissionQueue;
+use crate::proxy::audit::AuditLog;
+use crate::proxy::batch::EmbeddingBatcher;
+use crate::proxy::cache::ResponseCache;
+use crate::proxy::coalesce::Coalescer;
+use crate::proxy::headers::HeaderPolicy;
+use crate::proxy::health::HealthPolicy;
+use crate::proxy::models::{ModelAliases, ModelFallbacks};
+use crate::proxy::probe::HealthProber;
+use crate::proxy::transform::Transforms;
 use crate::proxy::{KeyPool, ProxyEngine, ProxyHandler, UpstreamClient};
 use crate::routes::create_router;
 use anyhow::{Context, Result};
 use clap::Parser;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use secrecy::ExposeSecret;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::signal;
-use tracing::info;
+use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser, Debug)]
@@ -30,17 +44,20 @@ struct Args {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    init_tracing()?;
-
-    info!("Starting KeyCycleProxy Rust server...");
-
     // Parse command line arguments
     let args = Args::parse();
 
     // Load configuration
     let (config, api_keys) = load_config().context("Failed to load configuration")?;
 
+    // Initialize tracing
+    let tracer_provider = init_tracing(&config.observability)?;
+
+    info!("Starting KeyCycleProxy Rust server...");
+    if let Some(endpoint) = &config.observability.otlp_endpoint {
+        info!("Exporting traces to {}", endpoint);
+    }
+
     if api_keys.is_empty() {
         anyhow::bail!("No API keys configured. Please set OPENAI_KEYS environment variable or create config.json");
     }
@@ -51,15 +68,127 @@ async fn main() -> Result<()> {
     let bind_addr = args.bind.unwrap_or(config.server.bind_addr.clone());
 
     // Initialize components
-    let key_pool = Arc::new(KeyPool::new(api_keys, &config.keys.rotation_strategy));
+    let key_pool = Arc::new(
+        KeyPool::new(api_keys, &config.keys.rotation_strategy)
+            .with_health_policy(HealthPolicy::from(&config.keys)),
+    );
     let upstream_client =
         UpstreamClient::new(config.upstream.clone()).context("Failed to create upstream client")?;
-    let engine = Arc::new(ProxyEngine::new(
+    if config.probes.enabled || key_pool.uses_latency() {
+        let prober = HealthProber::new(key_pool.clone(), upstream_client.clone(), &config.probes)
+            .context("Invalid [probes] config")?;
+        if config.probes.enabled {
+            info!(
+                "Probing every key with {} {} every {}ms",
+                config.probes.method, config.probes.path, config.probes.interval_ms
+            );
+            prober.spawn();
+        } else {
+            // Latency comes from real traffic; keys idle for a full interval get probed instead
+            info!(
+                "Probing idle keys with {} {} every {}ms",
+                config.probes.method, config.probes.path, config.probes.interval_ms
+            );
+            prober.spawn_idle();
+        }
+    }
+    let model_aliases = ModelAliases::new(config.models.aliases.clone());
+    if !model_aliases.is_empty() {
+        info!("Loaded {} model aliases", model_aliases.len());
+    }
+    let model_fallbacks = ModelFallbacks::new(config.models.fallbacks.clone());
+    if !model_fallbacks.is_empty() {
+        info!("Loaded {} model fallback chains", model_fallbacks.len());
+    }
+    let mut engine = ProxyEngine::new(
         key_pool.clone(),
         upstream_client,
         config.upstream.max_retries,
-    ));
-    let handler = Arc::new(ProxyHandler::new(engine));
+    )
+    .with_model_aliases(model_aliases)
+    .with_model_fallbacks(model_fallbacks);
+    if config.cache.enabled {
+        let cache =
+            ResponseCache::new(&config.cache).context("Failed to initialize response cache")?;
+        info!("Response cache enabled ({} backend)", config.cache.backend);
+        engine = engine.with_cache(cache);
+    }
+    if !config.coalesce.paths.is_empty() {
+        info!(
+            "Coalescing identical requests on {:?}",
+            config.coalesce.paths
+        );
+        engine = engine.with_coalescer(Coalescer::new(config.coalesce.paths.clone()));
+    }
+    if config.batching.enabled {
+        info!(
+            "Batching embeddings requests within {}ms windows",
+            config.batching.window_ms
+        );
+        engine = engine.with_batcher(EmbeddingBatcher::new(&config.batching));
+    }
+    if config.routing.sticky {
+        info!(
+            "Sticky routing by '{}' header or body user",
+            config.routing.sticky_header
+        );
+        engine = engine.with_sticky_routing(
+            config.routing.sticky_header.clone(),
+            config.routing.sticky_target,
+        );
+    }
+    engine = engine.with_header_policy(HeaderPolicy::new(&config.headers).with_control_headers([
+        config.observability.client_header.as_str(),
+        config.admission.client_header.as_str(),
+        config.admission.priority_header.as_str(),
+        config.routing.sticky_header.as_str(),
+        config.transforms.client_header.as_str(),
+        config.audit.client_header.as_str(),
+    ]));
+    if config.observability.access_log {
+        engine = engine.with_access_log(AccessLog::new(&config.observability));
+    }
+    if config.audit.enabled {
+        let secrets = key_pool
+            .get_all_keys()
+            .iter()
+            .map(|key| key.key.expose_secret().to_string())
+            .collect();
+        let audit = AuditLog::new(&config.audit, secrets).context("Invalid [audit] config")?;
+        info!(
+            "Audit logging {:.0}% of requests to {}",
+            config.audit.sample_rate * 100.0,
+            config.audit.dir
+        );
+        engine = engine.with_audit(audit);
+    }
+    if config.headers.attempts_header {
+        engine = engine.with_attempts_header();
+    }
+    if config.validation.enabled {
+        engine = engine.with_validation();
+    }
+    let transforms = Transforms::new(&config.transforms).context("Invalid [transforms] config")?;
+    if !transforms.is_empty() {
+        info!(
+            "Loaded {} request and {} response transforms",
+            config.transforms.request.len(),
+            config.transforms.response.len()
+        );
+        engine = engine.with_transforms(transforms);
+    }
+    let engine = Arc::new(engine);
+    let mut handler = ProxyHandler::new(engine.clone());
+    if config.admission.enabled {
+        let admission = AdmissionQueue::new(&config.admission)
+            .context("Failed to configure admission queue")?;
+        info!(
+            "Admission queue enabled ({} concurrent requests)",
+            config.admission.max_concurrent
+        );
+        handler = handler.with_admission(admission);
+    }
+    let handler = Arc::new(handler);
 
     // Create router with middleware
     let app = create_router(
@@ -68,8 +197,8 @@ async fn main() -> Result<()> {
         Duration::from_millis(config.upstream.request_timeout_ms),
     );
 
-    // Start latency measurement task
-    start_latency_updater(key_pool.clone());
+    // Reload model aliases and fallbacks from config.toml on SIGHUP
+    start_config_reloader(engine.clone());
 
     // Start server
     info!("Server starting on {}", bind_addr);
@@ -86,36 +215,53 @@ async fn main() -> Result<()> {
         .context("Server error")?;
 
     info!("Server shutdown complete");
+    if let Some(provider) = tracer_provider {
+        // Flush the spans still queued for export
+        if let Err(e) = provider.shutdown() {
+            error!("Failed to flush traces: {}", e);
+        }
+    }
     Ok(())
 }
 
-fn init_tracing() -> Result<()> {
+fn init_tracing(config: &ObservabilityConfig) -> Result<Option<SdkTracerProvider>> {
+    let tracer_provider = telemetry::tracer_provider(config)?;
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "key_cycle_proxy=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(telemetry::env_filter(config))
+        .with(telemetry::fmt_layer(config.log_format))
+        .with(tracer_provider.as_ref().map(telemetry::layer))
         .init();
 
-    Ok(())
+    Ok(tracer_provider)
 }
 
-fn start_latency_updater(key_pool: Arc<KeyPool>) {
+#[cfg(unix)]
+fn start_config_reloader(engine: Arc<ProxyEngine>) {
     tokio::spawn(async move {
-        // Initial latency measurement
-        key_pool.update_all_latencies().await;
-        info!("Initial latency measurements complete");
-
-        // Update latencies every 60 seconds
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
-        loop {
-            interval.tick().await;
-            key_pool.update_all_latencies().await;
+        let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading config.toml...");
+            match load_server_config() {
+                Ok(config) => {
+                    engine.reload_model_aliases(ModelAliases::new(config.models.aliases));
+                    engine.reload_model_fallbacks(ModelFallbacks::new(config.models.fallbacks));
+                }
+                Err(e) => error!("Failed to reload configuration: {:#}", e),
+            }
         }
     });
 }
 
+#[cfg(not(unix))]
+fn start_config_reloader(_engine: Arc<ProxyEngine>) {}
+
 async fn shutdown_signal(grace_period: Duration) {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -0,0 +1,370 @@
+use crate::config::ObservabilityConfig;
+use crate::proxy::engine::{Attempt, REQUEST_ID_HEADER, SERVED_MODEL_HEADER};
+use crate::proxy::error::ProxyError;
+use crate::proxy::stream::{next_event, ProxiedResponse, StreamObserver, StreamOutcome};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method};
+use bytes::{Bytes, BytesMut};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Largest non-streaming body buffered to read its `usage`
+const MAX_USAGE_BODY_BYTES: usize = 1024 * 1024;
+
+/// One structured line per finished request, logged under the `access_log` target
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    client_header: String,
+}
+
+impl AccessLog {
+    pub fn new(config: &ObservabilityConfig) -> Self {
+        Self {
+            client_header: config.client_header.to_ascii_lowercase(),
+        }
+    }
+
+    /// Start the entry for a request as it arrives
+    pub fn begin(&self, method: &Method, path: &str, headers: &HeaderMap) -> AccessEntry {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        AccessEntry {
+            started: Instant::now(),
+            request_id: header(REQUEST_ID_HEADER),
+            method: method.to_string(),
+            path: path.to_string(),
+            client: header(&self.client_header),
+            model: None,
+            served_model: None,
+            key_id: None,
+            attempts: 0,
+            status: 0,
+            first_byte: None,
+            usage: UsageScanner::Off,
+        }
+    }
+}
+
+/// What is known about one request; logged when its response body ends
+#[derive(Debug)]
+pub struct AccessEntry {
+    started: Instant,
+    request_id: Option<String>,
+    method: String,
+    path: String,
+    client: Option<String>,
+    model: Option<String>,
+    served_model: Option<String>,
+    key_id: Option<String>,
+    attempts: usize,
+    status: u16,
+    first_byte: Option<Duration>,
+    usage: UsageScanner,
+}
+
+impl AccessEntry {
+    pub fn set_model(&mut self, model: &str) {
+        self.model = Some(model.to_string());
+    }
+
+    /// Follow the response to the client; the line is logged once its body ends
+    pub fn observe(mut self, response: ProxiedResponse) -> ProxiedResponse {
+        self.status = response.status.as_u16();
+        self.served_model = response
+            .headers
+            .get(SERVED_MODEL_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        self.usage = if content_type.starts_with("text/event-stream") {
+            UsageScanner::sse()
+        } else if content_type.starts_with("application/json") {
+            UsageScanner::Json(BytesMut::new())
+        } else {
+            UsageScanner::Off
+        };
+        response.tap(self)
+    }
+
+    /// Log a request that ended in an error instead of a response body
+    pub fn log_error(mut self, error: &ProxyError) {
+        self.status = error.status_code().as_u16();
+        let outcome = match error.upstream_response() {
+            Some(_) => "upstream_error",
+            None => "error",
+        };
+        self.log(outcome, None, Some(error.code()));
+    }
+
+    /// The upstream calls made for this request; the last one names the key that answered
+    pub fn set_attempts(&mut self, attempts: &[Attempt]) {
+        self.attempts = attempts.len();
+        self.key_id = attempts.last().map(|attempt| attempt.key_id.clone());
+    }
+
+    fn log(&self, outcome: &str, usage: Option<Usage>, error_code: Option<&str>) {
+        let usage = usage.unwrap_or_default();
+        info!(
+            target: "access_log",
+            request_id = self.request_id.as_deref(),
+            method = %self.method,
+            path = %self.path,
+            model = self.model.as_deref(),
+            served_model = self.served_model.as_deref(),
+            client = self.client.as_deref(),
+            key_id = self.key_id.as_deref(),
+            attempts = self.attempts,
+            status = self.status,
+            ttfb_ms = self.first_byte.map(|d| d.as_millis() as u64),
+            total_ms = self.started.elapsed().as_millis() as u64,
+            prompt_tokens = usage.prompt_tokens,
+            completion_tokens = usage.completion_tokens,
+            total_tokens = usage.total_tokens,
+            error_code,
+            outcome,
+            "request completed"
+        );
+    }
+}
+
+impl StreamObserver for AccessEntry {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        if self.first_byte.is_none() {
+            self.first_byte = Some(self.started.elapsed());
+        }
+        self.usage.feed(chunk);
+    }
+
+    fn on_end(&mut self, outcome: StreamOutcome) {
+        let usage = self.usage.finish();
+        let outcome = match outcome {
+            StreamOutcome::Completed => "completed",
+            StreamOutcome::Failed => "failed",
+            StreamOutcome::Aborted => "aborted",
+        };
+        self.log(outcome, usage, None);
+    }
+}
+
+/// Token counts of a response's `usage` object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Usage {
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+    total_tokens: Option<u64>,
+}
+
+impl Usage {
+    fn from_value(value: &Value) -> Option<Self> {
+        let usage = value.get("usage")?.as_object()?;
+        let count = |field: &str| usage.get(field).and_then(Value::as_u64);
+        Some(Self {
+            prompt_tokens: count("prompt_tokens"),
+            completion_tokens: count("completion_tokens"),
+            total_tokens: count("total_tokens"),
+        })
+    }
+}
+
+/// Finds `usage` in a body as it streams past, without holding on to SSE events
+#[derive(Debug)]
+enum UsageScanner {
+    /// JSON body, buffered up to `MAX_USAGE_BODY_BYTES`
+    Json(BytesMut),
+    /// The incomplete trailing SSE event, and the last usage seen
+    Sse {
+        buffer: BytesMut,
+        usage: Option<Usage>,
+    },
+    Off,
+}
+
+impl UsageScanner {
+    fn sse() -> Self {
+        UsageScanner::Sse {
+            buffer: BytesMut::new(),
+            usage: None,
+        }
+    }
+
+    fn feed(&mut self, chunk: &Bytes) {
+        match self {
+            UsageScanner::Json(buffer) => {
+                if buffer.len() + chunk.len() > MAX_USAGE_BODY_BYTES {
+                    *self = UsageScanner::Off;
+                } else {
+                    buffer.extend_from_slice(chunk);
+                }
+            }
+            UsageScanner::Sse { buffer, usage } => {
+                buffer.extend_from_slice(chunk);
+                scan_events(buffer, usage, false);
+            }
+            UsageScanner::Off => {}
+        }
+    }
+
+    fn finish(&mut self) -> Option<Usage> {
+        match self {
+            UsageScanner::Json(buffer) => serde_json::from_slice::<Value>(buffer)
+                .ok()
+                .and_then(|body| Usage::from_value(&body)),
+            UsageScanner::Sse { buffer, usage } => {
+                scan_events(buffer, usage, true);
+                *usage
+            }
+            UsageScanner::Off => None,
+        }
+    }
+}
+
+fn scan_events(buffer: &mut BytesMut, usage: &mut Option<Usage>, flush: bool) {
+    while let Some(event) = next_event(buffer, flush) {
+        if let Some(found) = sse_usage(&event) {
+            *usage = Some(found);
+        }
+    }
+}
+
+fn sse_usage(event: &[u8]) -> Option<Usage> {
+    let event = std::str::from_utf8(event).ok()?;
+    event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+        .find_map(|data| Usage::from_value(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::stream::collect;
+    use axum::http::{HeaderValue, StatusCode};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Log output captured by a test subscriber
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_line_after_body() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_writer(move || writer.clone()),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let log = AccessLog::new(&ObservabilityConfig::default());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-kcp-client", HeaderValue::from_static("chat-ui"));
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("req-1"));
+        let mut entry = log.begin(&Method::POST, "/v1/chat/completions", &headers);
+        entry.set_model("gpt-4o");
+        entry.set_attempts(&[
+            Attempt {
+                key_id: "...ey-1".to_string(),
+                status: Some(429),
+            },
+            Attempt {
+                key_id: "...ey-2".to_string(),
+                status: Some(200),
+            },
+        ]);
+
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let body = r#"{"usage": {"prompt_tokens": 4, "completion_tokens": 2, "total_tokens": 6}}"#;
+        let response = entry.observe(ProxiedResponse {
+            status: StatusCode::OK,
+            headers: response_headers,
+            body: Box::pin(futures::stream::once(async move {
+                Ok(Bytes::from_static(body.as_bytes()))
+            })),
+        });
+        assert!(captured.0.lock().unwrap().is_empty());
+        collect(response.body).await.unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+        assert_eq!(line["target"], "access_log");
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["client"], "chat-ui");
+        assert_eq!(line["model"], "gpt-4o");
+        assert_eq!(line["key_id"], "...ey-2");
+        assert_eq!(line["attempts"], 2);
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["total_tokens"], 6);
+        assert_eq!(line["outcome"], "completed");
+        assert!(line["ttfb_ms"].is_u64());
+    }
+
+    fn scan(mut scanner: UsageScanner, chunks: &[&'static str]) -> Option<Usage> {
+        for chunk in chunks {
+            scanner.feed(&Bytes::from_static(chunk.as_bytes()));
+        }
+        scanner.finish()
+    }
+
+    #[test]
+    fn test_usage_from_json_body() {
+        let usage = scan(
+            UsageScanner::Json(BytesMut::new()),
+            &[
+                r#"{"id": "chatcmpl-1", "usage": {"prompt_tokens": 9, "#,
+                r#""completion_tokens": 3, "total_tokens": 12}}"#,
+            ],
+        );
+        assert_eq!(
+            usage,
+            Some(Usage {
+                prompt_tokens: Some(9),
+                completion_tokens: Some(3),
+                total_tokens: Some(12),
+            })
+        );
+    }
+
+    #[test]
+    fn test_usage_from_final_sse_event() {
+        let usage = scan(
+            UsageScanner::sse(),
+            &[
+                "data: {\"choices\": [{\"delta\": {\"content\": \"Hi\"}}], \"usage\": null}\n\n",
+                "data: {\"choices\": [], \"usage\": {\"prompt_tokens\": 5, ",
+                "\"completion_tokens\": 1, \"total_tokens\": 6}}\n\ndata: [DONE]\n\n",
+            ],
+        );
+        assert_eq!(usage.and_then(|u| u.total_tokens), Some(6));
+
+        let usage = scan(
+            UsageScanner::sse(),
+            &["data: {\"choices\": []}\n\ndata: [DONE]\n\n"],
+        );
+        assert_eq!(usage, None);
+    }
+}
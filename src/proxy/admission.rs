@@ -0,0 +1,473 @@
+use crate::config::AdmissionConfig;
+use crate::proxy::error::{ProxyError, ProxyResult};
+use crate::proxy::stream::{tap, BodyStream, StreamObserver, StreamOutcome};
+use anyhow::{bail, Result};
+use axum::body::Body;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+/// Stride numerator; a client's pass advances by `STRIDE / weight` per admission
+const STRIDE: u64 = 1 << 20;
+
+/// Client id used when the request carries no client header
+const ANONYMOUS_CLIENT: &str = "anonymous";
+
+/// Admission control in front of the engine: strict priority between classes,
+/// weighted fair sharing between clients within a class
+#[derive(Debug)]
+pub struct AdmissionQueue {
+    max_concurrent: usize,
+    max_wait: Duration,
+    priority_header: String,
+    client_header: String,
+    default_class: usize,
+    /// Class indices by name; `state.classes` is ordered by rank
+    class_names: HashMap<String, usize>,
+    clients: HashMap<String, (Option<usize>, u64)>,
+    state: Mutex<QueueState>,
+}
+
+#[derive(Debug)]
+struct QueueState {
+    in_flight: usize,
+    next_id: u64,
+    classes: Vec<ClassState>,
+}
+
+#[derive(Debug)]
+struct ClassState {
+    name: String,
+    max_queued: usize,
+    max_in_flight: usize,
+    in_flight: usize,
+    queued: usize,
+    /// Pass of the last admitted client; newly active clients start here
+    virtual_time: u64,
+    clients: HashMap<String, ClientQueue>,
+}
+
+#[derive(Debug)]
+struct ClientQueue {
+    pass: u64,
+    stride: u64,
+    waiters: VecDeque<Waiter>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    admit: oneshot::Sender<Permit>,
+}
+
+/// A slot of `max_concurrent`, released when dropped
+#[derive(Debug)]
+pub struct Permit {
+    queue: Arc<AdmissionQueue>,
+    class: usize,
+    armed: bool,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.armed {
+            self.queue.release(self.class);
+        }
+    }
+}
+
+impl Permit {
+    /// Keep the slot until the response body has been fully sent
+    pub fn hold_until_sent(self, response: Response<Body>) -> Response<Body> {
+        response.map(|body| {
+            let body: BodyStream = Box::pin(
+                body.into_data_stream()
+                    .map(|chunk| chunk.map_err(std::io::Error::other)),
+            );
+            Body::from_stream(tap(body, self))
+        })
+    }
+}
+
+impl StreamObserver for Permit {
+    fn on_end(&mut self, _outcome: StreamOutcome) {
+        if std::mem::take(&mut self.armed) {
+            self.queue.release(self.class);
+        }
+    }
+}
+
+impl AdmissionQueue {
+    pub fn new(config: &AdmissionConfig) -> Result<Self> {
+        let mut classes: Vec<_> = config.classes.iter().collect();
+        classes.sort_by_key(|(name, class)| (class.rank, name.as_str()));
+        let class_names: HashMap<String, usize> = classes
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| (name.to_string(), index))
+            .collect();
+
+        let Some(&default_class) = class_names.get(&config.default_class) else {
+            bail!("Unknown default priority class '{}'", config.default_class);
+        };
+        let mut clients = HashMap::new();
+        for (id, client) in &config.clients {
+            let class = match &client.class {
+                Some(name) => match class_names.get(name) {
+                    Some(&index) => Some(index),
+                    None => bail!("Client '{}' uses unknown priority class '{}'", id, name),
+                },
+                None => None,
+            };
+            clients.insert(
+                id.clone(),
+                (class, STRIDE / u64::from(client.weight.max(1))),
+            );
+        }
+
+        let classes = classes
+            .into_iter()
+            .map(|(name, class)| ClassState {
+                name: name.clone(),
+                max_queued: class.max_queued,
+                max_in_flight: class.max_in_flight.unwrap_or(usize::MAX),
+                in_flight: 0,
+                queued: 0,
+                virtual_time: 0,
+                clients: HashMap::new(),
+            })
+            .collect();
+
+        Ok(Self {
+            max_concurrent: config.max_concurrent.max(1),
+            max_wait: config.max_wait(),
+            priority_header: config.priority_header.to_ascii_lowercase(),
+            client_header: config.client_header.to_ascii_lowercase(),
+            default_class,
+            class_names,
+            clients,
+            state: Mutex::new(QueueState {
+                in_flight: 0,
+                next_id: 0,
+                classes,
+            }),
+        })
+    }
+
+    /// Wait for a slot, or fail with 503 when the class queue is full or the wait too long
+    pub async fn admit(self: &Arc<Self>, headers: &HeaderMap) -> ProxyResult<Permit> {
+        let (class, client, stride) = self.classify(headers);
+
+        let (id, admitted) = {
+            let mut state = self.state.lock().unwrap();
+            let has_slot = state.in_flight < self.max_concurrent;
+            let queue = &mut state.classes[class];
+            if has_slot && queue.in_flight < queue.max_in_flight && queue.queued == 0 {
+                queue.in_flight += 1;
+                state.in_flight += 1;
+                return Ok(self.permit(class));
+            }
+            if queue.queued >= queue.max_queued {
+                warn!("Shedding request: '{}' queue is full", queue.name);
+                return Err(ProxyError::Overloaded {
+                    reason: format!("the '{}' queue is full", queue.name),
+                });
+            }
+
+            let (admit, admitted) = oneshot::channel();
+            let id = state.next_id;
+            state.next_id += 1;
+            let queue = &mut state.classes[class];
+            let virtual_time = queue.virtual_time;
+            queue
+                .clients
+                .entry(client.clone())
+                .or_insert_with(|| ClientQueue {
+                    pass: virtual_time,
+                    stride,
+                    waiters: VecDeque::new(),
+                })
+                .waiters
+                .push_back(Waiter { id, admit });
+            queue.queued += 1;
+            debug!("Queued request from '{}' in '{}'", client, queue.name);
+            (id, admitted)
+        };
+
+        // Leaves the queue on timeout or when the client goes away
+        let _waiting = Waiting {
+            queue: self,
+            class,
+            client,
+            id,
+        };
+        match tokio::time::timeout(self.max_wait, admitted).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(ProxyError::Overloaded {
+                reason: "timed out waiting for capacity".to_string(),
+            }),
+        }
+    }
+
+    /// Requests currently holding a slot
+    #[allow(dead_code)]
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+
+    fn classify(&self, headers: &HeaderMap) -> (usize, String, u64) {
+        let client = headers
+            .get(&self.client_header)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or(ANONYMOUS_CLIENT)
+            .to_string();
+        let (client_class, stride) = self.clients.get(&client).copied().unwrap_or((None, STRIDE));
+        let requested = headers
+            .get(&self.priority_header)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|name| self.class_names.get(name.trim()).copied());
+        // Classes are ordered by rank, so the header can lower a client's class but never raise it
+        let ceiling = client_class.unwrap_or(self.default_class);
+        let class = requested.map_or(ceiling, |requested| requested.max(ceiling));
+        (class, client, stride)
+    }
+
+    fn permit(self: &Arc<Self>, class: usize) -> Permit {
+        Permit {
+            queue: self.clone(),
+            class,
+            armed: true,
+        }
+    }
+
+    fn release(self: &Arc<Self>, class: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        state.classes[class].in_flight -= 1;
+        self.dispatch(&mut state);
+    }
+
+    /// Hand free slots to waiters: highest class first, lowest pass within a class
+    fn dispatch(self: &Arc<Self>, state: &mut QueueState) {
+        while state.in_flight < self.max_concurrent {
+            let Some(class) = state
+                .classes
+                .iter()
+                .position(|c| c.queued > 0 && c.in_flight < c.max_in_flight)
+            else {
+                return;
+            };
+
+            let queue = &mut state.classes[class];
+            let Some((client, waiter)) = next_waiter(queue) else {
+                return;
+            };
+            queue.queued -= 1;
+            queue.in_flight += 1;
+            state.in_flight += 1;
+
+            if let Err(mut permit) = waiter.admit.send(self.permit(class)) {
+                // The waiter gave up in the meantime; reclaim the slot here
+                permit.armed = false;
+                state.in_flight -= 1;
+                state.classes[class].in_flight -= 1;
+            } else {
+                debug!("Admitted queued request from '{}'", client);
+            }
+        }
+    }
+}
+
+fn next_waiter(queue: &mut ClassState) -> Option<(String, Waiter)> {
+    let client = queue
+        .clients
+        .iter()
+        .filter(|(_, c)| !c.waiters.is_empty())
+        .min_by_key(|(id, c)| (c.pass, id.as_str()))
+        .map(|(id, _)| id.clone())?;
+
+    let entry = queue.clients.get_mut(&client)?;
+    let waiter = entry.waiters.pop_front()?;
+    queue.virtual_time = entry.pass;
+    entry.pass += entry.stride;
+    if entry.waiters.is_empty() {
+        queue.clients.remove(&client);
+    }
+    Some((client, waiter))
+}
+
+struct Waiting<'a> {
+    queue: &'a Arc<AdmissionQueue>,
+    class: usize,
+    client: String,
+    id: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        let queue = &mut state.classes[self.class];
+        let Some(entry) = queue.clients.get_mut(&self.client) else {
+            return;
+        };
+        if let Some(position) = entry.waiters.iter().position(|w| w.id == self.id) {
+            entry.waiters.remove(position);
+            queue.queued -= 1;
+            if entry.waiters.is_empty() {
+                queue.clients.remove(&self.client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AdmissionClientConfig, PriorityClassConfig};
+    use axum::http::HeaderValue;
+
+    fn queue(max_concurrent: usize, max_queued: usize) -> Arc<AdmissionQueue> {
+        let mut config = AdmissionConfig {
+            enabled: true,
+            max_concurrent,
+            max_wait_ms: 1000,
+            ..Default::default()
+        };
+        for class in config.classes.values_mut() {
+            class.max_queued = max_queued;
+        }
+        config.clients.insert(
+            "heavy".to_string(),
+            AdmissionClientConfig {
+                class: Some("batch".to_string()),
+                weight: 3,
+            },
+        );
+        Arc::new(AdmissionQueue::new(&config).unwrap())
+    }
+
+    fn headers(client: &str, priority: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-kcp-client", HeaderValue::from_str(client).unwrap());
+        if let Some(priority) = priority {
+            headers.insert("x-kcp-priority", HeaderValue::from_static(priority));
+        }
+        headers
+    }
+
+    /// Queue one request per header set, in order, and return the order they are admitted in
+    async fn admission_order(queue: &Arc<AdmissionQueue>, requests: Vec<HeaderMap>) -> Vec<usize> {
+        let blocker = queue.admit(&HeaderMap::new()).await.unwrap();
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (index, headers) in requests.into_iter().enumerate() {
+            let queue = queue.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let permit = queue.admit(&headers).await.unwrap();
+                order_tx.send(index).unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                drop(permit);
+            });
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        drop(order_tx);
+        drop(blocker);
+
+        let mut order = Vec::new();
+        while let Some(index) = order_rx.recv().await {
+            order.push(index);
+        }
+        order
+    }
+
+    #[tokio::test]
+    async fn test_interactive_admitted_before_batch() {
+        let queue = queue(1, 10);
+        let order = admission_order(
+            &queue,
+            vec![
+                headers("indexer", Some("batch")),
+                headers("indexer", Some("batch")),
+                headers("chat", None),
+            ],
+        )
+        .await;
+        assert_eq!(order, vec![2, 0, 1]);
+        assert_eq!(queue.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_fair_share_within_class() {
+        let queue = queue(1, 10);
+        // "heavy" has weight 3 in the batch class, "light" the default weight 1
+        let mut requests = Vec::new();
+        for _ in 0..4 {
+            requests.push(headers("heavy", None));
+        }
+        for _ in 0..4 {
+            requests.push(headers("light", Some("batch")));
+        }
+        let order = admission_order(&queue, requests).await;
+
+        let light_in_first_five = order[..5].iter().filter(|&&i| i >= 4).count();
+        assert_eq!(light_in_first_five, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sheds_when_queue_full_or_wait_exceeded() {
+        let queue = queue(1, 1);
+        let _held = queue.admit(&HeaderMap::new()).await.unwrap();
+
+        let waiting = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.admit(&HeaderMap::new()).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let shed = queue.admit(&HeaderMap::new()).await.unwrap_err();
+        assert!(matches!(shed, ProxyError::Overloaded { .. }));
+        assert_eq!(shed.status_code(), 503);
+
+        // max_wait_ms = 1000
+        let timed_out = waiting.await.unwrap().unwrap_err();
+        assert!(matches!(timed_out, ProxyError::Overloaded { .. }));
+    }
+
+    #[test]
+    fn test_rejects_unknown_classes() {
+        let config = AdmissionConfig {
+            default_class: "missing".to_string(),
+            ..Default::default()
+        };
+        assert!(AdmissionQueue::new(&config).is_err());
+
+        let mut config = AdmissionConfig::default();
+        config.classes.insert(
+            "realtime".to_string(),
+            PriorityClassConfig {
+                rank: 0,
+                max_queued: 10,
+                max_in_flight: Some(1),
+            },
+        );
+        assert!(AdmissionQueue::new(&config).is_ok());
+    }
+
+    #[test]
+    fn test_priority_header_cannot_raise_class() {
+        let queue = queue(1, 10);
+        let class = |client, priority| queue.classify(&headers(client, priority)).0;
+        let interactive = queue.class_names["interactive"];
+        let batch = queue.class_names["batch"];
+
+        assert_eq!(class("heavy", Some("interactive")), batch);
+        assert_eq!(class("chat", Some("batch")), batch);
+        assert_eq!(class("chat", Some("unknown")), interactive);
+        assert_eq!(class("chat", None), interactive);
+    }
+}
@@ -0,0 +1,716 @@
+use crate::config::AuditConfig;
+use crate::proxy::engine::REQUEST_ID_HEADER;
+use crate::proxy::error::ProxyError;
+use crate::proxy::stream::{next_event, ProxiedResponse, StreamObserver, StreamOutcome};
+use crate::util::unix_millis;
+use anyhow::{Context, Result};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method};
+use bytes::{Bytes, BytesMut};
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Client credentials, never recorded in the clear
+const CREDENTIAL_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "api-key",
+    "x-api-key",
+    "x-goog-api-key",
+];
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Opt-in record of request and response bodies, written as JSONL to rotating local files.
+///
+/// Requests only copy their bytes into a record, which is handed to a writer task over a
+/// bounded queue once the response has been delivered. The writer reassembles streams, masks
+/// key secrets, client credentials and the configured headers, then applies the PII patterns,
+/// so the client stream never waits on redaction or the disk.
+#[derive(Debug)]
+pub struct AuditLog {
+    sender: mpsc::Sender<AuditRecord>,
+    sampling: Sampling,
+    client_header: String,
+    max_body_bytes: usize,
+}
+
+impl AuditLog {
+    /// Start the writer task; `secrets` are the pool's API keys
+    pub fn new(config: &AuditConfig, secrets: Vec<String>) -> Result<Self> {
+        let lowercase = |names: &[String]| names.iter().map(|n| n.to_ascii_lowercase()).collect();
+        let patterns = config
+            .pii_patterns
+            .iter()
+            .map(|pii| {
+                Regex::new(&pii.pattern)
+                    .map(|regex| (regex, pii.replacement.clone()))
+                    .with_context(|| format!("Invalid audit PII pattern '{}'", pii.pattern))
+            })
+            .collect::<Result<_>>()?;
+        let redactor = Redactor {
+            secrets: secrets.into_iter().filter(|s| !s.is_empty()).collect(),
+            headers: lowercase(&config.redact_headers),
+            patterns,
+        };
+
+        let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+        let writer = AuditWriter {
+            dir: PathBuf::from(&config.dir),
+            max_file_bytes: config.max_file_bytes,
+            max_files: config.max_files.max(1),
+            redactor,
+            file: None,
+            written: 0,
+        };
+        tokio::spawn(writer.run(receiver));
+
+        Ok(Self {
+            sender,
+            sampling: Sampling {
+                default: config.sample_rate,
+                paths: config.paths.clone(),
+                clients: config.clients.clone(),
+                sampler: RandomState::new(),
+                draws: AtomicU64::new(0),
+            },
+            client_header: config.client_header.to_ascii_lowercase(),
+            max_body_bytes: config.max_body_bytes,
+        })
+    }
+
+    /// Start a record for the request, if it is sampled
+    pub fn begin(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> Option<AuditEntry> {
+        let client = headers
+            .get(&self.client_header)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        if !self.sampling.sampled(path, client.as_deref()) {
+            return None;
+        }
+
+        let record = AuditRecord {
+            timestamp_ms: unix_millis(),
+            duration_ms: 0,
+            request_id: headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            client,
+            method: method.to_string(),
+            path: path.to_string(),
+            status: 0,
+            outcome: "",
+            request_headers: headers.clone(),
+            request_body: body.slice(..body.len().min(self.max_body_bytes)),
+            request_truncated: body.len() > self.max_body_bytes,
+            response_body: Bytes::new(),
+            response_truncated: false,
+            streamed: false,
+        };
+        Some(AuditEntry {
+            sender: self.sender.clone(),
+            max_body_bytes: self.max_body_bytes,
+            started: Instant::now(),
+            response: BytesMut::new(),
+            record,
+        })
+    }
+}
+
+/// One sampled request, sent to the writer when its response ends
+#[derive(Debug)]
+pub struct AuditEntry {
+    sender: mpsc::Sender<AuditRecord>,
+    max_body_bytes: usize,
+    started: Instant,
+    response: BytesMut,
+    record: AuditRecord,
+}
+
+impl AuditEntry {
+    /// Copy the response body as it streams to the client
+    pub fn observe(mut self, response: ProxiedResponse) -> ProxiedResponse {
+        self.record.status = response.status.as_u16();
+        self.record.streamed = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        response.tap(self)
+    }
+
+    /// Record a request that ended in an error instead of a response body
+    pub fn log_error(mut self, error: &ProxyError) {
+        self.record.status = error.status_code().as_u16();
+        self.response = match error.upstream_response() {
+            Some((_, _, body)) => BytesMut::from(&body[..body.len().min(self.max_body_bytes)]),
+            None => BytesMut::from(
+                serde_json::to_vec(&error.to_openai_error())
+                    .unwrap_or_default()
+                    .as_slice(),
+            ),
+        };
+        self.send("error");
+    }
+
+    fn send(&mut self, outcome: &'static str) {
+        let mut record = std::mem::replace(&mut self.record, AuditRecord::empty());
+        record.outcome = outcome;
+        record.duration_ms = self.started.elapsed().as_millis() as u64;
+        record.response_body = std::mem::take(&mut self.response).freeze();
+        if self.sender.try_send(record).is_err() {
+            warn!("Audit queue is full or closed; dropping the record");
+        }
+    }
+}
+
+impl StreamObserver for AuditEntry {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        let room = self.max_body_bytes.saturating_sub(self.response.len());
+        if chunk.len() > room {
+            self.record.response_truncated = true;
+        }
+        self.response
+            .extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    fn on_end(&mut self, outcome: StreamOutcome) {
+        self.send(match outcome {
+            StreamOutcome::Completed => "completed",
+            StreamOutcome::Failed => "failed",
+            StreamOutcome::Aborted => "aborted",
+        });
+    }
+}
+
+/// Raw bytes and metadata of one exchange, redacted and serialized by the writer task
+#[derive(Debug)]
+struct AuditRecord {
+    timestamp_ms: u64,
+    duration_ms: u64,
+    request_id: Option<String>,
+    client: Option<String>,
+    method: String,
+    path: String,
+    status: u16,
+    outcome: &'static str,
+    request_headers: HeaderMap,
+    request_body: Bytes,
+    request_truncated: bool,
+    response_body: Bytes,
+    response_truncated: bool,
+    streamed: bool,
+}
+
+impl AuditRecord {
+    fn empty() -> Self {
+        Self {
+            timestamp_ms: 0,
+            duration_ms: 0,
+            request_id: None,
+            client: None,
+            method: String::new(),
+            path: String::new(),
+            status: 0,
+            outcome: "",
+            request_headers: HeaderMap::new(),
+            request_body: Bytes::new(),
+            request_truncated: false,
+            response_body: Bytes::new(),
+            response_truncated: false,
+            streamed: false,
+        }
+    }
+
+    /// The JSONL line for this record, with streams reassembled and everything masked
+    fn to_line(&self, redactor: &Redactor) -> Vec<u8> {
+        let model = serde_json::from_slice::<Value>(&self.request_body)
+            .ok()
+            .and_then(|body| body.get("model")?.as_str().map(String::from));
+        let response = if self.streamed && !self.response_truncated {
+            redactor.value(reassemble_sse(&self.response_body))
+        } else {
+            redactor.body(&self.response_body)
+        };
+        let record = json!({
+            "timestamp_ms": self.timestamp_ms,
+            "duration_ms": self.duration_ms,
+            "request_id": self.request_id,
+            "client": self.client,
+            "method": self.method,
+            "path": self.path,
+            "model": model,
+            "status": self.status,
+            "outcome": self.outcome,
+            "request": {
+                "headers": redactor.headers(&self.request_headers),
+                "body": redactor.body(&self.request_body),
+                "truncated": self.request_truncated,
+            },
+            "response": {"body": response, "truncated": self.response_truncated},
+        });
+        let mut line = serde_json::to_vec(&record).unwrap_or_default();
+        line.push(b'\n');
+        line
+    }
+}
+
+/// Which requests are recorded: the client's rate, else the route's, else the default
+#[derive(Debug)]
+struct Sampling {
+    default: f64,
+    paths: HashMap<String, f64>,
+    clients: HashMap<String, f64>,
+    sampler: RandomState,
+    draws: AtomicU64,
+}
+
+impl Sampling {
+    fn rate(&self, path: &str, client: Option<&str>) -> f64 {
+        if let Some(rate) = client.and_then(|client| self.clients.get(client)) {
+            return *rate;
+        }
+        if let Some(rate) = self.paths.get(path) {
+            return *rate;
+        }
+        self.paths
+            .iter()
+            .filter_map(|(pattern, rate)| {
+                let prefix = pattern.strip_suffix('*')?;
+                path.starts_with(prefix).then_some((prefix.len(), *rate))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map_or(self.default, |(_, rate)| rate)
+    }
+
+    fn sampled(&self, path: &str, client: Option<&str>) -> bool {
+        let rate = self.rate(path, client);
+        if rate >= 1.0 {
+            return true;
+        }
+        let draw = self
+            .sampler
+            .hash_one(self.draws.fetch_add(1, Ordering::Relaxed));
+        (draw as f64 / u64::MAX as f64) < rate
+    }
+}
+
+/// Masks secrets and PII in everything that is recorded
+#[derive(Debug)]
+struct Redactor {
+    secrets: Vec<String>,
+    headers: Vec<String>,
+    patterns: Vec<(Regex, String)>,
+}
+
+impl Redactor {
+    fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        for (pattern, replacement) in &self.patterns {
+            text = pattern
+                .replace_all(&text, replacement.as_str())
+                .into_owned();
+        }
+        text
+    }
+
+    /// A body as JSON when it still parses after masking, else as a string
+    fn body(&self, body: &[u8]) -> Value {
+        let text = self.text(&String::from_utf8_lossy(body));
+        serde_json::from_str(&text).unwrap_or(Value::String(text))
+    }
+
+    fn value(&self, value: Value) -> Value {
+        self.body(value.to_string().as_bytes())
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Map<String, Value> {
+        let mut recorded = Map::new();
+        for (name, value) in headers {
+            let name = name.as_str();
+            let value = if CREDENTIAL_HEADERS.contains(&name)
+                || self.headers.iter().any(|header| header == name)
+            {
+                REDACTED.to_string()
+            } else {
+                self.text(&String::from_utf8_lossy(value.as_bytes()))
+            };
+            recorded.insert(name.to_string(), Value::String(value));
+        }
+        recorded
+    }
+}
+
+/// Rebuild one completion from the chunks of a streamed response
+fn reassemble_sse(body: &[u8]) -> Value {
+    let mut buffer = BytesMut::from(body);
+    let mut completion = Map::new();
+    let mut choices: Vec<Map<String, Value>> = Vec::new();
+    while let Some(event) = next_event(&mut buffer, true) {
+        let Ok(event) = std::str::from_utf8(&event) else {
+            continue;
+        };
+        for data in event.lines().filter_map(|line| line.strip_prefix("data:")) {
+            let Ok(Value::Object(chunk)) = serde_json::from_str::<Value>(data.trim()) else {
+                continue;
+            };
+            for field in ["id", "model", "created", "system_fingerprint"] {
+                if let Some(value) = chunk.get(field) {
+                    completion.insert(field.to_string(), value.clone());
+                }
+            }
+            if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+                completion.insert("usage".to_string(), usage.clone());
+            }
+            for choice in chunk
+                .get("choices")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                merge_choice(&mut choices, choice);
+            }
+        }
+    }
+    completion.insert(
+        "choices".to_string(),
+        Value::Array(choices.into_iter().map(Value::Object).collect()),
+    );
+    Value::Object(completion)
+}
+
+/// Merge a streamed choice into the choice with the same index
+fn merge_choice(choices: &mut Vec<Map<String, Value>>, choice: &Value) {
+    let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+    while choices.len() <= index {
+        let mut choice = Map::new();
+        choice.insert("index".to_string(), json!(choices.len()));
+        choices.push(choice);
+    }
+    let merged = &mut choices[index];
+
+    // Chat completions stream `delta`, legacy completions stream `text`
+    if let Some(delta) = choice.get("delta").and_then(Value::as_object) {
+        let message = merged
+            .entry("message")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Some(message) = message.as_object_mut() {
+            merge_delta(message, delta);
+        }
+    }
+    if let Some(text) = choice.get("text").and_then(Value::as_str) {
+        append(merged, "text", text);
+    }
+    if let Some(reason) = choice.get("finish_reason").filter(|r| !r.is_null()) {
+        merged.insert("finish_reason".to_string(), reason.clone());
+    }
+}
+
+/// Fold a delta into the message built so far.
+///
+/// Text is concatenated, nested objects such as `function_call` are merged field by field,
+/// and arrays of indexed parts such as `tool_calls` are merged by `index`, so tool arguments
+/// are rebuilt the same way as content. Identifiers are sent whole and replace earlier values.
+fn merge_delta(target: &mut Map<String, Value>, delta: &Map<String, Value>) {
+    const IDENTIFIERS: &[&str] = &["role", "id", "type", "name"];
+    for (field, value) in delta {
+        match value {
+            Value::Null => {}
+            Value::String(text) if !IDENTIFIERS.contains(&field.as_str()) => {
+                append(target, field, text)
+            }
+            Value::Object(part) => {
+                let merged = target
+                    .entry(field.as_str())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                match merged.as_object_mut() {
+                    Some(merged) => merge_delta(merged, part),
+                    None => *merged = value.clone(),
+                }
+            }
+            Value::Array(parts) if parts.iter().all(|p| p.get("index").is_some()) => {
+                let merged = target
+                    .entry(field.as_str())
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Some(merged) = merged.as_array_mut() {
+                    for part in parts.iter().filter_map(Value::as_object) {
+                        merge_indexed(merged, part);
+                    }
+                }
+            }
+            _ => {
+                target.insert(field.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn merge_indexed(parts: &mut Vec<Value>, part: &Map<String, Value>) {
+    let index = part.get("index");
+    let position = match parts.iter().position(|p| p.get("index") == index) {
+        Some(position) => position,
+        None => {
+            parts.push(Value::Object(Map::new()));
+            parts.len() - 1
+        }
+    };
+    if let Some(merged) = parts[position].as_object_mut() {
+        merge_delta(merged, part);
+    }
+}
+
+fn append(object: &mut Map<String, Value>, field: &str, text: &str) {
+    match object.get_mut(field) {
+        Some(Value::String(current)) => current.push_str(text),
+        _ => {
+            object.insert(field.to_string(), Value::String(text.to_string()));
+        }
+    }
+}
+
+/// Appends records to `audit-<unix millis>.jsonl` files, keeping the newest `max_files`
+struct AuditWriter {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    redactor: Redactor,
+    file: Option<tokio::fs::File>,
+    written: u64,
+}
+
+impl AuditWriter {
+    async fn run(mut self, mut receiver: mpsc::Receiver<AuditRecord>) {
+        while let Some(record) = receiver.recv().await {
+            let line = record.to_line(&self.redactor);
+            if let Err(e) = self.write(&line).await {
+                warn!(
+                    "Failed to write audit record to {}: {}",
+                    self.dir.display(),
+                    e
+                );
+                self.file = None;
+            }
+        }
+    }
+
+    async fn write(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() || self.written + line.len() as u64 > self.max_file_bytes {
+            self.rotate().await?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line).await?;
+            file.flush().await?;
+            self.written += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("audit-{}.jsonl", unix_millis()));
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        self.written = file.metadata().await?.len();
+        self.file = Some(file);
+
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("audit-") && name.ends_with(".jsonl") {
+                files.push(entry.path());
+            }
+        }
+        // Millisecond names of equal length sort by age
+        files.sort();
+        let excess = files.len().saturating_sub(self.max_files);
+        for old in &files[..excess] {
+            tokio::fs::remove_file(old).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PiiPatternConfig;
+    use axum::http::{HeaderValue, StatusCode};
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    fn config(dir: &std::path::Path) -> AuditConfig {
+        AuditConfig {
+            enabled: true,
+            dir: dir.display().to_string(),
+            redact_headers: vec!["X-Tenant-Token".to_string()],
+            pii_patterns: vec![PiiPatternConfig {
+                pattern: r"[\w.+-]+@[\w-]+\.[\w.]+".to_string(),
+                replacement: "[EMAIL]".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    async fn read_records(dir: &std::path::Path) -> Vec<Value> {
+        for _ in 0..100 {
+            let mut records = Vec::new();
+            if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let text = tokio::fs::read_to_string(entry.path()).await.unwrap();
+                    records.extend(text.lines().map(|l| serde_json::from_str(l).unwrap()));
+                }
+            }
+            if !records.is_empty() {
+                return records;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no audit records written");
+    }
+
+    #[tokio::test]
+    async fn test_records_streamed_exchange_with_redaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = AuditLog::new(&config(dir.path()), vec!["sk-secret-1".to_string()]).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer client"));
+        headers.insert("x-tenant-token", HeaderValue::from_static("tenant-123"));
+        headers.insert("x-kcp-client", HeaderValue::from_static("compliance-app"));
+        let body = Bytes::from_static(
+            br#"{"model": "gpt-4o", "messages": [{"role": "user", "content": "mail ana@example.com, key sk-secret-1"}]}"#,
+        );
+        let entry = audit
+            .begin(&Method::POST, "/v1/chat/completions", &headers, &body)
+            .unwrap();
+
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        let events = concat!(
+            "data: {\"id\": \"c1\", \"choices\": [{\"index\": 0, \"delta\": {\"role\": \"assistant\", \"content\": \"Hel\"}}]}\n\n",
+            "data: {\"id\": \"c1\", \"choices\": [{\"index\": 0, \"delta\": {\"content\": \"lo\"}, \"finish_reason\": \"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let response = entry.observe(ProxiedResponse {
+            status: StatusCode::OK,
+            headers: response_headers,
+            body: Box::pin(futures::stream::once(async move {
+                Ok(Bytes::from_static(events.as_bytes()))
+            })),
+        });
+        let mut body = response.body;
+        let mut delivered = Vec::new();
+        while let Some(chunk) = body.next().await {
+            delivered.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(delivered, events.as_bytes());
+
+        let records = read_records(dir.path()).await;
+        let record = &records[0];
+        assert_eq!(record["client"], "compliance-app");
+        assert_eq!(record["model"], "gpt-4o");
+        assert_eq!(record["outcome"], "completed");
+        assert_eq!(record["request"]["headers"]["authorization"], REDACTED);
+        assert_eq!(record["request"]["headers"]["x-tenant-token"], REDACTED);
+        assert_eq!(
+            record["request"]["body"]["messages"][0]["content"],
+            "mail [EMAIL], key [REDACTED]"
+        );
+        let choice = &record["response"]["body"]["choices"][0];
+        assert_eq!(choice["message"]["content"], "Hello");
+        assert_eq!(choice["finish_reason"], "stop");
+    }
+
+    #[test]
+    fn test_reassembles_streamed_tool_calls() {
+        let events = concat!(
+            "data: {\"choices\": [{\"index\": 0, \"delta\": {\"role\": \"assistant\", \"content\": null, \"tool_calls\": [{\"index\": 0, \"id\": \"call_1\", \"type\": \"function\", \"function\": {\"name\": \"lookup\", \"arguments\": \"\"}}]}}]}\n\n",
+            "data: {\"choices\": [{\"index\": 0, \"delta\": {\"tool_calls\": [{\"index\": 0, \"function\": {\"arguments\": \"{\\\"city\\\": \"}}]}}]}\n\n",
+            "data: {\"choices\": [{\"index\": 0, \"delta\": {\"tool_calls\": [{\"index\": 0, \"function\": {\"arguments\": \"\\\"Oslo\\\"}\"}}]}}]}\n\n",
+            "data: {\"choices\": [{\"index\": 0, \"delta\": {}, \"finish_reason\": \"tool_calls\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let completion = reassemble_sse(events.as_bytes());
+        let choice = &completion["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["role"], "assistant");
+        let call = &choice["message"]["tool_calls"][0];
+        assert_eq!(call["id"], "call_1");
+        assert_eq!(call["function"]["name"], "lookup");
+        assert_eq!(call["function"]["arguments"], r#"{"city": "Oslo"}"#);
+    }
+
+    #[test]
+    fn test_sampling_precedence() {
+        let sampling = Sampling {
+            default: 0.5,
+            paths: HashMap::from([
+                ("/v1/embeddings".to_string(), 0.0),
+                ("/v1/chat/*".to_string(), 1.0),
+            ]),
+            clients: HashMap::from([("batch".to_string(), 0.0)]),
+            sampler: RandomState::new(),
+            draws: AtomicU64::new(0),
+        };
+        assert_eq!(sampling.rate("/v1/chat/completions", None), 1.0);
+        assert_eq!(sampling.rate("/v1/chat/completions", Some("batch")), 0.0);
+        assert_eq!(sampling.rate("/v1/embeddings", Some("ui")), 0.0);
+        assert_eq!(sampling.rate("/v1/images/generations", None), 0.5);
+        assert!(!sampling.sampled("/v1/embeddings", None));
+        assert!(sampling.sampled("/v1/chat/completions", None));
+
+        let sampled = (0..10_000)
+            .filter(|_| sampling.sampled("/v1/images/generations", None))
+            .count();
+        assert!((4_500..5_500).contains(&sampled), "sampled {}", sampled);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = AuditWriter {
+            dir: dir.path().to_path_buf(),
+            max_file_bytes: 10,
+            max_files: 2,
+            redactor: Redactor {
+                secrets: Vec::new(),
+                headers: Vec::new(),
+                patterns: Vec::new(),
+            },
+            file: None,
+            written: 0,
+        };
+        for _ in 0..4 {
+            writer.write(b"0123456789\n").await.unwrap();
+            // Distinct millisecond file names
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        let count = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(count, 2);
+    }
+}
@@ -0,0 +1,449 @@
+use crate::config::BatchingConfig;
+use crate::proxy::cache::canonical_json;
+use crate::proxy::engine::REQUEST_ID_HEADER;
+use crate::proxy::error::{ProxyError, ProxyResult};
+use crate::proxy::stream::{collect, ProxiedResponse};
+use axum::http::{header::CONTENT_LENGTH, HeaderMap, StatusCode};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// Path whose requests can be merged
+pub const EMBEDDINGS_PATH: &str = "/v1/embeddings";
+
+/// Forwarded headers that differ per request and are set again for the merged call
+const PER_REQUEST_HEADERS: &[&str] = &[REQUEST_ID_HEADER, "traceparent", "tracestate"];
+
+/// Sends a request body upstream; the flag is set when the body merges several callers' inputs
+pub type Dispatch =
+    Arc<dyn Fn(Bytes, bool) -> BoxFuture<'static, ProxyResult<ProxiedResponse>> + Send + Sync>;
+
+/// Merges concurrent embedding requests for the same model into one array-input call
+#[derive(Debug)]
+pub struct EmbeddingBatcher {
+    window: Duration,
+    max_inputs: usize,
+    open: Mutex<HashMap<String, Batch>>,
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+struct Batch {
+    id: u64,
+    /// The first request's body; merged requests replace its `input`
+    template: Value,
+    callers: Vec<Caller>,
+    inputs: usize,
+}
+
+impl std::fmt::Debug for Batch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Batch")
+            .field("id", &self.id)
+            .field("callers", &self.callers.len())
+            .field("inputs", &self.inputs)
+            .finish()
+    }
+}
+
+struct Caller {
+    body: Bytes,
+    /// Sends this caller's own request; the first caller's also sends the merged one
+    dispatch: Dispatch,
+    inputs: Vec<Value>,
+    /// Characters (or tokens, for pre-tokenized input) used to split `usage`
+    weight: u64,
+    reply: oneshot::Sender<ProxyResult<ProxiedResponse>>,
+}
+
+impl EmbeddingBatcher {
+    pub fn new(config: &BatchingConfig) -> Self {
+        Self {
+            window: config.window(),
+            max_inputs: config.max_inputs.max(1),
+            open: Mutex::new(HashMap::new()),
+            next_id: Default::default(),
+        }
+    }
+
+    /// Queue an embeddings request, or `None` when its body cannot be merged.
+    ///
+    /// `forwarded` are the client headers that go upstream with the request; only requests
+    /// forwarding the same headers are merged. `dispatch` sends the merged call when this
+    /// request opens a new batch, and this request alone if the merged call is rejected.
+    pub fn submit(
+        self: &Arc<Self>,
+        body: &Bytes,
+        model: &str,
+        forwarded: &reqwest::header::HeaderMap,
+        dispatch: Dispatch,
+    ) -> Option<oneshot::Receiver<ProxyResult<ProxiedResponse>>> {
+        let mut request: Value = serde_json::from_slice(body).ok()?;
+        let (inputs, weight) = split_input(request.get("input")?)?;
+        if inputs.len() > self.max_inputs {
+            return None;
+        }
+
+        // Requests batch together when everything but `input` matches, headers included
+        request.as_object_mut()?.remove("input");
+        request["model"] = Value::String(model.to_string());
+        let group = format!(
+            "{}\n{}",
+            canonical_json(&request),
+            header_fingerprint(forwarded)
+        );
+
+        let (reply, receiver) = oneshot::channel();
+        let caller = Caller {
+            body: body.clone(),
+            dispatch,
+            inputs,
+            weight,
+            reply,
+        };
+
+        let mut open = self.open.lock().unwrap();
+        if let Some(batch) = open.get_mut(&group) {
+            if batch.inputs + caller.inputs.len() <= self.max_inputs {
+                batch.inputs += caller.inputs.len();
+                batch.callers.push(caller);
+                if batch.inputs == self.max_inputs {
+                    let batch = open.remove(&group).unwrap();
+                    tokio::spawn(execute(batch));
+                }
+                return Some(receiver);
+            }
+            // Full: send what has been collected and start over with this request
+            let batch = open.remove(&group).unwrap();
+            tokio::spawn(execute(batch));
+        }
+
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        open.insert(
+            group.clone(),
+            Batch {
+                id,
+                template: serde_json::from_slice(body).ok()?,
+                inputs: caller.inputs.len(),
+                callers: vec![caller],
+            },
+        );
+        drop(open);
+
+        let batcher = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(batcher.window).await;
+            let batch = {
+                let mut open = batcher.open.lock().unwrap();
+                match open.get(&group) {
+                    Some(batch) if batch.id == id => open.remove(&group),
+                    // Already sent because it filled up
+                    _ => None,
+                }
+            };
+            if let Some(batch) = batch {
+                execute(batch).await;
+            }
+        });
+
+        Some(receiver)
+    }
+}
+
+/// Forwarded headers in a stable order, leaving out the ones unique to each request
+pub fn header_fingerprint(headers: &reqwest::header::HeaderMap) -> String {
+    let mut lines: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| !PER_REQUEST_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes())))
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+/// Normalize `input` into one entry per embedding, with a weight for usage accounting
+fn split_input(input: &Value) -> Option<(Vec<Value>, u64)> {
+    match input {
+        Value::String(text) => Some((vec![input.clone()], text.chars().count() as u64)),
+        Value::Array(items) if items.is_empty() => None,
+        // A single pre-tokenized input
+        Value::Array(items) if items.iter().all(Value::is_number) => {
+            Some((vec![input.clone()], items.len() as u64))
+        }
+        Value::Array(items) => {
+            let mut weight = 0;
+            for item in items {
+                weight += match item {
+                    Value::String(text) => text.chars().count() as u64,
+                    Value::Array(tokens) if tokens.iter().all(Value::is_number) => {
+                        tokens.len() as u64
+                    }
+                    _ => return None,
+                };
+            }
+            Some((items.clone(), weight))
+        }
+        _ => None,
+    }
+}
+
+async fn execute(batch: Batch) {
+    let Batch {
+        template,
+        mut callers,
+        ..
+    } = batch;
+
+    // Nothing to merge: forward the original request untouched
+    if callers.len() == 1 {
+        let caller = callers.pop().unwrap();
+        let _ = caller
+            .reply
+            .send((caller.dispatch)(caller.body, false).await);
+        return;
+    }
+
+    debug!(
+        "Sending {} embedding requests as one upstream call",
+        callers.len()
+    );
+    let mut merged = template;
+    merged["input"] = Value::Array(callers.iter().flat_map(|c| c.inputs.clone()).collect());
+    let body = match serde_json::to_vec(&merged) {
+        Ok(body) => Bytes::from(body),
+        Err(e) => return fail_all(callers, ProxyError::from(e)),
+    };
+
+    let dispatch = callers[0].dispatch.clone();
+    let response = match dispatch(body, true).await {
+        Ok(response) if blames_input(response.status) => {
+            return dispatch_each(callers, response.status).await
+        }
+        Ok(response) => response,
+        Err(ProxyError::UpstreamStatus { status, .. }) if blames_input(status) => {
+            return dispatch_each(callers, status).await
+        }
+        Err(e) => return fail_all(callers, e),
+    };
+    let status = response.status;
+    let mut headers = response.headers;
+    headers.remove(CONTENT_LENGTH);
+    let body = match collect(response.body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return fail_all(
+                callers,
+                ProxyError::internal(format!("Failed to read batched response: {}", e)),
+            )
+        }
+    };
+
+    // Any other error applies to every request in the batch
+    if !status.is_success() {
+        for caller in callers {
+            let _ = caller
+                .reply
+                .send(Ok(buffered(status, headers.clone(), body.clone())));
+        }
+        return;
+    }
+
+    let parts = match serde_json::from_slice(&body)
+        .ok()
+        .and_then(|response| split_response(response, &callers))
+    {
+        Some(parts) => parts,
+        None => {
+            return fail_all(
+                callers,
+                ProxyError::internal("Malformed upstream embeddings response"),
+            )
+        }
+    };
+    for (caller, part) in callers.into_iter().zip(parts) {
+        let _ = caller
+            .reply
+            .send(Ok(buffered(status, headers.clone(), part)));
+    }
+}
+
+/// Split a merged embeddings response into one response body per caller
+fn split_response(response: Value, callers: &[Caller]) -> Option<Vec<Bytes>> {
+    let mut data: Vec<Value> = response.get("data")?.as_array()?.clone();
+    let total: usize = callers.iter().map(|c| c.inputs.len()).sum();
+    if data.len() != total {
+        return None;
+    }
+    data.sort_by_key(|item| {
+        item.get("index")
+            .and_then(Value::as_u64)
+            .unwrap_or(u64::MAX)
+    });
+
+    let usage = split_usage(response.get("usage"), callers);
+    let mut data = data.into_iter();
+    let mut parts = Vec::with_capacity(callers.len());
+    for (position, caller) in callers.iter().enumerate() {
+        let items: Vec<Value> = data
+            .by_ref()
+            .take(caller.inputs.len())
+            .enumerate()
+            .map(|(index, mut item)| {
+                item["index"] = Value::from(index);
+                item
+            })
+            .collect();
+
+        let mut part = response.clone();
+        part["data"] = Value::Array(items);
+        if let Some(usage) = &usage {
+            part["usage"] = usage[position].clone();
+        }
+        parts.push(Bytes::from(serde_json::to_vec(&part).ok()?));
+    }
+    Some(parts)
+}
+
+/// Share each usage counter out by input weight, giving rounding leftovers to the last caller
+fn split_usage(usage: Option<&Value>, callers: &[Caller]) -> Option<Vec<Value>> {
+    let usage = usage?.as_object()?;
+    let total_weight: u64 = callers.iter().map(|c| c.weight).sum::<u64>().max(1);
+
+    let mut shares = vec![serde_json::Map::new(); callers.len()];
+    for (field, value) in usage {
+        let Some(total) = value.as_u64() else {
+            continue;
+        };
+        let mut assigned = 0;
+        for (position, caller) in callers.iter().enumerate() {
+            let share = if position + 1 == callers.len() {
+                total - assigned
+            } else {
+                total * caller.weight / total_weight
+            };
+            assigned += share;
+            shares[position].insert(field.clone(), Value::from(share));
+        }
+    }
+    Some(shares.into_iter().map(Value::Object).collect())
+}
+
+/// A rejection that may come from one caller's input or from the merged size
+fn blames_input(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE
+    )
+}
+
+/// Send every caller's original request on its own, so one bad input only fails its caller
+async fn dispatch_each(callers: Vec<Caller>, status: StatusCode) {
+    debug!(
+        "Batched embeddings call was rejected with {}; sending {} requests one by one",
+        status,
+        callers.len()
+    );
+    futures::future::join_all(callers.into_iter().map(|caller| async move {
+        let _ = caller
+            .reply
+            .send((caller.dispatch)(caller.body, false).await);
+    }))
+    .await;
+}
+
+fn buffered(status: StatusCode, headers: HeaderMap, body: Bytes) -> ProxiedResponse {
+    ProxiedResponse {
+        status,
+        headers,
+        body: Box::pin(futures::stream::once(async move { Ok(body) })),
+    }
+}
+
+fn fail_all(callers: Vec<Caller>, error: ProxyError) {
+    let error = Arc::new(error);
+    for caller in callers {
+        let _ = caller.reply.send(Err(ProxyError::Shared(error.clone())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn caller(input: Value) -> (Caller, oneshot::Receiver<ProxyResult<ProxiedResponse>>) {
+        let (inputs, weight) = split_input(&input).unwrap();
+        let (reply, receiver) = oneshot::channel();
+        let dispatch: Dispatch =
+            Arc::new(|_, _| Box::pin(async { Err(ProxyError::internal("not dispatched")) }));
+        let caller = Caller {
+            body: Bytes::new(),
+            dispatch,
+            inputs,
+            weight,
+            reply,
+        };
+        (caller, receiver)
+    }
+
+    #[test]
+    fn test_split_input_forms() {
+        assert_eq!(
+            split_input(&json!("abcd")).unwrap(),
+            (vec![json!("abcd")], 4)
+        );
+        assert_eq!(split_input(&json!(["ab", "c"])).unwrap().0.len(), 2);
+        assert_eq!(
+            split_input(&json!([1, 2, 3])).unwrap(),
+            (vec![json!([1, 2, 3])], 3)
+        );
+        assert_eq!(split_input(&json!([[1, 2], [3]])).unwrap().1, 3);
+        assert!(split_input(&json!([])).is_none());
+        assert!(split_input(&json!(["a", 1])).is_none());
+    }
+
+    #[test]
+    fn test_split_response_reindexes_and_splits_usage() {
+        let (first, _) = caller(json!("aaa"));
+        let (second, _) = caller(json!(["b", "cccccc"]));
+        let callers = vec![first, second];
+
+        let response = json!({
+            "object": "list",
+            "model": "text-embedding-3-small",
+            "data": [
+                {"object": "embedding", "index": 2, "embedding": [2.0]},
+                {"object": "embedding", "index": 0, "embedding": [0.0]},
+                {"object": "embedding", "index": 1, "embedding": [1.0]}
+            ],
+            "usage": {"prompt_tokens": 10, "total_tokens": 10}
+        });
+        let parts = split_response(response, &callers).unwrap();
+        let first: Value = serde_json::from_slice(&parts[0]).unwrap();
+        let second: Value = serde_json::from_slice(&parts[1]).unwrap();
+
+        assert_eq!(
+            first["data"],
+            json!([{"object": "embedding", "index": 0, "embedding": [0.0]}])
+        );
+        assert_eq!(second["data"][0]["embedding"], json!([1.0]));
+        assert_eq!(second["data"][1]["index"], 1);
+        assert_eq!(second["data"][1]["embedding"], json!([2.0]));
+        assert_eq!(first["usage"]["prompt_tokens"], 3);
+        assert_eq!(second["usage"]["prompt_tokens"], 7);
+        assert_eq!(second["model"], "text-embedding-3-small");
+    }
+
+    #[test]
+    fn test_split_response_rejects_count_mismatch() {
+        let (only, _) = caller(json!(["a", "b"]));
+        let response = json!({"data": [{"index": 0, "embedding": []}]});
+        assert!(split_response(response, &[only]).is_none());
+    }
+}
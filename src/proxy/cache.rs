@@ -0,0 +1,651 @@
+use crate::config::CacheConfig;
+use crate::proxy::stream::{split_events, ProxiedResponse, StreamObserver, StreamOutcome};
+use crate::util::unix_timestamp;
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use bytes::{Bytes, BytesMut};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Request header to control caching per request (`bypass` or `refresh`),
+/// and response header reporting `hit`, `miss` or `bypass`
+pub const CACHE_HEADER: &str = "x-kcp-cache";
+
+/// Endpoints whose output does not depend on sampling
+const DETERMINISTIC_PATHS: &[&str] = &["/v1/embeddings", "/v1/moderations"];
+
+/// How the cache treats a single request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheDirective {
+    /// Serve from cache when possible and store misses
+    Use,
+    /// Skip the lookup but store the fresh response (`Cache-Control: no-cache`)
+    Refresh,
+    /// Neither read nor write (`Cache-Control: no-store`)
+    Bypass,
+}
+
+impl CacheDirective {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let kcp = headers
+            .get(CACHE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_ascii_lowercase());
+        match kcp.as_deref() {
+            Some("bypass") => return CacheDirective::Bypass,
+            Some("refresh") => return CacheDirective::Refresh,
+            _ => {}
+        }
+
+        let cache_control = headers
+            .get_all(axum::http::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        if cache_control.iter().any(|d| d == "no-store") {
+            CacheDirective::Bypass
+        } else if cache_control.iter().any(|d| d == "no-cache") {
+            CacheDirective::Refresh
+        } else {
+            CacheDirective::Use
+        }
+    }
+}
+
+/// A cacheable request: its key and what the client asked the cache to do
+#[derive(Debug, Clone)]
+pub struct CacheRequest {
+    pub key: String,
+    pub directive: CacheDirective,
+}
+
+/// A complete upstream response, replayable as-is (SSE bodies included)
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+    pub stored_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedMeta {
+    status: u16,
+    headers: Vec<(String, String)>,
+    stored_at: u64,
+}
+
+impl CachedResponse {
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        unix_timestamp().saturating_sub(self.stored_at) < ttl.as_secs()
+    }
+
+    /// Rebuild the response, splitting SSE bodies back into one chunk per event
+    pub fn replay(&self) -> ProxiedResponse {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+
+        let is_sse = headers
+            .get(axum::http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        let chunks = if is_sse {
+            split_events(&self.body)
+        } else {
+            vec![self.body.clone()]
+        };
+
+        ProxiedResponse {
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            headers,
+            body: Box::pin(futures::stream::iter(chunks.into_iter().map(Ok))),
+        }
+    }
+}
+
+/// Opt-in exact-match response cache
+#[derive(Debug)]
+pub struct ResponseCache {
+    backend: CacheBackend,
+    ttl: Duration,
+    max_entry_bytes: usize,
+    deterministic_only: bool,
+}
+
+#[derive(Debug)]
+enum CacheBackend {
+    Memory(Mutex<MemoryStore>),
+    Disk(DiskStore),
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Result<Self> {
+        let backend = match config.backend.as_str() {
+            "memory" => CacheBackend::Memory(Mutex::new(MemoryStore::new(
+                config.max_entries,
+                config.max_total_bytes,
+            ))),
+            "disk" => {
+                let dir = PathBuf::from(&config.disk_path);
+                std::fs::create_dir_all(&dir).with_context(|| {
+                    format!("Failed to create cache directory {}", dir.display())
+                })?;
+                CacheBackend::Disk(
+                    DiskStore::open(dir, config.max_entries, config.max_total_bytes)
+                        .context("Failed to index cache directory")?,
+                )
+            }
+            other => anyhow::bail!("Unknown cache backend '{}'", other),
+        };
+
+        Ok(Self {
+            backend,
+            ttl: config.ttl(),
+            max_entry_bytes: config.max_entry_bytes,
+            deterministic_only: config.deterministic_only,
+        })
+    }
+
+    /// Key and directive for a request, or `None` when it must not be cached
+    pub fn prepare(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &Bytes,
+        model: &str,
+    ) -> Option<CacheRequest> {
+        let mut request: Value = serde_json::from_slice(body).ok()?;
+        if self.deterministic_only && !is_deterministic(path, &request) {
+            return None;
+        }
+        // Key on the resolved model so alias changes never serve stale entries
+        request["model"] = Value::String(model.to_string());
+
+        Some(CacheRequest {
+            key: request_hash(method, path, &request),
+            directive: CacheDirective::from_headers(headers),
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let entry = match &self.backend {
+            CacheBackend::Memory(store) => store.lock().unwrap().get(key),
+            CacheBackend::Disk(store) => store.get(key).await,
+        }?;
+
+        if entry.is_fresh(self.ttl) {
+            Some(entry)
+        } else {
+            self.remove(key).await;
+            None
+        }
+    }
+
+    pub async fn put(&self, key: String, entry: CachedResponse) {
+        if entry.body.len() > self.max_entry_bytes {
+            return;
+        }
+        match &self.backend {
+            CacheBackend::Memory(store) => store.lock().unwrap().put(key, entry),
+            CacheBackend::Disk(store) => {
+                if let Err(e) = store.put(&key, &entry).await {
+                    warn!("Failed to write cache entry: {:#}", e);
+                }
+            }
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        match &self.backend {
+            CacheBackend::Memory(store) => store.lock().unwrap().remove(key),
+            CacheBackend::Disk(store) => store.remove(key).await,
+        }
+    }
+
+    /// Record a successful response as it streams and store it once complete
+    pub fn record(self: &Arc<Self>, key: String, response: ProxiedResponse) -> ProxiedResponse {
+        if !response.status.is_success() {
+            return response;
+        }
+        let recorder = CacheRecorder {
+            cache: self.clone(),
+            key,
+            status: response.status.as_u16(),
+            headers: cacheable_headers(&response.headers),
+            body: Some(BytesMut::new()),
+        };
+        response.tap(recorder)
+    }
+}
+
+struct CacheRecorder {
+    cache: Arc<ResponseCache>,
+    key: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Option<BytesMut>,
+}
+
+impl StreamObserver for CacheRecorder {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        if let Some(body) = self.body.as_mut() {
+            if body.len() + chunk.len() > self.cache.max_entry_bytes {
+                debug!("Response too large to cache");
+                self.body = None;
+            } else {
+                body.extend_from_slice(chunk);
+            }
+        }
+    }
+
+    fn on_end(&mut self, outcome: StreamOutcome) {
+        let Some(body) = self.body.take() else {
+            return;
+        };
+        if outcome != StreamOutcome::Completed {
+            return;
+        }
+        let entry = CachedResponse {
+            status: self.status,
+            headers: std::mem::take(&mut self.headers),
+            body: body.freeze(),
+            stored_at: unix_timestamp(),
+        };
+        let cache = self.cache.clone();
+        let key = std::mem::take(&mut self.key);
+        tokio::spawn(async move { cache.put(key, entry).await });
+    }
+}
+
+#[derive(Debug)]
+struct MemoryStore {
+    entries: LruCache<String, CachedResponse>,
+    total_bytes: usize,
+    max_total_bytes: usize,
+}
+
+impl MemoryStore {
+    fn new(max_entries: usize, max_total_bytes: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN)),
+            total_bytes: 0,
+            max_total_bytes,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<CachedResponse> {
+        self.entries.get(key).cloned()
+    }
+
+    fn put(&mut self, key: String, entry: CachedResponse) {
+        self.total_bytes += entry.body.len();
+        if let Some((_, evicted)) = self.entries.push(key, entry) {
+            self.total_bytes -= evicted.body.len();
+        }
+        while self.total_bytes > self.max_total_bytes {
+            match self.entries.pop_lru() {
+                Some((_, evicted)) => self.total_bytes -= evicted.body.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(evicted) = self.entries.pop(key) {
+            self.total_bytes -= evicted.body.len();
+        }
+    }
+}
+
+/// One file per entry: a JSON metadata line followed by the raw body.
+///
+/// Entry sizes are tracked in memory, loaded from the directory once at startup, so a write
+/// never rescans the directory; the least recently used files go first once `max_entries` or
+/// `max_total_bytes` is exceeded.
+#[derive(Debug)]
+struct DiskStore {
+    dir: PathBuf,
+    index: Mutex<DiskIndex>,
+}
+
+#[derive(Debug)]
+struct DiskIndex {
+    /// Entry key to file size
+    entries: LruCache<String, usize>,
+    total_bytes: usize,
+    max_total_bytes: usize,
+}
+
+impl DiskIndex {
+    /// Record an entry's size, returning the keys of the entries it pushed out
+    fn insert(&mut self, key: String, bytes: usize) -> Vec<String> {
+        let mut evicted = Vec::new();
+        self.total_bytes += bytes;
+        if let Some((old, old_bytes)) = self.entries.push(key.clone(), bytes) {
+            self.total_bytes -= old_bytes;
+            if old != key {
+                evicted.push(old);
+            }
+        }
+        while self.total_bytes > self.max_total_bytes {
+            match self.entries.pop_lru() {
+                Some((old, old_bytes)) => {
+                    self.total_bytes -= old_bytes;
+                    evicted.push(old);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(bytes) = self.entries.pop(key) {
+            self.total_bytes -= bytes;
+        }
+    }
+}
+
+impl DiskStore {
+    /// Index the entries already in `dir`, oldest first, dropping leftover temporary files
+    fn open(dir: PathBuf, max_entries: usize, max_total_bytes: usize) -> Result<Self> {
+        let mut existing = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let key = entry.file_name().to_string_lossy().into_owned();
+            existing.push((metadata.modified()?, key, metadata.len() as usize));
+        }
+        existing.sort();
+
+        let mut index = DiskIndex {
+            entries: LruCache::new(NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN)),
+            total_bytes: 0,
+            max_total_bytes,
+        };
+        for (_, key, bytes) in existing {
+            for old in index.insert(key, bytes) {
+                let _ = std::fs::remove_file(dir.join(old));
+            }
+        }
+        Ok(Self {
+            dir,
+            index: Mutex::new(index),
+        })
+    }
+
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        self.index.lock().unwrap().entries.get(key)?;
+        let data = tokio::fs::read(self.dir.join(key)).await.ok()?;
+        let split = data.iter().position(|b| *b == b'\n')?;
+        let meta: CachedMeta = serde_json::from_slice(&data[..split]).ok()?;
+        Some(CachedResponse {
+            status: meta.status,
+            headers: meta.headers,
+            body: Bytes::copy_from_slice(&data[split + 1..]),
+            stored_at: meta.stored_at,
+        })
+    }
+
+    async fn put(&self, key: &str, entry: &CachedResponse) -> Result<()> {
+        let meta = CachedMeta {
+            status: entry.status,
+            headers: entry.headers.clone(),
+            stored_at: entry.stored_at,
+        };
+        let mut data = serde_json::to_vec(&meta)?;
+        data.push(b'\n');
+        data.extend_from_slice(&entry.body);
+
+        // Write then rename so readers never see a partial entry
+        let tmp = self.dir.join(format!("{}.tmp", key));
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, self.dir.join(key)).await?;
+
+        let evicted = self
+            .index
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), data.len());
+        for old in evicted {
+            let _ = tokio::fs::remove_file(self.dir.join(old)).await;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) {
+        self.index.lock().unwrap().remove(key);
+        let _ = tokio::fs::remove_file(self.dir.join(key)).await;
+    }
+}
+
+/// Whether a request produces the same output every time
+pub fn is_deterministic(path: &str, request: &Value) -> bool {
+    DETERMINISTIC_PATHS.contains(&path)
+        || request
+            .get("temperature")
+            .and_then(Value::as_f64)
+            .is_some_and(|t| t == 0.0)
+}
+
+/// Stable hash of method, path and the canonical form of the JSON body
+pub fn request_hash(method: &Method, path: &str, body: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(canonical_json(body).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Serialize JSON with object keys sorted, independent of input key order
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let fields: Vec<String> = keys
+                .into_iter()
+                .map(|k| format!("{}:{}", Value::String(k.clone()), canonical_json(&map[k])))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Headers worth replaying; connection-level and per-response headers are dropped
+fn cacheable_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    const SKIPPED: &[&str] = &[
+        "content-length",
+        "transfer-encoding",
+        "connection",
+        "date",
+        "set-cookie",
+    ];
+    headers
+        .iter()
+        .filter(|(name, _)| !SKIPPED.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn memory_cache(max_entries: usize) -> ResponseCache {
+        ResponseCache::new(&CacheConfig {
+            enabled: true,
+            max_entries,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn entry(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: Bytes::from_static(body.as_bytes()),
+            stored_at: unix_timestamp(),
+        }
+    }
+
+    #[test]
+    fn test_canonical_hash_ignores_key_order() {
+        let a = json!({"model": "gpt-4", "temperature": 0, "messages": [{"role": "user", "content": "hi"}]});
+        let b = json!({"messages": [{"content": "hi", "role": "user"}], "temperature": 0, "model": "gpt-4"});
+        let c = json!({"model": "gpt-4", "temperature": 0, "messages": [{"role": "user", "content": "hey"}]});
+
+        let path = "/v1/chat/completions";
+        assert_eq!(
+            request_hash(&Method::POST, path, &a),
+            request_hash(&Method::POST, path, &b)
+        );
+        assert_ne!(
+            request_hash(&Method::POST, path, &a),
+            request_hash(&Method::POST, path, &c)
+        );
+    }
+
+    #[test]
+    fn test_prepare_respects_determinism_and_directives() {
+        let cache = memory_cache(10);
+        let mut headers = HeaderMap::new();
+        let chat = Bytes::from(r#"{"model": "gpt-4", "temperature": 0}"#);
+        let sampled = Bytes::from(r#"{"model": "gpt-4", "temperature": 0.7}"#);
+        let embedding = Bytes::from(r#"{"model": "text-embedding-3-small", "input": "hi"}"#);
+
+        let path = "/v1/chat/completions";
+        let request = cache
+            .prepare(&Method::POST, path, &headers, &chat, "gpt-4")
+            .unwrap();
+        assert_eq!(request.directive, CacheDirective::Use);
+        assert!(cache
+            .prepare(&Method::POST, path, &headers, &sampled, "gpt-4")
+            .is_none());
+        assert!(cache
+            .prepare(&Method::POST, "/v1/embeddings", &headers, &embedding, "e")
+            .is_some());
+
+        headers.insert("cache-control", HeaderValue::from_static("no-cache"));
+        let request = cache
+            .prepare(&Method::POST, path, &headers, &chat, "gpt-4")
+            .unwrap();
+        assert_eq!(request.directive, CacheDirective::Refresh);
+
+        headers.insert(CACHE_HEADER, HeaderValue::from_static("bypass"));
+        let request = cache
+            .prepare(&Method::POST, path, &headers, &chat, "gpt-4")
+            .unwrap();
+        assert_eq!(request.directive, CacheDirective::Bypass);
+    }
+
+    #[tokio::test]
+    async fn test_memory_lru_eviction_and_ttl() {
+        let cache = memory_cache(2);
+        cache.put("a".to_string(), entry("a")).await;
+        cache.put("b".to_string(), entry("b")).await;
+        assert!(cache.get("a").await.is_some());
+        cache.put("c".to_string(), entry("c")).await;
+
+        // "b" was least recently used
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+
+        let mut stale = entry("stale");
+        stale.stored_at -= 7200;
+        cache.put("stale".to_string(), stale).await;
+        assert!(cache.get("stale").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_backend_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(&CacheConfig {
+            enabled: true,
+            backend: "disk".to_string(),
+            disk_path: dir.path().to_string_lossy().to_string(),
+            max_entries: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut sse = entry("data: {\"a\":1}\n\ndata: [DONE]\n\n");
+        sse.headers = vec![("content-type".to_string(), "text/event-stream".to_string())];
+        cache.put("first".to_string(), sse).await;
+
+        let cached = cache.get("first").await.unwrap();
+        let replay = cached.replay();
+        assert_eq!(replay.headers["content-type"], "text/event-stream");
+        let chunks: Vec<_> = futures::StreamExt::collect::<Vec<_>>(replay.body).await;
+        assert_eq!(chunks.len(), 2);
+
+        // max_entries = 1 evicts the older file
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cache.put("second".to_string(), entry("{}")).await;
+        assert!(cache.get("first").await.is_none());
+        assert!(cache.get("second").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_disk_index_survives_restart_and_bounds_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            enabled: true,
+            backend: "disk".to_string(),
+            disk_path: dir.path().to_string_lossy().to_string(),
+            max_total_bytes: 200,
+            ..Default::default()
+        };
+        let cache = ResponseCache::new(&config).unwrap();
+        cache.put("a".to_string(), entry("first")).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cache.put("b".to_string(), entry("second")).await;
+        std::fs::write(dir.path().join("c.tmp"), b"partial").unwrap();
+
+        // Reopening indexes the existing entries and drops the leftover temporary file
+        let cache = ResponseCache::new(&config).unwrap();
+        assert!(!dir.path().join("c.tmp").exists());
+        assert!(cache.get("a").await.is_some());
+
+        // Going over max_total_bytes pushes out the least recently used entry
+        cache.put("c".to_string(), entry("third")).await;
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::proxy::batch::header_fingerprint;
+use crate::proxy::cache::request_hash;
+use crate::proxy::error::{ProxyError, ProxyResult};
+use crate::proxy::stream::{BodyStream, ProxiedResponse};
+use axum::http::{HeaderMap, Method, StatusCode};
+use bytes::Bytes;
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// Single-flight deduplication of identical concurrent requests
+#[derive(Debug)]
+pub struct Coalescer {
+    paths: HashSet<String>,
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+/// One upstream call and everything it has produced so far
+#[derive(Debug)]
+struct Flight {
+    state: Mutex<FlightState>,
+    /// Bumped whenever `state` changes
+    updates: watch::Sender<u64>,
+}
+
+#[derive(Debug, Default)]
+struct FlightState {
+    head: Option<Result<(StatusCode, HeaderMap), Arc<ProxyError>>>,
+    chunks: Vec<Bytes>,
+    end: Option<FlightEnd>,
+}
+
+#[derive(Debug, Clone)]
+enum FlightEnd {
+    Completed,
+    Failed(String),
+}
+
+impl Flight {
+    fn update(&self, f: impl FnOnce(&mut FlightState)) {
+        f(&mut self.state.lock().unwrap());
+        self.updates.send_modify(|version| *version += 1);
+    }
+}
+
+impl Coalescer {
+    pub fn new(paths: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            paths: paths.into_iter().collect(),
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.paths.contains(path)
+    }
+
+    /// Number of upstream calls currently shared
+    #[allow(dead_code)]
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+
+    /// Join the in-flight call for `key`, or start it with `call` when there is none.
+    ///
+    /// The call runs on its own task so it completes for the remaining waiters
+    /// even if the client that started it goes away.
+    pub async fn run<F>(self: &Arc<Self>, key: String, call: F) -> ProxyResult<ProxiedResponse>
+    where
+        F: Future<Output = ProxyResult<ProxiedResponse>> + Send + 'static,
+    {
+        let (flight, updates) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(flight) => {
+                    debug!("Joining in-flight request {}", key);
+                    (flight.clone(), flight.updates.subscribe())
+                }
+                None => {
+                    let (sender, receiver) = watch::channel(0);
+                    let flight = Arc::new(Flight {
+                        state: Mutex::new(FlightState::default()),
+                        updates: sender,
+                    });
+                    flights.insert(key.clone(), flight.clone());
+                    tokio::spawn(drive(self.clone(), key, flight.clone(), call));
+                    (flight, receiver)
+                }
+            }
+        };
+
+        wait_for_head(flight, updates).await
+    }
+
+    fn finish(&self, key: &str, flight: &Arc<Flight>) {
+        let mut flights = self.flights.lock().unwrap();
+        if flights.get(key).is_some_and(|f| Arc::ptr_eq(f, flight)) {
+            flights.remove(key);
+        }
+    }
+}
+
+/// Identity of a request for coalescing: the same hash the response cache uses, plus the
+/// forwarded headers, so requests billed to another organization or project never share a call
+pub fn request_key(
+    method: &Method,
+    path: &str,
+    body: &Bytes,
+    model: &str,
+    forwarded: &reqwest::header::HeaderMap,
+) -> Option<String> {
+    let mut request: Value = serde_json::from_slice(body).ok()?;
+    request["model"] = Value::String(model.to_string());
+    Some(format!(
+        "{}\n{}",
+        request_hash(method, path, &request),
+        header_fingerprint(forwarded)
+    ))
+}
+
+async fn drive<F>(coalescer: Arc<Coalescer>, key: String, flight: Arc<Flight>, call: F)
+where
+    F: Future<Output = ProxyResult<ProxiedResponse>>,
+{
+    // Releases the waiters even if the call panics or the runtime shuts down
+    let _guard = FlightGuard {
+        coalescer,
+        key,
+        flight: flight.clone(),
+    };
+
+    match call.await {
+        Ok(response) => {
+            flight.update(|state| state.head = Some(Ok((response.status, response.headers))));
+            let mut body = response.body;
+            let end = loop {
+                match body.next().await {
+                    Some(Ok(chunk)) => flight.update(|state| state.chunks.push(chunk)),
+                    Some(Err(e)) => break FlightEnd::Failed(e.to_string()),
+                    None => break FlightEnd::Completed,
+                }
+            };
+            flight.update(|state| state.end = Some(end));
+        }
+        Err(e) => flight.update(|state| state.head = Some(Err(Arc::new(e)))),
+    }
+}
+
+struct FlightGuard {
+    coalescer: Arc<Coalescer>,
+    key: String,
+    flight: Arc<Flight>,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        // Later requests start a fresh call; current waiters keep reading the buffer
+        self.coalescer.finish(&self.key, &self.flight);
+        self.flight.update(|state| {
+            if state.head.is_none() {
+                let e = ProxyError::internal("Coalesced request was abandoned");
+                state.head = Some(Err(Arc::new(e)));
+            } else if state.end.is_none() {
+                state.end = Some(FlightEnd::Failed("Coalesced request was abandoned".into()));
+            }
+        });
+    }
+}
+
+async fn wait_for_head(
+    flight: Arc<Flight>,
+    mut updates: watch::Receiver<u64>,
+) -> ProxyResult<ProxiedResponse> {
+    loop {
+        let head = flight.state.lock().unwrap().head.clone();
+        match head {
+            Some(Ok((status, headers))) => {
+                return Ok(ProxiedResponse {
+                    status,
+                    headers,
+                    body: shared_body(flight, updates),
+                })
+            }
+            Some(Err(e)) => return Err(ProxyError::Shared(e)),
+            None => {
+                if updates.changed().await.is_err() {
+                    return Err(ProxyError::internal("Coalesced request was abandoned"));
+                }
+            }
+        }
+    }
+}
+
+/// Replay the flight's body from the start, then follow it as it grows
+fn shared_body(flight: Arc<Flight>, updates: watch::Receiver<u64>) -> BodyStream {
+    futures::stream::unfold(
+        (flight, updates, 0usize, false),
+        |(flight, mut updates, index, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                let next = {
+                    let state = flight.state.lock().unwrap();
+                    match (state.chunks.get(index), &state.end) {
+                        (Some(chunk), _) => Some(Ok(chunk.clone())),
+                        (None, Some(FlightEnd::Completed)) => return None,
+                        (None, Some(FlightEnd::Failed(message))) => {
+                            Some(Err(std::io::Error::other(message.clone())))
+                        }
+                        (None, None) => None,
+                    }
+                };
+                match next {
+                    Some(Ok(chunk)) => {
+                        return Some((Ok(chunk), (flight, updates, index + 1, false)))
+                    }
+                    Some(Err(e)) => return Some((Err(e), (flight, updates, index, true))),
+                    None => {
+                        if updates.changed().await.is_err() {
+                            let e = std::io::Error::other("Coalesced request was abandoned");
+                            return Some((Err(e), (flight, updates, index, true)));
+                        }
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::stream::collect;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn slow_stream(parts: &[&'static str]) -> BodyStream {
+        let parts: Vec<_> = parts.to_vec();
+        futures::stream::iter(parts)
+            .then(|part| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok(Bytes::from_static(part.as_bytes()))
+            })
+            .boxed()
+    }
+
+    #[test]
+    fn test_request_key_includes_forwarded_headers() {
+        let body = Bytes::from_static(br#"{"model": "m", "input": "x"}"#);
+        let key = |organization: &'static str, request_id: &'static str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert("openai-organization", organization.parse().unwrap());
+            headers.insert("x-request-id", request_id.parse().unwrap());
+            request_key(&Method::POST, "/v1/embeddings", &body, "m", &headers).unwrap()
+        };
+        assert_eq!(key("org-a", "1"), key("org-a", "2"));
+        assert_ne!(key("org-a", "1"), key("org-b", "1"));
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_one_call() {
+        let coalescer = Arc::new(Coalescer::new(["/v1/embeddings".to_string()]));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let waiters = (0..5).map(|_| {
+            let coalescer = coalescer.clone();
+            let calls = calls.clone();
+            async move {
+                let response = coalescer
+                    .run("same".to_string(), async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        Ok(ProxiedResponse {
+                            status: StatusCode::OK,
+                            headers: HeaderMap::new(),
+                            body: slow_stream(&["data: a\n\n", "data: b\n\n"]),
+                        })
+                    })
+                    .await
+                    .unwrap();
+                collect(response.body).await.unwrap()
+            }
+        });
+        let bodies = futures::future::join_all(waiters).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(bodies.iter().all(|b| b == "data: a\n\ndata: b\n\n"));
+        assert_eq!(coalescer.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_errors_fan_out_to_waiters() {
+        let coalescer = Arc::new(Coalescer::new(Vec::new()));
+        let first = coalescer.run("k".to_string(), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Err(ProxyError::NoKeyAvailable {
+                model: "gpt-4".to_string(),
+            })
+        });
+        let second = coalescer.run("k".to_string(), async { unreachable!() });
+
+        let (first, second) = tokio::join!(first, second);
+        for result in [first, second] {
+            let err = result.err().unwrap();
+            assert!(matches!(err, ProxyError::Shared(_)));
+            assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
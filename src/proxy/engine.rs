@@ -1,38 +1,236 @@
+use crate::config::{ApiKeyInfo, StickyTarget};
 use crate::proxy::{
+    access_log::{AccessEntry, AccessLog},
+    audit::AuditLog,
+    batch::{self, EmbeddingBatcher},
+    cache::{CacheDirective, ResponseCache, CACHE_HEADER},
+    coalesce::{self, Coalescer},
     error::{ProxyError, ProxyResult},
-    key_pool::KeyPool,
+    headers::HeaderPolicy,
+    health::AttemptOutcome,
+    key_pool::{Affinity, KeyPool},
+    models::{ModelAliases, ModelFallbacks},
+    provider,
+    stream::{BodyStream, ProxiedResponse, StreamObserver, StreamOutcome},
+    transform::Transforms,
     upstream::{should_rotate_key, UpstreamClient},
+    validate,
 };
+use crate::telemetry;
 use crate::types::OpenAIRequest;
-use crate::util::{
-    convert_axum_headers_to_reqwest, convert_axum_method_to_reqwest,
-    convert_reqwest_headers_to_axum,
-};
+use crate::util::convert_axum_method_to_reqwest;
+use arc_swap::ArcSwap;
 use axum::body::Body;
-use axum::http::{HeaderMap, Method, StatusCode};
+use axum::http::{header::CONTENT_LENGTH, HeaderMap, HeaderValue, Method, StatusCode};
 use axum::response::Response;
 use bytes::Bytes;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio_stream::StreamExt;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
 #[derive(Debug, Clone)]
 pub struct ProxyEngine {
     key_pool: Arc<KeyPool>,
     upstream_client: UpstreamClient,
     max_retries: u32,
+    model_aliases: Arc<ArcSwap<ModelAliases>>,
+    model_fallbacks: Arc<ArcSwap<ModelFallbacks>>,
+    cache: Option<Arc<ResponseCache>>,
+    coalescer: Option<Arc<Coalescer>>,
+    sticky: Option<StickyRouting>,
+    batcher: Option<Arc<EmbeddingBatcher>>,
+    header_policy: Arc<HeaderPolicy>,
+    transforms: Option<Arc<Transforms>>,
+    validate: bool,
+    attempts_header: bool,
+    access_log: Option<Arc<AccessLog>>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+/// Where sticky sessions are read from and what they are pinned to
+#[derive(Debug, Clone)]
+struct StickyRouting {
+    header: String,
+    target: StickyTarget,
 }
 
+/// Response header naming the model that actually served the request
+pub const SERVED_MODEL_HEADER: &str = "x-kcp-model";
+
+/// Response header listing the key id and status of every upstream attempt
+pub const ATTEMPTS_HEADER: &str = "x-kcp-attempts";
+
+/// One upstream call made for a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attempt {
+    pub key_id: String,
+    /// `None` when no response came back
+    pub status: Option<u16>,
+}
+
+/// Upstream calls made for one request, in order; shared with the work it hands off
+#[derive(Debug, Clone, Default)]
+pub struct Attempts(Arc<Mutex<Vec<Attempt>>>);
+
+impl Attempts {
+    fn push(&self, key_id: String, status: Option<u16>) {
+        self.0.lock().unwrap().push(Attempt { key_id, status });
+    }
+
+    pub fn to_vec(&self) -> Vec<Attempt> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// `key_id=status` of every attempt, `error` when no response came back
+    fn summary(&self) -> String {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|attempt| match attempt.status {
+                Some(status) => format!("{}={}", attempt.key_id, status),
+                None => format!("{}=error", attempt.key_id),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A request on its way upstream, after parsing, alias resolution and transforms
+#[derive(Debug, Clone)]
+struct Outbound {
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: Bytes,
+    requested_model: String,
+    model: String,
+    attempts: Attempts,
+    /// The body merges several callers' embeddings inputs
+    merged: bool,
+}
+
+/// Request id header, accepted from the client or generated by the router
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Response header carrying the upstream's own `x-request-id`
+pub const UPSTREAM_REQUEST_ID_HEADER: &str = "x-upstream-request-id";
+
 impl ProxyEngine {
     pub fn new(key_pool: Arc<KeyPool>, upstream_client: UpstreamClient, max_retries: u32) -> Self {
         Self {
             key_pool,
             upstream_client,
             max_retries,
+            model_aliases: Arc::new(ArcSwap::from_pointee(ModelAliases::default())),
+            model_fallbacks: Arc::new(ArcSwap::from_pointee(ModelFallbacks::default())),
+            cache: None,
+            coalescer: None,
+            sticky: None,
+            batcher: None,
+            header_policy: Arc::new(HeaderPolicy::default()),
+            transforms: None,
+            validate: false,
+            attempts_header: false,
+            access_log: None,
+            audit: None,
         }
     }
 
+    /// Resolve requested models through the given alias table
+    pub fn with_model_aliases(self, aliases: ModelAliases) -> Self {
+        self.model_aliases.store(Arc::new(aliases));
+        self
+    }
+
+    /// Swap in a new alias table; in-flight requests keep the table they started with
+    pub fn reload_model_aliases(&self, aliases: ModelAliases) {
+        info!("Reloaded {} model aliases", aliases.len());
+        self.model_aliases.store(Arc::new(aliases));
+    }
+
+    /// Try the given fallback models when no key can serve the requested one
+    pub fn with_model_fallbacks(self, fallbacks: ModelFallbacks) -> Self {
+        self.model_fallbacks.store(Arc::new(fallbacks));
+        self
+    }
+
+    /// Swap in new fallback chains
+    pub fn reload_model_fallbacks(&self, fallbacks: ModelFallbacks) {
+        info!("Reloaded {} model fallback chains", fallbacks.len());
+        self.model_fallbacks.store(Arc::new(fallbacks));
+    }
+
+    /// Serve repeated deterministic requests from the given cache
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Share one upstream call between identical concurrent requests on the coalescer's paths
+    pub fn with_coalescer(mut self, coalescer: Coalescer) -> Self {
+        self.coalescer = Some(Arc::new(coalescer));
+        self
+    }
+
+    /// Keep each session on one key (or url) while it stays healthy
+    pub fn with_sticky_routing(mut self, header: impl Into<String>, target: StickyTarget) -> Self {
+        self.sticky = Some(StickyRouting {
+            header: header.into().to_ascii_lowercase(),
+            target,
+        });
+        self
+    }
+
+    /// Merge concurrent embeddings requests into batched upstream calls
+    pub fn with_batcher(mut self, batcher: EmbeddingBatcher) -> Self {
+        self.batcher = Some(Arc::new(batcher));
+        self
+    }
+
+    /// Filter headers between client and upstream with the given policy
+    pub fn with_header_policy(mut self, policy: HeaderPolicy) -> Self {
+        self.header_policy = Arc::new(policy);
+        self
+    }
+
+    /// Rewrite request and response bodies with the given transforms
+    pub fn with_transforms(mut self, transforms: Transforms) -> Self {
+        self.transforms = Some(Arc::new(transforms));
+        self
+    }
+
+    /// Reject malformed requests to the main endpoints before they reach a key
+    pub fn with_validation(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    /// Report every upstream attempt in the `x-kcp-attempts` response header
+    pub fn with_attempts_header(mut self) -> Self {
+        self.attempts_header = true;
+        self
+    }
+
+    /// Log one structured line per finished request
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Record sampled request and response bodies to the audit log
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+
     /// Process a proxy request with automatic key rotation and retry logic
+    #[tracing::instrument(
+        name = "proxy_request",
+        skip_all,
+        fields(request_id = request_id(&headers), path = %path)
+    )]
     pub async fn proxy_request(
         &self,
         method: Method,
@@ -40,97 +238,449 @@ impl ProxyEngine {
         headers: HeaderMap,
         body: Bytes,
     ) -> ProxyResult<Response<Body>> {
+        let mut access = self
+            .access_log
+            .as_ref()
+            .map(|log| log.begin(&method, &path, &headers));
+        let audit = self
+            .audit
+            .as_ref()
+            .and_then(|audit| audit.begin(&method, &path, &headers, &body));
+
+        let attempts = Attempts::default();
+        let result = self
+            .handle(method, path, headers, body, &mut access, &attempts)
+            .await;
+        if let Some(access) = &mut access {
+            access.set_attempts(&attempts.to_vec());
+        }
+        match result {
+            Ok(mut response) => {
+                if let Some(access) = access {
+                    response = access.observe(response);
+                }
+                if let Some(audit) = audit {
+                    response = audit.observe(response);
+                }
+                Ok(response.into_response())
+            }
+            Err(error) => {
+                if let Some(access) = access {
+                    access.log_error(&error);
+                }
+                if let Some(audit) = audit {
+                    audit.log_error(&error);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    async fn handle(
+        &self,
+        method: Method,
+        path: String,
+        headers: HeaderMap,
+        body: Bytes,
+        access: &mut Option<AccessEntry>,
+        attempts: &Attempts,
+    ) -> ProxyResult<ProxiedResponse> {
         debug!("Processing {} request to {}", method, path);
 
         // Parse request to extract model if it's a JSON body
-        let model = if method == Method::POST && !body.is_empty() {
+        let requested_model = if method == Method::POST && !body.is_empty() {
             self.extract_model_from_body(&body)?
         } else {
             "others".to_string() // Default for non-POST requests
         };
 
-        debug!("Extracted model: {}", model);
+        debug!("Extracted model: {}", requested_model);
+        if let Some(access) = access {
+            access.set_model(&requested_model);
+        }
+
+        if self.validate {
+            validate::validate_request(&path, &body)?;
+        }
+
+        // Resolve aliases, then walk the fallback chain for the resolved model
+        let model = self
+            .model_aliases
+            .load()
+            .resolve(&requested_model)
+            .to_string();
+
+        // Apply request transforms before anything is keyed on the body
+        let mut body = body;
+        let mut response_transform = None;
+        if let Some(transforms) = &self.transforms {
+            if let Some(transformed) =
+                transforms.transform_request(&path, &model, &headers, &body)?
+            {
+                debug!("Applied request transforms to {}", path);
+                body = transformed;
+            }
+            response_transform = transforms.response_transform(&path, &model, &headers);
+        }
+
+        let response = self
+            .respond(Outbound {
+                method,
+                path,
+                headers,
+                body,
+                requested_model,
+                model,
+                attempts: attempts.clone(),
+                merged: false,
+            })
+            .await?;
+        Ok(match response_transform {
+            Some(transform) => transform.apply(response),
+            None => response,
+        })
+    }
+
+    /// Serve from the cache where it applies, otherwise forward upstream
+    async fn respond(&self, request: Outbound) -> ProxyResult<ProxiedResponse> {
+        let cached = self.cache.as_ref().and_then(|cache| {
+            cache
+                .prepare(
+                    &request.method,
+                    &request.path,
+                    &request.headers,
+                    &request.body,
+                    &request.model,
+                )
+                .map(|cache_request| (cache, cache_request))
+        });
+        let Some((cache, cache_request)) = cached else {
+            return self.forward(request).await;
+        };
+
+        if cache_request.directive == CacheDirective::Use {
+            if let Some(entry) = cache.get(&cache_request.key).await {
+                debug!("Serving {} from cache", request.path);
+                let mut response = entry.replay();
+                // Nothing was sent upstream for this request
+                response.headers.remove(ATTEMPTS_HEADER);
+                return Ok(with_cache_status(response, "hit"));
+            }
+        }
+
+        let response = self.forward(request).await?;
+        Ok(match cache_request.directive {
+            CacheDirective::Bypass => with_cache_status(response, "bypass"),
+            CacheDirective::Use | CacheDirective::Refresh => {
+                with_cache_status(cache.record(cache_request.key, response), "miss")
+            }
+        })
+    }
+
+    /// Forward upstream, joining an identical in-flight request where coalescing applies
+    async fn forward(&self, request: Outbound) -> ProxyResult<ProxiedResponse> {
+        let shared = self
+            .coalescer
+            .as_ref()
+            .filter(|coalescer| coalescer.applies_to(&request.path))
+            .and_then(|coalescer| {
+                coalesce::request_key(
+                    &request.method,
+                    &request.path,
+                    &request.body,
+                    &request.model,
+                    &self.header_policy.filter_request(&request.headers),
+                )
+                .map(|key| (coalescer, key))
+            });
+        let Some((coalescer, key)) = shared else {
+            return self.dispatch(request).await;
+        };
+
+        // Only the request whose call is shared records attempts
+        let engine = self.clone();
+        coalescer
+            .run(key, async move { engine.dispatch(request).await })
+            .await
+    }
+
+    /// Send upstream, merging embeddings requests into batches when enabled
+    async fn dispatch(&self, request: Outbound) -> ProxyResult<ProxiedResponse> {
+        let Some(batcher) = self
+            .batcher
+            .as_ref()
+            .filter(|_| request.method == Method::POST && request.path == batch::EMBEDDINGS_PATH)
+        else {
+            return self.forward_with_fallbacks(&request).await;
+        };
+
+        let engine = self.clone();
+        let send: batch::Dispatch = {
+            let request = request.clone();
+            Arc::new(move |body, merged| {
+                let engine = engine.clone();
+                let request = Outbound {
+                    body,
+                    merged,
+                    ..request.clone()
+                };
+                Box::pin(async move { engine.forward_with_fallbacks(&request).await })
+            })
+        };
+        let forwarded = self.header_policy.filter_request(&request.headers);
+        match batcher.submit(&request.body, &request.model, &forwarded, send) {
+            Some(reply) => reply
+                .await
+                .unwrap_or_else(|_| Err(ProxyError::internal("Embeddings batch was dropped"))),
+            None => self.forward_with_fallbacks(&request).await,
+        }
+    }
+
+    /// Forward to the first model in the fallback chain that has a working key
+    async fn forward_with_fallbacks(&self, request: &Outbound) -> ProxyResult<ProxiedResponse> {
+        let Outbound {
+            method,
+            path,
+            headers,
+            body,
+            requested_model,
+            model,
+            attempts,
+            merged,
+        } = request;
+        let candidates = self.model_fallbacks.load().chain(model);
+        let session = self.session_id(headers, body);
+        let affinity = self
+            .sticky
+            .as_ref()
+            .zip(session.as_deref())
+            .map(|(sticky, session)| Affinity {
+                session,
+                target: sticky.target,
+            });
+        let mut upstream_headers = self.header_policy.filter_request(headers);
+        if let Some(id) = headers.get(REQUEST_ID_HEADER) {
+            if let Ok(value) = reqwest::header::HeaderValue::from_bytes(id.as_bytes()) {
+                upstream_headers.insert(REQUEST_ID_HEADER, value);
+            }
+        }
 
-        // Attempt the request with retries
-        let mut attempt_count = 0;
         let mut last_error = None;
-        let mut use_next_key = false;
-
-        while attempt_count <= self.max_retries {
-            // Get appropriate API key for the model
-            // On first attempt, use model-specific key; on retries, rotate through all keys
-            let key_info = if use_next_key {
-                match self.key_pool.get_next_key() {
-                    Some(key) => key,
-                    None => {
-                        return Err(ProxyError::NoKeyAvailable {
-                            model: model.clone(),
-                        })
-                    }
-                }
+        for (position, candidate) in candidates.iter().enumerate() {
+            if position > 0 {
+                warn!("Falling back from model '{}' to '{}'", model, candidate);
+            }
+
+            // Rewrite the forwarded body to match the model actually being served
+            let candidate_body = if candidate != requested_model {
+                debug!("Rewriting model {} -> {}", requested_model, candidate);
+                rewrite_model_in_body(body, candidate)?
             } else {
-                match self.key_pool.get_key_for_model(&model) {
-                    Some(key) => key,
-                    None => {
-                        return Err(ProxyError::NoKeyAvailable {
-                            model: model.clone(),
-                        })
-                    }
-                }
+                body.clone()
             };
 
-            info!(
-                "Forwarding to {} with API key (redacted) - attempt {}",
-                key_info.url,
-                attempt_count + 1
-            );
-
-            // Make the upstream request
-            match self
-                .upstream_client
-                .forward_request(
-                    convert_axum_method_to_reqwest(&method),
-                    key_info.clone(),
-                    &path,
-                    Some(body.clone()),
-                    Some(convert_axum_headers_to_reqwest(&headers)),
-                )
-                .await
-            {
-                Ok(response) => {
-                    let status = response.status();
-                    debug!("Received response from upstream. Status: {}", status);
-
-                    // Check if we should rotate the key due to the response
-                    if should_rotate_key(status) {
-                        warn!(
-                            "Error from upstream ({}). Changing API key and retrying.",
-                            status
+            // Retries stay on keys that serve this model, never repeating a key
+            let mut tried: Vec<Arc<ApiKeyInfo>> = Vec::new();
+            while tried.len() <= self.max_retries as usize {
+                let selected = info_span!("select_key", model = %candidate, key_id = field::Empty)
+                    .in_scope(|| {
+                        let key_info = self.key_pool.select_key(candidate, &tried, affinity)?;
+                        Span::current().record("key_id", key_info.key_id());
+                        Some(key_info)
+                    });
+                let Some(key_info) = selected else {
+                    break;
+                };
+                tried.push(key_info.clone());
+
+                // The key may forward the routed model under a name of its own
+                let (served_model, body) = match key_info.aliases.get(candidate.as_str()) {
+                    Some(target) if target != candidate => {
+                        debug!("Key alias rewrites model {} -> {}", candidate, target);
+                        (target.as_str(), rewrite_model_in_body(body, target)?)
+                    }
+                    _ => (candidate.as_str(), candidate_body.clone()),
+                };
+
+                info!(
+                    "Forwarding to {} with API key (redacted) - attempt {}",
+                    key_info.url,
+                    tried.len()
+                );
+
+                // Make the upstream request, as a child span of its own in the caller's trace
+                let attempt_span = info_span!(
+                    "upstream_attempt",
+                    key_id = %key_info.key_id(),
+                    model = %candidate,
+                    attempt = tried.len(),
+                    status = field::Empty,
+                    retry_reason = field::Empty,
+                );
+                let mut attempt_headers = upstream_headers.clone();
+                attempt_span.in_scope(|| telemetry::inject_trace_context(&mut attempt_headers));
+                let in_flight = self.key_pool.begin_request(&key_info);
+                match self
+                    .upstream_client
+                    .forward_request(
+                        convert_axum_method_to_reqwest(method),
+                        key_info.clone(),
+                        path,
+                        Some(body.clone()),
+                        Some(attempt_headers),
+                    )
+                    .instrument(attempt_span.clone())
+                    .await
+                {
+                    Ok((response, started)) => {
+                        let status = response.status();
+                        attempt_span.record("status", status.as_u16());
+                        debug!(
+                            "Received response from upstream. Status: {}, upstream request id: {}",
+                            status,
+                            upstream_request_id(response.headers()).unwrap_or("-")
                         );
-                        attempt_count += 1;
-                        use_next_key = true; // Switch to using get_next_key for retries
+                        attempts.push(key_info.key_id(), Some(status.as_u16()));
+                        if let Some(outcome) = AttemptOutcome::from_response(&response) {
+                            self.key_pool.record_outcome(&key_info, outcome);
+                        }
+
+                        // Check if we should rotate the key due to the response; a merged
+                        // batch's 400 is about its inputs, which the batcher retries one by one
+                        let batch_rejected = *merged && status == reqwest::StatusCode::BAD_REQUEST;
+                        if should_rotate_key(status) && !batch_rejected {
+                            let reason = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                                "rate_limited"
+                            } else {
+                                "upstream_status"
+                            };
+                            attempt_span.record("retry_reason", reason);
+                            warn!(
+                                "Error from upstream ({}, upstream request id: {}). Changing API key and retrying.",
+                                status,
+                                upstream_request_id(response.headers()).unwrap_or("-")
+                            );
+                            // Kept whole so the client sees the real error if no key succeeds
+                            let status = StatusCode::from_u16(status.as_u16())
+                                .unwrap_or(StatusCode::BAD_GATEWAY);
+                            let mut headers = self.response_headers(response.headers());
+                            headers.remove(CONTENT_LENGTH);
+                            last_error = Some(match response.bytes().await {
+                                Ok(body) => ProxyError::UpstreamStatus {
+                                    status,
+                                    headers: Box::new(headers),
+                                    body,
+                                },
+                                Err(e) => {
+                                    warn!("Failed to read upstream error body: {}", e);
+                                    ProxyError::UpstreamFailed { source: e }
+                                }
+                            });
+                            continue;
+                        }
 
-                        last_error = Some(ProxyError::UpstreamFailed {
-                            source: response.error_for_status_ref().unwrap_err(),
+                        // Success - convert reqwest::Response to a streamed response
+                        let key_info_id = key_info.key_id();
+                        let mut response = self.convert_response(response, &key_info, &body)?;
+                        if let Ok(value) = HeaderValue::from_str(served_model) {
+                            response.headers.insert(SERVED_MODEL_HEADER, value);
+                        }
+                        self.insert_attempts_header(&mut response.headers, attempts);
+                        if status.is_success() {
+                            self.key_pool.record_first_byte(
+                                &key_info,
+                                candidate,
+                                started.elapsed(),
+                            );
+                            response = response.tap(LatencyRecorder {
+                                key_pool: self.key_pool.clone(),
+                                key_info,
+                                model: candidate.clone(),
+                                started,
+                            });
+                        }
+                        if let Some(in_flight) = in_flight {
+                            response = response.tap(in_flight);
+                        }
+                        // Open until the last byte reaches the client or it goes away
+                        response = response.tap(StreamSpan {
+                            span: info_span!(
+                                "stream",
+                                key_id = %key_info_id,
+                                status = status.as_u16(),
+                                outcome = field::Empty,
+                            ),
                         });
-                        continue;
+                        return Ok(response);
+                    }
+                    Err(e) => {
+                        let reason = match &e {
+                            ProxyError::Timeout => "timeout",
+                            ProxyError::UpstreamFailed { source } if source.is_timeout() => {
+                                "timeout"
+                            }
+                            // The key's provider cannot express the request; another may
+                            ProxyError::InvalidRequest { .. }
+                            | ProxyError::UnsupportedOperation { .. } => "unsupported",
+                            _ => "transport_error",
+                        };
+                        attempt_span.record("retry_reason", reason);
+                        attempts.push(key_info.key_id(), None);
+                        if reason == "unsupported" {
+                            warn!("Key cannot serve this request: {}", e);
+                        } else {
+                            error!("Error sending request to upstream: {}", e);
+                            self.key_pool
+                                .record_outcome(&key_info, AttemptOutcome::Failure);
+                        }
+                        last_error = Some(e);
                     }
-
-                    // Success - convert reqwest::Response to axum::Response
-                    return self.convert_response(response).await;
-                }
-                Err(e) => {
-                    error!("Error sending request to upstream: {}", e);
-                    last_error = Some(e);
-                    attempt_count += 1;
-                    use_next_key = true; // Switch to using get_next_key for retries
                 }
             }
         }
 
-        // All retries exhausted
-        error!("All API keys have been tried for model '{}'", model);
-        Err(last_error.unwrap_or(ProxyError::AllRetriesExhausted))
+        // Every key of every model in the chain has been tried
+        error!(
+            "All API keys have been tried for model '{}': {}",
+            model,
+            attempts.summary()
+        );
+        let mut error = last_error.unwrap_or(ProxyError::NoKeyAvailable {
+            model: model.clone(),
+        });
+        if let ProxyError::UpstreamStatus { headers, .. } = &mut error {
+            self.insert_attempts_header(headers, attempts);
+        }
+        Err(error)
+    }
+
+    /// Upstream headers returned to the client, with the upstream's `x-request-id` renamed
+    /// so the client gets the proxy's own id in that header
+    fn response_headers(&self, upstream: &reqwest::header::HeaderMap) -> HeaderMap {
+        let mut headers = self.header_policy.filter_response(upstream);
+        if let Some(id) = headers.remove(REQUEST_ID_HEADER) {
+            headers.insert(UPSTREAM_REQUEST_ID_HEADER, id);
+        }
+        headers
+    }
+
+    fn insert_attempts_header(&self, headers: &mut HeaderMap, attempts: &Attempts) {
+        if !self.attempts_header {
+            return;
+        }
+        if let Ok(value) = HeaderValue::from_str(&attempts.summary()) {
+            headers.insert(ATTEMPTS_HEADER, value);
+        }
+    }
+
+    /// Session id for sticky routing: the configured header, else the body `user` field
+    fn session_id(&self, headers: &HeaderMap, body: &Bytes) -> Option<String> {
+        let sticky = self.sticky.as_ref()?;
+        if let Some(session) = headers.get(&sticky.header).and_then(|v| v.to_str().ok()) {
+            return Some(session.to_string());
+        }
+        let request: OpenAIRequest = serde_json::from_slice(body).ok()?;
+        request.other.get("user")?.as_str().map(String::from)
     }
 
     /// Extract model from request body for routing decisions
@@ -139,34 +689,108 @@ impl ProxyEngine {
         Ok(request.model)
     }
 
-    /// Convert reqwest::Response to axum::Response
-    async fn convert_response(&self, response: reqwest::Response) -> ProxyResult<Response<Body>> {
+    /// Convert reqwest::Response into a response whose body is still streaming
+    fn convert_response(
+        &self,
+        response: reqwest::Response,
+        key_info: &ApiKeyInfo,
+        request_body: &Bytes,
+    ) -> ProxyResult<ProxiedResponse> {
         let status = StatusCode::from_u16(response.status().as_u16())
             .map_err(|e| ProxyError::internal(format!("Invalid status code: {}", e)))?;
-
-        let mut builder = Response::builder().status(status);
+        let translate = status.is_success() && provider::translates_response(key_info);
 
         // Copy headers from upstream response
-        let reqwest_headers = response.headers();
-        let axum_headers = convert_reqwest_headers_to_axum(reqwest_headers);
-        for (name, value) in &axum_headers {
-            builder = builder.header(name, value);
+        let mut headers = self.response_headers(response.headers());
+        if translate {
+            // A translated body no longer matches the upstream length
+            headers.remove(CONTENT_LENGTH);
         }
 
         // Handle streaming response body
-        let body_stream = response.bytes_stream();
-        let body = Body::from_stream(body_stream.map(|chunk| chunk.map_err(std::io::Error::other)));
+        let mut body: BodyStream = Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(std::io::Error::other)),
+        );
+        if translate {
+            body = provider::translate_response(key_info, request_body, body);
+        }
+
+        Ok(ProxiedResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+fn request_id(headers: &HeaderMap) -> &str {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+}
+
+/// The upstream's id for the request, quoted in support tickets
+fn upstream_request_id(headers: &reqwest::header::HeaderMap) -> Option<&str> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .or_else(|| headers.get("openai-request-id"))
+        .and_then(|v| v.to_str().ok())
+}
+
+/// Span covering the response body stream, closed when the stream ends
+struct StreamSpan {
+    span: Span,
+}
+
+impl StreamObserver for StreamSpan {
+    fn on_end(&mut self, outcome: StreamOutcome) {
+        let outcome = match outcome {
+            StreamOutcome::Completed => "completed",
+            StreamOutcome::Failed => "failed",
+            StreamOutcome::Aborted => "aborted",
+        };
+        self.span.record("outcome", outcome);
+    }
+}
+
+/// Feeds the key's total-time average once the body has been fully streamed
+struct LatencyRecorder {
+    key_pool: Arc<KeyPool>,
+    key_info: Arc<ApiKeyInfo>,
+    model: String,
+    started: Instant,
+}
 
-        builder
-            .body(body)
-            .map_err(|e| ProxyError::internal(format!("Failed to build response: {}", e)))
+impl StreamObserver for LatencyRecorder {
+    fn on_end(&mut self, outcome: StreamOutcome) {
+        if outcome == StreamOutcome::Completed {
+            self.key_pool
+                .record_total(&self.key_info, &self.model, self.started.elapsed());
+        }
     }
 }
 
+fn with_cache_status(mut response: ProxiedResponse, status: &'static str) -> ProxiedResponse {
+    response
+        .headers
+        .insert(CACHE_HEADER, HeaderValue::from_static(status));
+    response
+}
+
+/// Replace the `model` field of a JSON request body
+fn rewrite_model_in_body(body: &Bytes, model: &str) -> ProxyResult<Bytes> {
+    let mut request: OpenAIRequest = serde_json::from_slice(body)?;
+    request.model = model.to_string();
+    Ok(Bytes::from(serde_json::to_vec(&request)?))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{ApiKeyInfo, UpstreamConfig};
+    use crate::config::UpstreamConfig;
     use secrecy::SecretString;
 
     fn create_test_key(id: &str, models: Vec<&str>) -> ApiKeyInfo {
@@ -176,6 +800,7 @@ mod tests {
             models: models.into_iter().map(String::from).collect(),
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         }
     }
 
@@ -198,4 +823,44 @@ mod tests {
         let result = engine.extract_model_from_body(&invalid_body);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_model_alias_rewrites_body() {
+        let keys = vec![create_test_key("1", vec!["gpt-4o-2024-08-06"])];
+        let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+        let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+        let engine = ProxyEngine::new(key_pool, upstream_client, 3).with_model_aliases(
+            ModelAliases::new(std::collections::HashMap::from([(
+                "gpt-4".to_string(),
+                "gpt-4o-2024-08-06".to_string(),
+            )])),
+        );
+        assert_eq!(
+            engine.model_aliases.load().resolve("gpt-4"),
+            "gpt-4o-2024-08-06"
+        );
+
+        let body = Bytes::from(r#"{"model": "gpt-4", "temperature": 0}"#);
+        let rewritten = rewrite_model_in_body(&body, "gpt-4o-2024-08-06").unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(parsed["model"], "gpt-4o-2024-08-06");
+        assert_eq!(parsed["temperature"], 0);
+
+        engine.reload_model_aliases(ModelAliases::default());
+        assert_eq!(engine.model_aliases.load().resolve("gpt-4"), "gpt-4");
+    }
+
+    #[test]
+    fn test_attempts_summary() {
+        let attempts = Attempts::default();
+        attempts.push("...ey-1".to_string(), Some(429));
+        attempts.push("...ey-2".to_string(), None);
+        attempts.clone().push("...ey-3".to_string(), Some(200));
+
+        assert_eq!(attempts.to_vec().len(), 3);
+        assert_eq!(
+            attempts.summary(),
+            "...ey-1=429, ...ey-2=error, ...ey-3=200"
+        );
+    }
 }
@@ -1,7 +1,10 @@
-use crate::types::ErrorResponse;
-use axum::http::StatusCode;
+use crate::types::{OpenAIError, OpenAIErrorDetails};
+use axum::body::Body;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use bytes::Bytes;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,6 +25,14 @@ pub enum ProxyError {
         source: reqwest::Error,
     },
 
+    /// Error response of the last upstream attempt, returned to the client as is
+    #[error("Upstream returned {status}")]
+    UpstreamStatus {
+        status: StatusCode,
+        headers: Box<HeaderMap>,
+        body: Bytes,
+    },
+
     #[error("Request timeout")]
     Timeout,
 
@@ -34,6 +45,13 @@ pub enum ProxyError {
         source: serde_json::Error,
     },
 
+    #[error("{message}")]
+    InvalidRequest {
+        message: String,
+        /// The offending request field
+        param: Option<String>,
+    },
+
     #[error("Request body too large")]
     PayloadTooLarge,
 
@@ -43,6 +61,16 @@ pub enum ProxyError {
     #[error("All retries exhausted")]
     AllRetriesExhausted,
 
+    #[error("{path} is not supported by the {provider} provider")]
+    UnsupportedOperation { provider: String, path: String },
+
+    #[error("Server overloaded: {reason}")]
+    Overloaded { reason: String },
+
+    /// Failure of an upstream call shared by several coalesced requests
+    #[error("{0}")]
+    Shared(Arc<ProxyError>),
+
     #[error("Internal server error: {message}")]
     Internal { message: String },
 }
@@ -67,26 +95,163 @@ impl ProxyError {
                     StatusCode::BAD_GATEWAY
                 }
             }
+            ProxyError::UpstreamStatus { status, .. } => *status,
             ProxyError::Timeout => StatusCode::GATEWAY_TIMEOUT,
             ProxyError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
             ProxyError::InvalidJson { .. } => StatusCode::BAD_REQUEST,
+            ProxyError::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
             ProxyError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             ProxyError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
             ProxyError::AllRetriesExhausted => StatusCode::BAD_GATEWAY,
+            ProxyError::UnsupportedOperation { .. } => StatusCode::NOT_IMPLEMENTED,
+            ProxyError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::Shared(source) => source.status_code(),
             ProxyError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    /// OpenAI error `type`, as the official SDKs classify errors
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            ProxyError::InvalidJson { .. }
+            | ProxyError::InvalidRequest { .. }
+            | ProxyError::PayloadTooLarge
+            | ProxyError::MethodNotAllowed
+            | ProxyError::UnsupportedOperation { .. } => "invalid_request_error",
+            ProxyError::RateLimited => "rate_limit_error",
+            ProxyError::Shared(source) => source.error_type(),
+            _ => "server_error",
+        }
+    }
+
+    /// Stable machine-readable `code`; every proxy-generated error is prefixed `kcp_`
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProxyError::NoKeyAvailable { .. } => "kcp_no_key_available",
+            ProxyError::NoKeyFound => "kcp_no_key_found",
+            ProxyError::InvalidApiKey => "kcp_invalid_api_key",
+            ProxyError::UpstreamFailed { source } if source.is_timeout() => "kcp_upstream_timeout",
+            ProxyError::UpstreamFailed { .. } => "kcp_upstream_unreachable",
+            ProxyError::UpstreamStatus { .. } => "kcp_upstream_error",
+            ProxyError::Timeout => "kcp_timeout",
+            ProxyError::RateLimited => "kcp_rate_limited",
+            ProxyError::InvalidJson { .. } => "kcp_invalid_json",
+            ProxyError::InvalidRequest { .. } => "kcp_invalid_request",
+            ProxyError::PayloadTooLarge => "kcp_payload_too_large",
+            ProxyError::MethodNotAllowed => "kcp_method_not_allowed",
+            ProxyError::AllRetriesExhausted => "kcp_retries_exhausted",
+            ProxyError::UnsupportedOperation { .. } => "kcp_unsupported_operation",
+            ProxyError::Overloaded { .. } => "kcp_overloaded",
+            ProxyError::Shared(source) => source.code(),
+            ProxyError::Internal { .. } => "kcp_internal_error",
+        }
+    }
+
+    /// The offending request field, when the error is about one
+    fn param(&self) -> Option<String> {
+        match self {
+            ProxyError::InvalidRequest { param, .. } => param.clone(),
+            ProxyError::Shared(source) => source.param(),
+            _ => None,
+        }
+    }
+
+    /// The upstream's own error response, when this error carries one
+    pub(crate) fn upstream_response(&self) -> Option<(StatusCode, &HeaderMap, &Bytes)> {
+        match self {
+            ProxyError::UpstreamStatus {
+                status,
+                headers,
+                body,
+            } => Some((*status, headers, body)),
+            ProxyError::Shared(source) => source.upstream_response(),
+            _ => None,
+        }
+    }
+
+    /// The error in the OpenAI error envelope
+    pub fn to_openai_error(&self) -> OpenAIError {
+        OpenAIError {
+            error: OpenAIErrorDetails {
+                message: self.to_string(),
+                error_type: Some(self.error_type().to_string()),
+                param: self.param(),
+                code: Some(self.code().to_string()),
+            },
+        }
+    }
 }
 
 impl IntoResponse for ProxyError {
     fn into_response(self) -> Response {
-        let status = self.status_code();
-        let error_response = ErrorResponse::new(self.to_string());
+        // The upstream already answered in its own format; pass it through untouched
+        if let Some((status, headers, body)) = self.upstream_response() {
+            tracing::warn!("Returning upstream error response (status: {})", status);
+            let mut response = Response::new(Body::from(body.clone()));
+            *response.status_mut() = status;
+            *response.headers_mut() = headers.clone();
+            return response;
+        }
 
-        tracing::error!("Proxy error: {} (status: {})", self, status);
+        let status = self.status_code();
+        if status.is_client_error() {
+            tracing::debug!("Rejected request: {} (status: {})", self, status);
+        } else {
+            tracing::error!("Proxy error: {} (status: {})", self, status);
+        }
 
-        (status, Json(error_response)).into_response()
+        (status, Json(self.to_openai_error())).into_response()
     }
 }
 
 pub type ProxyResult<T> = Result<T, ProxyError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_openai_envelope() {
+        let error = ProxyError::Shared(Arc::new(ProxyError::NoKeyAvailable {
+            model: "gpt-4o".to_string(),
+        }));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["type"], "server_error");
+        assert_eq!(body["error"]["code"], "kcp_no_key_available");
+        assert_eq!(
+            body["error"]["message"],
+            "No API key available for model 'gpt-4o'"
+        );
+        assert!(body["error"]["param"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_passes_through() {
+        let upstream = r#"{"error":{"message":"This model's maximum context length is 8192 tokens","type":"invalid_request_error","param":"messages","code":"context_length_exceeded"}}"#;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", "req-1".parse().unwrap());
+        let error = ProxyError::UpstreamStatus {
+            status: StatusCode::BAD_REQUEST,
+            headers: Box::new(headers),
+            body: Bytes::from_static(upstream.as_bytes()),
+        };
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-request-id"], "req-1");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, upstream.as_bytes());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::proxy::{engine::ProxyEngine, error::ProxyResult};
+use crate::proxy::{admission::AdmissionQueue, engine::ProxyEngine, error::ProxyResult};
 use axum::{
     body::Body,
     extract::{Path, Request, State},
@@ -12,11 +12,41 @@ use tracing::{debug, error};
 #[derive(Debug, Clone)]
 pub struct ProxyHandler {
     engine: Arc<ProxyEngine>,
+    admission: Option<Arc<AdmissionQueue>>,
 }
 
 impl ProxyHandler {
     pub fn new(engine: Arc<ProxyEngine>) -> Self {
-        Self { engine }
+        Self {
+            engine,
+            admission: None,
+        }
+    }
+
+    /// Queue requests by priority class once the admission queue is at capacity
+    pub fn with_admission(mut self, admission: AdmissionQueue) -> Self {
+        self.admission = Some(Arc::new(admission));
+        self
+    }
+
+    /// Forward to the engine once admitted, holding the slot while the body streams
+    async fn admit_and_proxy(
+        &self,
+        method: Method,
+        path: String,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> ProxyResult<Response<Body>> {
+        let Some(admission) = &self.admission else {
+            return self.engine.proxy_request(method, path, headers, body).await;
+        };
+
+        let permit = admission.admit(&headers).await?;
+        let response = self
+            .engine
+            .proxy_request(method, path, headers, body)
+            .await?;
+        Ok(permit.hold_until_sent(response))
     }
 
     /// Handle all OpenAI API requests
@@ -39,10 +69,7 @@ impl ProxyHandler {
         let path = uri.path().to_string();
 
         // Forward the request to the proxy engine
-        handler
-            .engine
-            .proxy_request(method, path, headers, body)
-            .await
+        handler.admit_and_proxy(method, path, headers, body).await
     }
 
     /// Handle requests with path parameters (for /v1/* routes)
@@ -65,8 +92,7 @@ impl ProxyHandler {
 
         // Forward the request to the proxy engine
         handler
-            .engine
-            .proxy_request(method, full_path, headers, body)
+            .admit_and_proxy(method, full_path, headers, body)
             .await
     }
 
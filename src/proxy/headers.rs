@@ -0,0 +1,226 @@
+use crate::config::HeadersConfig;
+use crate::proxy::cache::CACHE_HEADER;
+use crate::util::{convert_axum_headers_to_reqwest, convert_reqwest_headers_to_axum};
+
+/// Connection-specific headers that are never forwarded (RFC 9110 section 7.6.1)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Client framing and credentials; the upstream request gets its own
+const CLIENT_ONLY: &[&str] = &[
+    "host",
+    "content-length",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "api-key",
+    "x-api-key",
+    "x-goog-api-key",
+];
+
+/// Header names let through in one direction.
+///
+/// Entries are lowercase names, or prefixes ending in `*`. An empty allow list lets every
+/// header through; the deny list always wins.
+#[derive(Debug, Clone, Default)]
+struct HeaderFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl HeaderFilter {
+    fn new(allow: &[String], deny: &[String]) -> Self {
+        let lowercase = |names: &[String]| names.iter().map(|n| n.to_ascii_lowercase()).collect();
+        Self {
+            allow: lowercase(allow),
+            deny: lowercase(deny),
+        }
+    }
+
+    fn permits(&self, name: &str) -> bool {
+        let listed = |entries: &[String]| {
+            entries.iter().any(|entry| match entry.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => entry == name,
+            })
+        };
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+}
+
+/// Which headers cross the proxy between client and upstream.
+///
+/// Hop-by-hop headers, and any header the `Connection` header names, are always dropped.
+/// Client credentials, cookies, `Host` and `Content-Length` never reach the upstream, so the
+/// pool key cannot be overridden, and neither do the proxy's own control headers. Repeated
+/// headers such as `Set-Cookie` keep every value.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderPolicy {
+    request: HeaderFilter,
+    response: HeaderFilter,
+}
+
+impl HeaderPolicy {
+    pub fn new(config: &HeadersConfig) -> Self {
+        Self {
+            request: HeaderFilter::new(&config.request_allow, &config.request_deny),
+            response: HeaderFilter::new(&config.response_allow, &config.response_deny),
+        }
+    }
+
+    /// Keep the configured client, priority and sticky session headers from the upstream
+    pub fn with_control_headers<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.request
+            .deny
+            .extend(names.into_iter().map(|name| name.to_ascii_lowercase()));
+        self
+    }
+
+    /// Client request headers to send upstream
+    pub fn filter_request(&self, headers: &axum::http::HeaderMap) -> reqwest::header::HeaderMap {
+        let connection = connection_tokens(
+            headers
+                .get_all(axum::http::header::CONNECTION)
+                .iter()
+                .filter_map(|v| v.to_str().ok()),
+        );
+        let mut filtered = headers.clone();
+        retain(&mut filtered, |name| {
+            !is_hop_by_hop(name, &connection)
+                && !CLIENT_ONLY.contains(&name)
+                && name != CACHE_HEADER
+                && self.request.permits(name)
+        });
+        convert_axum_headers_to_reqwest(&filtered)
+    }
+
+    /// Upstream response headers to return to the client
+    pub fn filter_response(&self, headers: &reqwest::header::HeaderMap) -> axum::http::HeaderMap {
+        let mut filtered = convert_reqwest_headers_to_axum(headers);
+        let connection = connection_tokens(
+            filtered
+                .get_all(axum::http::header::CONNECTION)
+                .iter()
+                .filter_map(|v| v.to_str().ok()),
+        );
+        retain(&mut filtered, |name| {
+            !is_hop_by_hop(name, &connection) && self.response.permits(name)
+        });
+        filtered
+    }
+}
+
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    HOP_BY_HOP.contains(&name) || connection.iter().any(|token| token == name)
+}
+
+/// Header names listed in `Connection`, lowercased
+fn connection_tokens<'a>(values: impl Iterator<Item = &'a str>) -> Vec<String> {
+    values
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Drop every value of the headers whose name fails `keep`
+fn retain(headers: &mut axum::http::HeaderMap, keep: impl Fn(&str) -> bool) {
+    let dropped: Vec<_> = headers
+        .keys()
+        .filter(|name| !keep(name.as_str()))
+        .cloned()
+        .collect();
+    for name in dropped {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    fn request_headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_request_strips_hop_by_hop_and_credentials() {
+        let headers = request_headers(&[
+            ("host", "proxy.local"),
+            ("content-length", "42"),
+            ("connection", "keep-alive, x-trace-hop"),
+            ("x-trace-hop", "1"),
+            ("authorization", "Bearer client-token"),
+            ("cookie", "session=abc"),
+            ("openai-beta", "assistants=v2"),
+            ("x-stainless-lang", "python"),
+        ]);
+
+        let filtered = HeaderPolicy::default().filter_request(&headers);
+        let mut names: Vec<_> = filtered.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["openai-beta", "x-stainless-lang"]);
+    }
+
+    #[test]
+    fn test_allow_and_deny_lists() {
+        let policy = HeaderPolicy::new(&HeadersConfig {
+            request_allow: vec!["OpenAI-*".to_string(), "x-stainless-lang".to_string()],
+            request_deny: vec!["openai-organization".to_string()],
+            ..Default::default()
+        });
+        let headers = request_headers(&[
+            ("openai-beta", "assistants=v2"),
+            ("openai-organization", "org-client"),
+            ("x-stainless-lang", "python"),
+            ("user-agent", "curl"),
+        ]);
+
+        let filtered = policy.filter_request(&headers);
+        let mut names: Vec<_> = filtered.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["openai-beta", "x-stainless-lang"]);
+    }
+
+    #[test]
+    fn test_response_keeps_repeated_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.append("set-cookie", "a=1".parse().unwrap());
+        headers.append("set-cookie", "b=2".parse().unwrap());
+        headers.append("transfer-encoding", "chunked".parse().unwrap());
+        headers.append("x-request-id", "req-1".parse().unwrap());
+
+        let filtered = HeaderPolicy::default().filter_response(&headers);
+        let cookies: Vec<_> = filtered.get_all("set-cookie").iter().collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+        assert!(!filtered.contains_key("transfer-encoding"));
+        assert_eq!(filtered["x-request-id"], "req-1");
+    }
+
+    #[test]
+    fn test_control_headers_stay_with_the_proxy() {
+        let policy = HeaderPolicy::default().with_control_headers(["X-Team", "x-kcp-priority"]);
+        let headers = request_headers(&[
+            ("x-team", "search"),
+            ("x-kcp-priority", "batch"),
+            ("x-kcp-cache", "bypass"),
+            ("openai-beta", "assistants=v2"),
+        ]);
+
+        let filtered = policy.filter_request(&headers);
+        let names: Vec<_> = filtered.keys().map(|n| n.as_str()).collect();
+        assert_eq!(names, ["openai-beta"]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::config::KeysConfig;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// When keys are taken out of rotation and for how long
+#[derive(Debug, Clone)]
+pub struct HealthPolicy {
+    /// Consecutive failures that mark a key unhealthy
+    pub unhealthy_after: u32,
+    pub unhealthy_cooldown: Duration,
+    /// Cooldown after a 429 without `Retry-After`
+    pub rate_limit_cooldown: Duration,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self::from(&KeysConfig::default())
+    }
+}
+
+impl From<&KeysConfig> for HealthPolicy {
+    fn from(config: &KeysConfig) -> Self {
+        Self {
+            unhealthy_after: config.unhealthy_penalty.max(1),
+            unhealthy_cooldown: Duration::from_millis(config.unhealthy_cooldown_ms),
+            rate_limit_cooldown: Duration::from_millis(config.rate_limit_cooldown_ms),
+        }
+    }
+}
+
+/// Outcome of one upstream attempt, as far as the key's health is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptOutcome {
+    Success,
+    Failure,
+    /// The key itself was refused (401/403): out of rotation straight away
+    Rejected,
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+}
+
+impl AttemptOutcome {
+    /// How an upstream response reflects on the key; `None` when it says nothing
+    pub fn from_response(response: &reqwest::Response) -> Option<Self> {
+        let status = response.status();
+        match status.as_u16() {
+            429 => {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse().ok())
+                    .map(Duration::from_secs);
+                Some(AttemptOutcome::RateLimited { retry_after })
+            }
+            401 | 403 => Some(AttemptOutcome::Rejected),
+            418 => Some(AttemptOutcome::Failure),
+            // Usually about the request rather than the key
+            400..=499 => None,
+            500..=599 => Some(AttemptOutcome::Failure),
+            _ => Some(AttemptOutcome::Success),
+        }
+    }
+}
+
+/// Circuit state of a single key.
+///
+/// After `unhealthy_after` consecutive failures the key is skipped for the cooldown; once it
+/// expires the key is tried again and a single further failure opens it straight back up.
+#[derive(Debug, Default)]
+pub struct KeyHealth {
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    consecutive_failures: u32,
+    unavailable_until: Option<Instant>,
+}
+
+impl KeyHealth {
+    /// Whether the key is neither cooling down after a 429 nor open after failures
+    pub fn is_available(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .unavailable_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    pub fn record(&self, outcome: AttemptOutcome, policy: &HealthPolicy, key_url: &str) {
+        let mut state = self.state.lock().unwrap();
+        match outcome {
+            AttemptOutcome::Success => {
+                state.consecutive_failures = 0;
+                state.unavailable_until = None;
+            }
+            AttemptOutcome::Failure => {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= policy.unhealthy_after {
+                    warn!(
+                        "Key for {} is unhealthy after {} failures; cooling down for {:?}",
+                        key_url, state.consecutive_failures, policy.unhealthy_cooldown
+                    );
+                    state.unavailable_until = Some(Instant::now() + policy.unhealthy_cooldown);
+                }
+            }
+            AttemptOutcome::Rejected => {
+                warn!(
+                    "Key for {} was rejected upstream; cooling down for {:?}",
+                    key_url, policy.unhealthy_cooldown
+                );
+                state.consecutive_failures =
+                    (state.consecutive_failures + 1).max(policy.unhealthy_after);
+                state.unavailable_until = Some(Instant::now() + policy.unhealthy_cooldown);
+            }
+            AttemptOutcome::RateLimited { retry_after } => {
+                let cooldown = retry_after.unwrap_or(policy.rate_limit_cooldown);
+                state.unavailable_until = Some(Instant::now() + cooldown);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> HealthPolicy {
+        HealthPolicy {
+            unhealthy_after: 2,
+            unhealthy_cooldown: Duration::from_millis(20),
+            rate_limit_cooldown: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_circuit_opens_and_half_opens() {
+        let health = KeyHealth::default();
+        let policy = policy();
+
+        health.record(AttemptOutcome::Failure, &policy, "test");
+        assert!(health.is_available());
+        health.record(AttemptOutcome::Failure, &policy, "test");
+        assert!(!health.is_available());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(health.is_available());
+        // Still past the threshold, so one more failure reopens the circuit
+        health.record(AttemptOutcome::Failure, &policy, "test");
+        assert!(!health.is_available());
+
+        health.record(AttemptOutcome::Success, &policy, "test");
+        assert!(health.is_available());
+    }
+
+    #[test]
+    fn test_rejected_key_opens_immediately() {
+        let health = KeyHealth::default();
+        health.record(AttemptOutcome::Rejected, &policy(), "test");
+        assert!(!health.is_available());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(health.is_available());
+        health.record(AttemptOutcome::Failure, &policy(), "test");
+        assert!(!health.is_available());
+    }
+
+    #[test]
+    fn test_rate_limit_honours_retry_after() {
+        let health = KeyHealth::default();
+        health.record(
+            AttemptOutcome::RateLimited {
+                retry_after: Some(Duration::ZERO),
+            },
+            &policy(),
+            "test",
+        );
+        assert!(health.is_available());
+
+        health.record(
+            AttemptOutcome::RateLimited { retry_after: None },
+            &policy(),
+            "test",
+        );
+        assert!(!health.is_available());
+    }
+}
@@ -1,17 +1,69 @@
-use crate::config::ApiKeyInfo;
-use dashmap::DashMap;
+use crate::config::{ApiKeyInfo, StickyTarget};
+use crate::proxy::health::{AttemptOutcome, HealthPolicy, KeyHealth};
+use crate::proxy::latency::{LatencyStats, LatencyTracker};
+use crate::proxy::model_index::{ModelIndex, ModelRoute};
+use crate::proxy::stream::{StreamObserver, StreamOutcome};
+use secrecy::ExposeSecret;
+use std::borrow::Cow;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Keys inspected per `least_outstanding` selection
+const LEAST_OUTSTANDING_SAMPLES: usize = 8;
 
 #[derive(Debug)]
 pub struct KeyPool {
     keys: Vec<Arc<ApiKeyInfo>>,
+    /// Keys per model, each model with its own rotation cursor
+    model_index: ModelIndex,
+    /// Cursor for [`KeyPool::get_next_key`]
     current_index: AtomicUsize,
     strategy: RotationStrategy,
-    latency_cache: DashMap<usize, (Duration, Instant)>,
+    latency: LatencyTracker,
+    /// Indexed like `keys`
+    health: Vec<KeyHealth>,
+    health_policy: HealthPolicy,
+    /// Requests currently being served by each key, indexed like `keys`
+    in_flight: Vec<AtomicUsize>,
+    /// Source of randomness for power-of-two choices
+    sampler: RandomState,
+    /// Stable hashes of each key and its url for sticky sessions, indexed like `keys`
+    identities: Vec<KeyIdentity>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyIdentity {
+    key: u64,
+    url: u64,
+}
+
+/// Counts a request against its key until dropped
+#[derive(Debug)]
+pub struct InFlight {
+    key_pool: Arc<KeyPool>,
+    index: usize,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.key_pool.in_flight[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps the request counted while its response body streams
+impl StreamObserver for InFlight {
+    fn on_end(&mut self, _outcome: StreamOutcome) {}
+}
+
+/// Session affinity for a request: the same session keeps landing on the same target
+#[derive(Debug, Clone, Copy)]
+pub struct Affinity<'a> {
+    pub session: &'a str,
+    pub target: StickyTarget,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +71,12 @@ pub enum RotationStrategy {
     RoundRobin,
     RoundRobinHealthWeighted,
     LeastLatency,
+    /// Lowest `tier` first, smooth weighted round robin by `weight` within it
+    Weighted,
+    /// Fewest requests in flight
+    LeastOutstanding,
+    /// The better of two random keys by latency and requests in flight
+    PowerOfTwoChoices,
 }
 
 impl From<&str> for RotationStrategy {
@@ -27,6 +85,9 @@ impl From<&str> for RotationStrategy {
             "round_robin" => RotationStrategy::RoundRobin,
             "round_robin_health_weighted" => RotationStrategy::RoundRobinHealthWeighted,
             "least_latency" => RotationStrategy::LeastLatency,
+            "weighted" => RotationStrategy::Weighted,
+            "least_outstanding" => RotationStrategy::LeastOutstanding,
+            "power_of_two_choices" => RotationStrategy::PowerOfTwoChoices,
             _ => RotationStrategy::RoundRobinHealthWeighted,
         }
     }
@@ -34,38 +95,176 @@ impl From<&str> for RotationStrategy {
 
 impl KeyPool {
     pub fn new(keys: Vec<ApiKeyInfo>, strategy: &str) -> Self {
-        let keys: Vec<Arc<ApiKeyInfo>> = keys.into_iter().map(Arc::new).collect();
+        let keys: Vec<Arc<ApiKeyInfo>> = keys
+            .into_iter()
+            .enumerate()
+            .map(|(pool_index, key)| Arc::new(ApiKeyInfo { pool_index, ..key }))
+            .collect();
+        let key_count = keys.len();
+        let identities = keys
+            .iter()
+            .map(|key| KeyIdentity {
+                key: stable_hash(&(&key.url, key.key.expose_secret())),
+                url: stable_hash(&key.url),
+            })
+            .collect();
         Self {
+            identities,
+            health: keys.iter().map(|_| KeyHealth::default()).collect(),
+            model_index: ModelIndex::new(&keys),
             keys,
             current_index: AtomicUsize::new(0),
             strategy: RotationStrategy::from(strategy),
-            latency_cache: DashMap::new(),
+            latency: LatencyTracker::new(key_count),
+            health_policy: HealthPolicy::default(),
+            in_flight: (0..key_count).map(|_| AtomicUsize::new(0)).collect(),
+            sampler: RandomState::new(),
+        }
+    }
+
+    /// Take keys out of rotation according to the given policy
+    pub fn with_health_policy(mut self, policy: HealthPolicy) -> Self {
+        self.health_policy = policy;
+        self
+    }
+
+    /// Update a key's health from the outcome of a request sent with it
+    pub fn record_outcome(&self, key: &Arc<ApiKeyInfo>, outcome: AttemptOutcome) {
+        if let Some(index) = self.index_of(key) {
+            self.health[index].record(outcome, &self.health_policy, &key.url);
         }
     }
 
-    /// Get the best available API key for the given model
+    /// Whether a key is currently in rotation
+    #[allow(dead_code)]
+    pub fn is_available(&self, key: &Arc<ApiKeyInfo>) -> bool {
+        self.index_of(key)
+            .is_some_and(|index| self.health[index].is_available())
+    }
+
+    /// Count a request against the key until the returned guard is dropped
+    pub fn begin_request(self: &Arc<Self>, key: &Arc<ApiKeyInfo>) -> Option<InFlight> {
+        let index = self.index_of(key)?;
+        self.in_flight[index].fetch_add(1, Ordering::Relaxed);
+        Some(InFlight {
+            key_pool: self.clone(),
+            index,
+        })
+    }
+
+    /// Requests currently being served by the key
+    #[allow(dead_code)]
+    pub fn in_flight(&self, key: &Arc<ApiKeyInfo>) -> usize {
+        self.index_of(key)
+            .map_or(0, |index| self.in_flight[index].load(Ordering::Relaxed))
+    }
+
+    /// The key's slot in this pool; `None` for keys this pool did not hand out
+    fn index_of(&self, key: &Arc<ApiKeyInfo>) -> Option<usize> {
+        let index = key.pool_index;
+        self.keys
+            .get(index)
+            .filter(|k| Arc::ptr_eq(k, key))
+            .map(|_| index)
+    }
+
+    /// Get the best available API key for the given model.
+    ///
+    /// Only keys from the most specific match tier are rotated: exact names first, then
+    /// patterns, then `others`.
+    #[allow(dead_code)]
     pub fn get_key_for_model(&self, model: &str) -> Option<Arc<ApiKeyInfo>> {
-        let matching_keys: Vec<(usize, &Arc<ApiKeyInfo>)> = self
-            .keys
-            .iter()
-            .enumerate()
-            .filter(|(_, key)| key.supports_model(model))
-            .collect();
+        self.get_key_for_model_excluding(model, &[])
+    }
 
-        if matching_keys.is_empty() {
-            return None;
+    /// Like [`KeyPool::get_key_for_model`], skipping keys that were already tried.
+    ///
+    /// Once every key of a tier is excluded, selection moves on to the next tier.
+    pub fn get_key_for_model_excluding(
+        &self,
+        model: &str,
+        excluded: &[Arc<ApiKeyInfo>],
+    ) -> Option<Arc<ApiKeyInfo>> {
+        self.select_key(model, excluded, None)
+    }
+
+    /// Pick a key for the model, preferring the session's sticky key while it is usable.
+    ///
+    /// The sticky key is chosen by rendezvous hashing over the most specific tier, so a
+    /// session only moves when its key leaves the pool. If that key is excluded or out of
+    /// rotation, selection falls back to the normal strategy.
+    pub fn select_key(
+        &self,
+        model: &str,
+        excluded: &[Arc<ApiKeyInfo>],
+        affinity: Option<Affinity<'_>>,
+    ) -> Option<Arc<ApiKeyInfo>> {
+        let route = self.model_index.route(&self.keys, model);
+        if let Some(affinity) = affinity {
+            if let Some(index) = self.sticky_key(&route, affinity) {
+                let key = &self.keys[index];
+                let tried = excluded.iter().any(|tried| Arc::ptr_eq(tried, key));
+                if !tried && self.health[index].is_available() {
+                    return Some(key.clone());
+                }
+                debug!("Sticky key for session is unavailable; using normal rotation");
+            }
         }
 
-        match self.strategy {
-            RotationStrategy::RoundRobin => self.round_robin_selection(&matching_keys),
-            RotationStrategy::RoundRobinHealthWeighted => {
-                self.health_weighted_selection(&matching_keys)
+        for tier in route.tiers() {
+            let keys: Cow<'_, [usize]> = if excluded.is_empty() {
+                Cow::Borrowed(tier)
+            } else {
+                tier.iter()
+                    .copied()
+                    .filter(|&index| {
+                        !excluded
+                            .iter()
+                            .any(|tried| Arc::ptr_eq(tried, &self.keys[index]))
+                    })
+                    .collect()
+            };
+            if keys.is_empty() {
+                continue;
             }
-            RotationStrategy::LeastLatency => self.least_latency_selection(&matching_keys),
+
+            let index = match self.strategy {
+                RotationStrategy::RoundRobin => Some(round_robin_selection(&route, &keys)),
+                RotationStrategy::RoundRobinHealthWeighted => {
+                    Some(self.health_weighted_selection(&route, &keys))
+                }
+                RotationStrategy::LeastLatency => {
+                    Some(self.least_latency_selection(&route, model, &keys))
+                }
+                RotationStrategy::Weighted => self.weighted_selection(&route, &keys),
+                RotationStrategy::LeastOutstanding => {
+                    Some(self.least_outstanding_selection(&route, &keys))
+                }
+                RotationStrategy::PowerOfTwoChoices => {
+                    Some(self.power_of_two_selection(&route, model, &keys))
+                }
+            };
+            return index.map(|index| self.keys[index].clone());
         }
+        None
+    }
+
+    /// Index of the session's key within the model's most specific tier
+    fn sticky_key(&self, route: &ModelRoute, affinity: Affinity<'_>) -> Option<usize> {
+        let session = stable_hash(affinity.session);
+        route.tiers().first()?.iter().copied().max_by_key(|&index| {
+            let identity = self.identities[index];
+            let key_score = stable_hash(&(session, identity.key));
+            match affinity.target {
+                StickyTarget::Key => (key_score, 0),
+                // Pin the url first; keys of that url are then ranked the same way
+                StickyTarget::Url => (stable_hash(&(session, identity.url)), key_score),
+            }
+        })
     }
 
     /// Get the next key in round-robin fashion
+    #[allow(dead_code)]
     pub fn get_next_key(&self) -> Option<Arc<ApiKeyInfo>> {
         if self.keys.is_empty() {
             return None;
@@ -76,107 +275,218 @@ impl KeyPool {
         Some(self.keys[index].clone())
     }
 
-    /// Update latency measurement for a key
+    /// Record a probe's latency for a key
     pub fn update_latency(&self, key_index: usize, latency: Duration) {
-        self.latency_cache
-            .insert(key_index, (latency, Instant::now()));
+        self.latency.record_probe(key_index, latency);
         debug!("Updated latency for key {}: {:?}", key_index, latency);
     }
 
-    /// Get all keys for health checking
+    /// Response headers arrived from upstream for a request sent with this key
+    pub fn record_first_byte(&self, key: &Arc<ApiKeyInfo>, model: &str, elapsed: Duration) {
+        if let Some(index) = self.index_of(key) {
+            self.latency.record_first_byte(index, model, elapsed);
+        }
+    }
+
+    /// The upstream response body for this key finished streaming
+    pub fn record_total(&self, key: &Arc<ApiKeyInfo>, model: &str, elapsed: Duration) {
+        if let Some(index) = self.index_of(key) {
+            self.latency.record_total(index, model, elapsed);
+        }
+    }
+
+    /// Latency averages for a key, for one model or across all of them
     #[allow(dead_code)]
+    pub fn latency_stats(
+        &self,
+        key: &Arc<ApiKeyInfo>,
+        model: Option<&str>,
+    ) -> Option<LatencyStats> {
+        self.latency.stats(self.index_of(key)?, model)
+    }
+
+    /// Get all keys for health checking
     pub fn get_all_keys(&self) -> &[Arc<ApiKeyInfo>] {
         &self.keys
     }
 
-    /// Measure latency for all keys by making HEAD requests
-    pub async fn update_all_latencies(&self) {
-        info!("Starting latency measurements for {} keys", self.keys.len());
+    /// Whether the key has served no real traffic for `idle_for`
+    pub fn is_idle(&self, key_index: usize, idle_for: Duration) -> bool {
+        self.latency.is_idle(key_index, idle_for)
+    }
 
-        let client = reqwest::Client::new();
-        let mut tasks = vec![];
+    /// Whether the rotation strategy picks keys by latency, so idle keys need measuring
+    pub fn uses_latency(&self) -> bool {
+        matches!(
+            self.strategy,
+            RotationStrategy::LeastLatency | RotationStrategy::PowerOfTwoChoices
+        )
+    }
 
-        for (index, key_info) in self.keys.iter().enumerate() {
-            let client = client.clone();
-            let url = key_info.url.clone();
+    /// Keys in good standing; all of them when none are
+    fn available<'a>(&self, keys: &'a [usize]) -> Cow<'a, [usize]> {
+        let Some(first_down) = keys
+            .iter()
+            .position(|&index| !self.health[index].is_available())
+        else {
+            return Cow::Borrowed(keys);
+        };
+        let healthy: Vec<usize> = keys[..first_down]
+            .iter()
+            .chain(&keys[first_down + 1..])
+            .copied()
+            .filter(|&index| self.health[index].is_available())
+            .collect();
+        if healthy.is_empty() {
+            Cow::Borrowed(keys)
+        } else {
+            Cow::Owned(healthy)
+        }
+    }
 
-            let task = tokio::spawn(async move {
-                let latency = measure_key_latency(&client, &url).await;
-                (index, latency)
-            });
+    /// Next key in the model's rotation, stepping over keys out of rotation
+    fn health_weighted_selection(&self, route: &ModelRoute, keys: &[usize]) -> usize {
+        let start = route.next();
+        (0..keys.len())
+            .map(|offset| keys[(start + offset) % keys.len()])
+            .find(|&index| self.health[index].is_available())
+            .unwrap_or(keys[start % keys.len()])
+    }
 
-            tasks.push(task);
+    /// Smooth weighted round robin over the lowest tier that has a key in good standing
+    fn weighted_selection(&self, route: &ModelRoute, keys: &[usize]) -> Option<usize> {
+        let keys = self.available(keys);
+        let tier = keys.iter().map(|&index| self.keys[index].tier).min()?;
+
+        let mut current_weights = route.current_weights();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for &index in keys.iter().filter(|&&index| self.keys[index].tier == tier) {
+            let weight = i64::from(self.keys[index].weight);
+            let current = current_weights.entry(index).or_default();
+            *current += weight;
+            total += weight;
+            let current = *current;
+            if best.is_none_or(|best| current > current_weights[&best]) {
+                best = Some(index);
+            }
         }
+        let best = best?;
+        *current_weights.get_mut(&best).unwrap() -= total;
+        Some(best)
+    }
 
-        // Wait for all measurements to complete
-        for task in tasks {
-            match task.await {
-                Ok((index, latency)) => {
-                    self.update_latency(index, latency);
-                }
-                Err(e) => {
-                    error!("Failed to measure latency: {}", e);
+    /// Lowest time to first byte for the model among keys in good standing.
+    ///
+    /// Keys without any measurement yet are tried first so every key gets sampled.
+    fn least_latency_selection(&self, route: &ModelRoute, model: &str, keys: &[usize]) -> usize {
+        let keys = self.available(keys);
+
+        let mut unmeasured = Vec::new();
+        let mut best: Option<(Duration, usize)> = None;
+        for &index in keys.iter() {
+            match self.latency.first_byte(index, model) {
+                None => unmeasured.push(index),
+                Some(latency) if best.is_none_or(|(best, _)| latency < best) => {
+                    best = Some((latency, index))
                 }
+                Some(_) => {}
             }
         }
 
-        self.log_latency_summary();
-    }
-
-    fn round_robin_selection(&self, keys: &[(usize, &Arc<ApiKeyInfo>)]) -> Option<Arc<ApiKeyInfo>> {
-        if keys.is_empty() {
-            return None;
+        match best {
+            Some((_, index)) if unmeasured.is_empty() => index,
+            _ => round_robin_selection(route, &unmeasured),
         }
-
-        let current = self.current_index.fetch_add(1, Ordering::SeqCst);
-        let index = current % keys.len();
-        Some(keys[index].1.clone())
     }
 
-    fn health_weighted_selection(
-        &self,
-        keys: &[(usize, &Arc<ApiKeyInfo>)],
-    ) -> Option<Arc<ApiKeyInfo>> {
-        if keys.is_empty() {
-            return None;
+    /// Fewest requests in flight among a sample of keys, preferring keys in good standing.
+    ///
+    /// Up to [`LEAST_OUTSTANDING_SAMPLES`] keys are inspected from a random offset, so the
+    /// cost does not grow with the pool; the scan stops at the first idle key.
+    fn least_outstanding_selection(&self, route: &ModelRoute, keys: &[usize]) -> usize {
+        let start = self.sampler.hash_one(route.next()) as usize;
+        let mut best: Option<(usize, usize)> = None;
+        let mut fallback: Option<(usize, usize)> = None;
+        for offset in 0..keys.len().min(LEAST_OUTSTANDING_SAMPLES) {
+            let index = keys[(start + offset) % keys.len()];
+            let load = self.in_flight[index].load(Ordering::Relaxed);
+            let slot = if self.health[index].is_available() {
+                &mut best
+            } else {
+                &mut fallback
+            };
+            if slot.is_none_or(|(_, best_load)| load < best_load) {
+                *slot = Some((index, load));
+            }
+            if best.is_some_and(|(_, best_load)| best_load == 0) {
+                break;
+            }
         }
-
-        // For now, use round-robin with simple health scoring
-        // TODO: Implement proper health scoring based on recent errors
-        self.round_robin_selection(keys)
+        best.or(fallback).map_or(keys[0], |(index, _)| index)
     }
 
-    fn least_latency_selection(
-        &self,
-        keys: &[(usize, &Arc<ApiKeyInfo>)],
-    ) -> Option<Arc<ApiKeyInfo>> {
-        if keys.is_empty() {
-            return None;
+    /// Power of two random choices: sample two keys and keep the one with the lower expected
+    /// wait, time to first byte times (requests in flight + 1).
+    ///
+    /// Only the two sampled keys are inspected, so the cost does not grow with the pool.
+    fn power_of_two_selection(&self, route: &ModelRoute, model: &str, keys: &[usize]) -> usize {
+        if keys.len() == 1 {
+            return keys[0];
         }
 
-        // Find the key with the lowest cached latency
-        let mut best_key = keys[0].1.clone();
-        let mut best_latency = Duration::from_secs(u64::MAX);
-
-        for (index, key) in keys {
-            if let Some(entry) = self.latency_cache.get(index) {
-                let (latency, _) = entry.value();
-                if *latency < best_latency {
-                    best_latency = *latency;
-                    best_key = (*key).clone();
+        let first_position = self.sample_available(route, keys, None);
+        let first = keys[first_position];
+        let second = keys[self.sample_available(route, keys, Some(first_position))];
+        let load = |index: usize| self.in_flight[index].load(Ordering::Relaxed);
+        let cost = |index: usize| {
+            self.latency
+                .first_byte(index, model)
+                .map(|latency| latency.as_secs_f64() * (load(index) + 1) as f64)
+        };
+
+        match (cost(first), cost(second)) {
+            (Some(a), Some(b)) => {
+                if b < a {
+                    second
+                } else {
+                    first
                 }
             }
+            // Unmeasured keys compete on load alone and win ties so they get sampled
+            (a, _) => match load(first).cmp(&load(second)) {
+                std::cmp::Ordering::Less => first,
+                std::cmp::Ordering::Greater => second,
+                std::cmp::Ordering::Equal if a.is_none() => first,
+                std::cmp::Ordering::Equal => second,
+            },
         }
+    }
 
-        Some(best_key)
+    /// Random position in `keys` other than `other`, redrawing a few times to find a key in
+    /// good standing
+    fn sample_available(&self, route: &ModelRoute, keys: &[usize], other: Option<usize>) -> usize {
+        const DRAWS: usize = 4;
+        let choices = keys.len() - usize::from(other.is_some());
+        let mut position = 0;
+        for _ in 0..DRAWS {
+            let draw = self.sampler.hash_one(route.next()) as usize;
+            position = match other {
+                Some(other) => (other + 1 + draw % choices) % keys.len(),
+                None => draw % choices,
+            };
+            if self.health[keys[position]].is_available() {
+                break;
+            }
+        }
+        position
     }
 
-    fn log_latency_summary(&self) {
+    pub fn log_latency_summary(&self) {
         let mut latencies = vec![];
         for (i, key) in self.keys.iter().enumerate() {
-            if let Some(entry) = self.latency_cache.get(&i) {
-                let (latency, _) = entry.value();
-                latencies.push(format!("{}:{:?}", key.url, latency));
+            if let Some(latency) = self.latency.key_first_byte(i) {
+                latencies.push(format!("{}#{}:{:?}", key.url, i, latency));
             }
         }
         if !latencies.is_empty() {
@@ -185,28 +495,16 @@ impl KeyPool {
     }
 }
 
-async fn measure_key_latency(client: &reqwest::Client, url: &str) -> Duration {
-    let start = Instant::now();
-
-    // Make a HEAD request with timeout
-    let request_timeout = Duration::from_secs(5);
-    let result = timeout(request_timeout, client.head(url).send()).await;
+/// Next key of a non-empty list in the model's rotation
+fn round_robin_selection(route: &ModelRoute, keys: &[usize]) -> usize {
+    keys[route.next() % keys.len()]
+}
 
-    match result {
-        Ok(Ok(_)) => {
-            let duration = start.elapsed();
-            debug!("Latency measurement for {}: {:?}", url, duration);
-            duration
-        }
-        Ok(Err(e)) => {
-            warn!("HTTP error measuring latency for {}: {}", url, e);
-            Duration::from_secs(u64::MAX)
-        }
-        Err(_) => {
-            warn!("Timeout measuring latency for {}", url);
-            Duration::from_secs(u64::MAX)
-        }
-    }
+/// Hash that stays the same across restarts, so sessions keep their keys
+fn stable_hash(value: &(impl Hash + ?Sized)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
 }
 
 #[cfg(test)]
@@ -221,6 +519,7 @@ mod tests {
             models: models.into_iter().map(String::from).collect(),
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         }
     }
 
@@ -277,4 +576,288 @@ mod tests {
         let result = pool.get_key_for_model("claude-1");
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_excluded_keys_fall_through_tiers() {
+        let keys = vec![
+            create_test_key("1", vec!["others"]),
+            create_test_key("2", vec!["gpt-4"]),
+            create_test_key("3", vec!["gpt-3.5-turbo"]),
+        ];
+
+        let pool = KeyPool::new(keys, "round_robin");
+
+        let first = pool.get_key_for_model_excluding("gpt-4", &[]).unwrap();
+        assert!(first.url.contains("api-2"));
+
+        let second = pool
+            .get_key_for_model_excluding("gpt-4", std::slice::from_ref(&first))
+            .unwrap();
+        assert!(second.url.contains("api-1"));
+
+        assert!(pool
+            .get_key_for_model_excluding("gpt-4", &[first, second])
+            .is_none());
+    }
+
+    #[test]
+    fn test_specific_keys_preferred_over_others() {
+        let keys = vec![
+            create_test_key("1", vec!["others"]),
+            create_test_key("2", vec!["gpt-4o*"]),
+            create_test_key("3", vec!["gpt-4o-mini"]),
+        ];
+
+        let pool = KeyPool::new(keys, "round_robin");
+
+        for _ in 0..4 {
+            let key = pool.get_key_for_model("gpt-4o-mini").unwrap();
+            assert!(key.url.contains("api-3"));
+
+            let key = pool.get_key_for_model("gpt-4o-2024-08-06").unwrap();
+            assert!(key.url.contains("api-2"));
+
+            let key = pool.get_key_for_model("gpt-3.5-turbo").unwrap();
+            assert!(key.url.contains("api-1"));
+        }
+    }
+
+    #[test]
+    fn test_rotation_is_per_model() {
+        let keys = vec![
+            create_test_key("1", vec!["gpt-4", "gpt-3.5-turbo"]),
+            create_test_key("2", vec!["gpt-4", "gpt-3.5-turbo"]),
+            create_test_key("3", vec!["others"]),
+            create_test_key("4", vec!["others"]),
+        ];
+
+        let pool = KeyPool::new(keys, "round_robin");
+
+        // Interleaved traffic for other models does not skew gpt-4's rotation
+        let mut gpt4 = Vec::new();
+        for _ in 0..4 {
+            gpt4.push(pool.get_key_for_model("gpt-4").unwrap().url.clone());
+            pool.get_key_for_model("gpt-3.5-turbo").unwrap();
+            pool.get_key_for_model("some-random-model").unwrap();
+        }
+        assert_eq!(
+            gpt4,
+            [
+                "https://api-1.example.com",
+                "https://api-2.example.com",
+                "https://api-1.example.com",
+                "https://api-2.example.com"
+            ]
+        );
+
+        // Unlisted models rotate over `others` only
+        let a = pool.get_key_for_model("model-a").unwrap();
+        let b = pool.get_key_for_model("model-b").unwrap();
+        assert_ne!(a.url, b.url);
+        assert!(a.url.contains("api-3") || a.url.contains("api-4"));
+    }
+
+    #[test]
+    fn test_sticky_session_is_stable_until_key_unavailable() {
+        let keys = (1..=4)
+            .map(|i| create_test_key(&i.to_string(), vec!["gpt-4"]))
+            .collect();
+        let pool = KeyPool::new(keys, "round_robin").with_health_policy(HealthPolicy {
+            unhealthy_after: 1,
+            ..Default::default()
+        });
+        let affinity = Affinity {
+            session: "conversation-42",
+            target: StickyTarget::Key,
+        };
+
+        let sticky = pool.select_key("gpt-4", &[], Some(affinity)).unwrap();
+        for _ in 0..8 {
+            let key = pool.select_key("gpt-4", &[], Some(affinity)).unwrap();
+            assert!(Arc::ptr_eq(&key, &sticky));
+        }
+
+        // Other sessions spread over the keys
+        let spread: std::collections::HashSet<_> = (0..64)
+            .map(|i| {
+                let session = format!("session-{}", i);
+                let affinity = Affinity {
+                    session: &session,
+                    target: StickyTarget::Key,
+                };
+                pool.select_key("gpt-4", &[], Some(affinity))
+                    .unwrap()
+                    .url
+                    .clone()
+            })
+            .collect();
+        assert!(spread.len() > 1);
+
+        // An unhealthy sticky key falls back to rotation, then comes back once healthy
+        pool.record_outcome(&sticky, AttemptOutcome::Failure);
+        assert!(!pool.is_available(&sticky));
+        let fallback = pool.select_key("gpt-4", &[], Some(affinity)).unwrap();
+        assert!(!Arc::ptr_eq(&fallback, &sticky));
+
+        pool.record_outcome(&sticky, AttemptOutcome::Success);
+        let key = pool.select_key("gpt-4", &[], Some(affinity)).unwrap();
+        assert!(Arc::ptr_eq(&key, &sticky));
+    }
+
+    #[test]
+    fn test_health_weighted_skips_unavailable_keys() {
+        let keys = vec![
+            create_test_key("1", vec!["gpt-4"]),
+            create_test_key("2", vec!["gpt-4"]),
+        ];
+        let pool = KeyPool::new(keys, "round_robin_health_weighted");
+        let limited = pool.get_key_for_model("gpt-4").unwrap();
+        pool.record_outcome(&limited, AttemptOutcome::RateLimited { retry_after: None });
+
+        for _ in 0..4 {
+            let key = pool.get_key_for_model("gpt-4").unwrap();
+            assert!(!Arc::ptr_eq(&key, &limited));
+        }
+    }
+
+    #[test]
+    fn test_weighted_tiers_and_shares() {
+        let mut key_a = create_test_key("a", vec!["gpt-4"]);
+        key_a.weight = 7;
+        let mut key_b = create_test_key("b", vec!["gpt-4"]);
+        key_b.weight = 3;
+        let mut payg = create_test_key("payg", vec!["gpt-4"]);
+        payg.tier = 1;
+
+        let pool = KeyPool::new(vec![key_a, key_b, payg], "weighted");
+        let mut counts = std::collections::HashMap::new();
+        let mut sequence = Vec::new();
+        for _ in 0..10 {
+            let key = pool.get_key_for_model("gpt-4").unwrap();
+            sequence.push(key.url.clone());
+            *counts.entry(key.url.clone()).or_insert(0) += 1;
+        }
+        assert_eq!(counts["https://api-a.example.com"], 7);
+        assert_eq!(counts["https://api-b.example.com"], 3);
+        // Smooth: never more than three picks of "a" in a row
+        assert!(!sequence
+            .windows(4)
+            .any(|w| w.iter().all(|url| url.contains("api-a"))));
+
+        // The next tier only takes traffic once the first one is out of rotation
+        for url in ["a", "b"] {
+            let key = pool
+                .get_all_keys()
+                .iter()
+                .find(|k| k.url.contains(&format!("api-{}.", url)))
+                .unwrap()
+                .clone();
+            pool.record_outcome(&key, AttemptOutcome::RateLimited { retry_after: None });
+        }
+        let key = pool.get_key_for_model("gpt-4").unwrap();
+        assert!(key.url.contains("api-payg"));
+    }
+
+    #[test]
+    fn test_weighted_shares_are_per_model() {
+        let models = vec!["gpt-4", "gpt-4o"];
+        let mut key_a = create_test_key("a", models.clone());
+        key_a.weight = 7;
+        let mut key_b = create_test_key("b", models);
+        key_b.weight = 3;
+
+        // Interleaved traffic on another model does not shift this model's split
+        let pool = KeyPool::new(vec![key_a, key_b], "weighted");
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..10 {
+            for model in ["gpt-4", "gpt-4o"] {
+                let key = pool.get_key_for_model(model).unwrap();
+                *counts.entry((model, key.url.clone())).or_insert(0) += 1;
+            }
+        }
+        for model in ["gpt-4", "gpt-4o"] {
+            assert_eq!(counts[&(model, "https://api-a.example.com".to_string())], 7);
+            assert_eq!(counts[&(model, "https://api-b.example.com".to_string())], 3);
+        }
+    }
+
+    #[test]
+    fn test_least_outstanding_follows_in_flight_requests() {
+        let keys = (1..=3)
+            .map(|i| create_test_key(&i.to_string(), vec!["gpt-4"]))
+            .collect();
+        let pool = Arc::new(KeyPool::new(keys, "least_outstanding"));
+
+        let mut guards = Vec::new();
+        let mut picked = std::collections::HashSet::new();
+        for _ in 0..3 {
+            let key = pool.get_key_for_model("gpt-4").unwrap();
+            picked.insert(key.url.clone());
+            guards.push(pool.begin_request(&key).unwrap());
+        }
+        // Every key got one request before any got a second
+        assert_eq!(picked.len(), 3);
+
+        let busy = pool.get_all_keys()[0].clone();
+        guards.push(pool.begin_request(&busy).unwrap());
+        guards.retain(|guard| guard.index != 1);
+        assert_eq!(pool.in_flight(&busy), 2);
+        let key = pool.get_key_for_model("gpt-4").unwrap();
+        assert!(key.url.contains("api-2"));
+
+        drop(guards);
+        assert_eq!(pool.in_flight(&busy), 0);
+    }
+
+    #[test]
+    fn test_keys_are_found_by_pool_index() {
+        let keys: Vec<ApiKeyInfo> = (0..32)
+            .map(|i| create_test_key(&i.to_string(), vec!["gpt-4"]))
+            .collect();
+        let pool = Arc::new(KeyPool::new(keys.clone(), "least_outstanding"));
+        for (index, key) in pool.get_all_keys().iter().enumerate() {
+            assert_eq!(key.pool_index, index);
+        }
+
+        // A key this pool did not hand out is never mistaken for one of its own
+        let foreign = Arc::new(keys[3].clone());
+        assert!(pool.begin_request(&foreign).is_none());
+        let other = KeyPool::new(keys, "least_outstanding");
+        assert!(pool.begin_request(&other.get_all_keys()[3]).is_none());
+
+        // Every sample holds an idle key, so the busy ones are never picked
+        let _guards: Vec<InFlight> = pool.get_all_keys()[..LEAST_OUTSTANDING_SAMPLES / 2]
+            .iter()
+            .map(|key| pool.begin_request(key).unwrap())
+            .collect();
+        for _ in 0..8 {
+            let key = pool.get_key_for_model("gpt-4").unwrap();
+            assert_eq!(pool.in_flight(&key), 0);
+        }
+    }
+
+    #[test]
+    fn test_power_of_two_choices_avoids_loaded_and_slow_keys() {
+        let keys = vec![
+            create_test_key("1", vec!["gpt-4"]),
+            create_test_key("2", vec!["gpt-4"]),
+        ];
+        let pool = Arc::new(KeyPool::new(keys, "power_of_two_choices"));
+        let all_keys = pool.get_all_keys().to_vec();
+
+        // With two keys both are always sampled, so the comparison decides
+        pool.record_first_byte(&all_keys[0], "gpt-4", Duration::from_millis(100));
+        pool.record_first_byte(&all_keys[1], "gpt-4", Duration::from_millis(300));
+        for _ in 0..8 {
+            let key = pool.get_key_for_model("gpt-4").unwrap();
+            assert!(Arc::ptr_eq(&key, &all_keys[0]));
+        }
+
+        // Three requests in flight make the fast key's expected wait 400ms
+        let _guards: Vec<_> = (0..3)
+            .map(|_| pool.begin_request(&all_keys[0]).unwrap())
+            .collect();
+        let key = pool.get_key_for_model("gpt-4").unwrap();
+        assert!(Arc::ptr_eq(&key, &all_keys[1]));
+    }
 }
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the moving averages
+const ALPHA: f64 = 0.3;
+
+/// Exponentially weighted moving average of a duration
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ewma {
+    seconds: Option<f64>,
+}
+
+impl Ewma {
+    pub fn update(&mut self, sample: Duration) {
+        let sample = sample.as_secs_f64();
+        self.seconds = Some(match self.seconds {
+            Some(current) => current + ALPHA * (sample - current),
+            None => sample,
+        });
+    }
+
+    pub fn get(&self) -> Option<Duration> {
+        self.seconds.map(Duration::from_secs_f64)
+    }
+}
+
+/// Time to first byte and total time, as seen from real traffic
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    pub first_byte: Ewma,
+    pub total: Ewma,
+}
+
+#[derive(Debug, Default)]
+struct KeyLatency {
+    overall: LatencyStats,
+    models: HashMap<String, LatencyStats>,
+    /// Probe round trips, kept apart so they never blend into real first-byte times
+    probe: Ewma,
+    last_traffic: Option<Instant>,
+}
+
+impl KeyLatency {
+    fn key_first_byte(&self) -> Option<Duration> {
+        self.overall.first_byte.get().or_else(|| self.probe.get())
+    }
+}
+
+/// Passive latency measurements per key and per model
+#[derive(Debug)]
+pub struct LatencyTracker {
+    /// Indexed like the key pool
+    keys: Vec<Mutex<KeyLatency>>,
+}
+
+impl LatencyTracker {
+    pub fn new(key_count: usize) -> Self {
+        Self {
+            keys: (0..key_count).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Response headers arrived for a real request
+    pub fn record_first_byte(&self, key: usize, model: &str, elapsed: Duration) {
+        let mut latency = self.keys[key].lock().unwrap();
+        latency.last_traffic = Some(Instant::now());
+        latency.overall.first_byte.update(elapsed);
+        latency
+            .models
+            .entry(model.to_string())
+            .or_default()
+            .first_byte
+            .update(elapsed);
+    }
+
+    /// The response body of a real request finished streaming
+    pub fn record_total(&self, key: usize, model: &str, elapsed: Duration) {
+        let mut latency = self.keys[key].lock().unwrap();
+        latency.overall.total.update(elapsed);
+        latency
+            .models
+            .entry(model.to_string())
+            .or_default()
+            .total
+            .update(elapsed);
+    }
+
+    /// A probe answered; only consulted while the key has no real traffic to go by
+    pub fn record_probe(&self, key: usize, elapsed: Duration) {
+        self.keys[key].lock().unwrap().probe.update(elapsed);
+    }
+
+    /// Time to first byte for the model on this key, else for the key overall,
+    /// else the probe round trip
+    pub fn first_byte(&self, key: usize, model: &str) -> Option<Duration> {
+        let latency = self.keys[key].lock().unwrap();
+        latency
+            .models
+            .get(model)
+            .and_then(|stats| stats.first_byte.get())
+            .or_else(|| latency.key_first_byte())
+    }
+
+    /// Time to first byte for the key overall, else the probe round trip
+    pub fn key_first_byte(&self, key: usize) -> Option<Duration> {
+        self.keys[key].lock().unwrap().key_first_byte()
+    }
+
+    pub fn stats(&self, key: usize, model: Option<&str>) -> Option<LatencyStats> {
+        let latency = self.keys[key].lock().unwrap();
+        match model {
+            Some(model) => latency.models.get(model).cloned(),
+            None => Some(latency.overall.clone()),
+        }
+    }
+
+    /// Whether the key has served no real traffic for `idle_for`
+    pub fn is_idle(&self, key: usize, idle_for: Duration) -> bool {
+        self.keys[key]
+            .lock()
+            .unwrap()
+            .last_traffic
+            .is_none_or(|at| at.elapsed() >= idle_for)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ewma_moves_towards_samples() {
+        let mut ewma = Ewma::default();
+        assert!(ewma.get().is_none());
+        ewma.update(Duration::from_millis(100));
+        assert_eq!(ewma.get(), Some(Duration::from_millis(100)));
+        ewma.update(Duration::from_millis(200));
+        let value = ewma.get().unwrap().as_secs_f64();
+        assert!((value - 0.13).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_model_latency_falls_back_to_key() {
+        let tracker = LatencyTracker::new(2);
+        tracker.record_probe(0, Duration::from_millis(300));
+        assert_eq!(
+            tracker.first_byte(0, "gpt-4"),
+            Some(Duration::from_millis(300))
+        );
+        assert!(tracker.is_idle(0, Duration::from_secs(60)));
+        assert!(tracker.stats(0, None).unwrap().first_byte.get().is_none());
+
+        // Real traffic takes over from the probe, which no longer blends in
+        tracker.record_first_byte(0, "gpt-4o", Duration::from_millis(40));
+        tracker.record_probe(0, Duration::from_millis(300));
+        assert_eq!(
+            tracker.first_byte(0, "gpt-4"),
+            Some(Duration::from_millis(40))
+        );
+
+        tracker.record_first_byte(1, "gpt-4", Duration::from_millis(50));
+        tracker.record_total(1, "gpt-4", Duration::from_millis(900));
+        assert!(!tracker.is_idle(1, Duration::from_secs(60)));
+        let stats = tracker.stats(1, Some("gpt-4")).unwrap();
+        assert_eq!(stats.total.get(), Some(Duration::from_millis(900)));
+        assert!(tracker.stats(1, Some("gpt-4o")).is_none());
+    }
+}
@@ -1,8 +1,24 @@
+pub mod access_log;
+pub mod admission;
+pub mod audit;
+pub mod batch;
+pub mod cache;
+pub mod coalesce;
 pub mod engine;
 pub mod error;
 pub mod handler;
+pub mod headers;
+pub mod health;
 pub mod key_pool;
+pub mod latency;
+pub mod model_index;
+pub mod models;
+pub mod probe;
+pub mod provider;
+pub mod stream;
+pub mod transform;
 pub mod upstream;
+pub mod validate;
 
 pub use engine::ProxyEngine;
 #[allow(unused_imports)]
@@ -0,0 +1,252 @@
+use crate::config::ApiKeyInfo;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// Most unlisted models remembered at once
+const MAX_RESOLVED: usize = 1024;
+
+/// Least recently used share of remembered models dropped once `MAX_RESOLVED` is reached
+const EVICT_BATCH: usize = MAX_RESOLVED / 8;
+
+/// Keys able to serve one model, with that model's own rotation state
+#[derive(Debug, Default)]
+pub struct ModelRoute {
+    /// Key indices grouped by match tier, most specific first; empty tiers are left out
+    tiers: Vec<Vec<usize>>,
+    cursor: AtomicUsize,
+    /// Smooth weighted round-robin state, by key index
+    current_weights: Mutex<HashMap<usize, i64>>,
+}
+
+impl ModelRoute {
+    fn resolve(keys: &[Arc<ApiKeyInfo>], model: &str) -> Self {
+        let mut matches: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(index, key)| Some((key.model_match(model)?, index)))
+            .collect();
+        // Stable, so each tier keeps the configured key order
+        matches.sort_by_key(|&(key_match, _)| std::cmp::Reverse(key_match));
+
+        let mut tiers: Vec<Vec<usize>> = Vec::new();
+        let mut last = None;
+        for (key_match, index) in matches {
+            if last != Some(key_match) {
+                tiers.push(Vec::new());
+                last = Some(key_match);
+            }
+            tiers.last_mut().unwrap().push(index);
+        }
+        Self {
+            tiers,
+            ..Default::default()
+        }
+    }
+
+    /// Key indices per match tier, most specific first
+    pub fn tiers(&self) -> &[Vec<usize>] {
+        &self.tiers
+    }
+
+    /// Advance this model's rotation
+    pub fn next(&self) -> usize {
+        self.cursor.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// This model's weighted round-robin state, by key index
+    pub fn current_weights(&self) -> MutexGuard<'_, HashMap<usize, i64>> {
+        self.current_weights.lock().unwrap()
+    }
+}
+
+/// Model to key lookup, built once per key set.
+///
+/// Models listed by name on any key are resolved up front. Other models go to the keys
+/// listing `others`, unless some key has a glob, regex or exclusion; then they are resolved
+/// on first use and remembered, least recently used first out, so each later request costs
+/// one lookup. Models that end up on the `others` keys share that route's rotation, and a
+/// flood of unknown names only pushes out routes that have not been used lately.
+#[derive(Debug)]
+pub struct ModelIndex {
+    named: HashMap<String, Arc<ModelRoute>>,
+    others: Arc<ModelRoute>,
+    /// Whether unlisted models need matching against patterns
+    has_patterns: bool,
+    resolved: RwLock<HashMap<String, Resolved>>,
+    /// Ticks on every lookup of a remembered model
+    clock: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Resolved {
+    route: Arc<ModelRoute>,
+    used: AtomicU64,
+}
+
+impl ModelIndex {
+    pub fn new(keys: &[Arc<ApiKeyInfo>]) -> Self {
+        let mut named = HashMap::new();
+        for key in keys {
+            for name in key.matcher().exact_names() {
+                if !named.contains_key(name) {
+                    named.insert(name.to_string(), Arc::new(ModelRoute::resolve(keys, name)));
+                }
+            }
+        }
+
+        let others = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| key.matcher().serves_others())
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let others = ModelRoute {
+            tiers: if others.is_empty() {
+                Vec::new()
+            } else {
+                vec![others]
+            },
+            ..Default::default()
+        };
+
+        Self {
+            named,
+            others: Arc::new(others),
+            has_patterns: keys.iter().any(|key| key.matcher().has_patterns()),
+            resolved: RwLock::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Keys serving `model`
+    pub fn route(&self, keys: &[Arc<ApiKeyInfo>], model: &str) -> Arc<ModelRoute> {
+        if let Some(route) = self.named.get(model) {
+            return route.clone();
+        }
+        if !self.has_patterns {
+            return self.others.clone();
+        }
+
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(resolved) = self.resolved.read().unwrap().get(model) {
+            resolved.used.store(now, Ordering::Relaxed);
+            return resolved.route.clone();
+        }
+
+        let route = ModelRoute::resolve(keys, model);
+        let route = if !route.tiers.is_empty() && route.tiers == self.others.tiers {
+            self.others.clone()
+        } else {
+            Arc::new(route)
+        };
+        let mut resolved = self.resolved.write().unwrap();
+        if resolved.len() >= MAX_RESOLVED && !resolved.contains_key(model) {
+            let mut used: Vec<u64> = resolved
+                .values()
+                .map(|r| r.used.load(Ordering::Relaxed))
+                .collect();
+            let (_, &mut cutoff, _) = used.select_nth_unstable(EVICT_BATCH - 1);
+            resolved.retain(|_, r| r.used.load(Ordering::Relaxed) > cutoff);
+        }
+        resolved
+            .entry(model.to_string())
+            .or_insert_with(|| Resolved {
+                route,
+                used: AtomicU64::new(now),
+            })
+            .route
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(models: &[&[&str]]) -> Vec<Arc<ApiKeyInfo>> {
+        models
+            .iter()
+            .map(|models| {
+                Arc::new(ApiKeyInfo {
+                    models: models.iter().map(|m| m.to_string()).collect(),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_named_models_are_tiered() {
+        let keys = keys(&[&["others"], &["gpt-4", "gpt-4o"], &["gpt-4*"], &["gpt-4"]]);
+        let index = ModelIndex::new(&keys);
+
+        assert_eq!(
+            index.route(&keys, "gpt-4").tiers(),
+            &[vec![1, 3], vec![2], vec![0]]
+        );
+        assert_eq!(
+            index.route(&keys, "gpt-4o").tiers(),
+            &[vec![1], vec![2], vec![0]]
+        );
+        // Unlisted models still go through the patterns
+        assert_eq!(
+            index.route(&keys, "gpt-4-turbo").tiers(),
+            &[vec![2], vec![0]]
+        );
+        assert_eq!(index.route(&keys, "claude-2").tiers(), &[vec![0]]);
+    }
+
+    #[test]
+    fn test_unlisted_models_share_others_route() {
+        let keys = keys(&[&["gpt-4"], &["others"], &["others", "gpt-4"]]);
+        let index = ModelIndex::new(&keys);
+
+        let route = index.route(&keys, "claude-2");
+        assert_eq!(route.tiers(), &[vec![1, 2]]);
+        assert!(Arc::ptr_eq(&route, &index.route(&keys, "mistral-large")));
+        // Without patterns nothing needs remembering
+        assert!(index.resolved.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_exclusions_apply_to_unlisted_models() {
+        let keys = keys(&[&["others", "!dall-e*"], &["others"]]);
+        let index = ModelIndex::new(&keys);
+
+        assert_eq!(index.route(&keys, "dall-e-3").tiers(), &[vec![1]]);
+        assert_eq!(index.route(&keys, "gpt-4").tiers(), &[vec![0, 1]]);
+        // Each resolved model keeps its own cursor
+        let route = index.route(&keys, "dall-e-3");
+        route.next();
+        assert_eq!(index.route(&keys, "dall-e-3").next(), 1);
+        assert_eq!(index.route(&keys, "gpt-4").next(), 0);
+    }
+
+    #[test]
+    fn test_unknown_models_keep_hot_routes() {
+        let keys = keys(&[&["gpt-4*"], &["others"]]);
+        let index = ModelIndex::new(&keys);
+
+        let hot = index.route(&keys, "gpt-4-turbo");
+        hot.next();
+        // Unknown names share the `others` route, and only push out routes gone cold
+        for i in 0..MAX_RESOLVED * 2 {
+            let route = index.route(&keys, &format!("junk-{}", i));
+            assert!(Arc::ptr_eq(&route, &index.others));
+            index.route(&keys, "gpt-4-turbo");
+        }
+        assert!(index
+            .resolved
+            .read()
+            .unwrap()
+            .contains_key(&format!("junk-{}", MAX_RESOLVED * 2 - 1)));
+        // Pattern matches beyond the limit push out the least recently used
+        for i in 0..MAX_RESOLVED * 2 {
+            index.route(&keys, &format!("gpt-4-{}", i));
+            index.route(&keys, "gpt-4-turbo");
+        }
+        assert!(index.resolved.read().unwrap().len() <= MAX_RESOLVED);
+        assert_eq!(index.route(&keys, "gpt-4-turbo").next(), 1);
+    }
+}
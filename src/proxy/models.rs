@@ -0,0 +1,265 @@
+use regex::Regex;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Config-driven model alias table, e.g. `gpt-4 -> gpt-4o-2024-08-06`.
+///
+/// Aliases are resolved once, before key selection; they are not chained. A key's own
+/// `aliases` then map the routed model to what is forwarded with that key.
+#[derive(Debug, Clone, Default)]
+pub struct ModelAliases {
+    aliases: HashMap<String, String>,
+}
+
+impl ModelAliases {
+    pub fn new(aliases: HashMap<String, String>) -> Self {
+        Self { aliases }
+    }
+
+    /// Resolve a requested model to the model forwarded upstream
+    pub fn resolve<'a>(&'a self, model: &'a str) -> &'a str {
+        self.aliases.get(model).map(String::as_str).unwrap_or(model)
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
+
+/// Config-driven fallback chains, e.g. `gpt-4o -> [gpt-4o-mini]`.
+///
+/// Fallbacks are tried in order once every key for the previous model has failed or when
+/// no key serves it at all.
+#[derive(Debug, Clone, Default)]
+pub struct ModelFallbacks {
+    chains: HashMap<String, Vec<String>>,
+}
+
+impl ModelFallbacks {
+    pub fn new(chains: HashMap<String, Vec<String>>) -> Self {
+        Self { chains }
+    }
+
+    /// The model itself followed by its fallbacks, without duplicates
+    pub fn chain(&self, model: &str) -> Vec<String> {
+        let mut chain = vec![model.to_string()];
+        for fallback in self.chains.get(model).into_iter().flatten() {
+            if !chain.contains(fallback) {
+                chain.push(fallback.clone());
+            }
+        }
+        chain
+    }
+
+    pub fn len(&self) -> usize {
+        self.chains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+}
+
+/// How specifically a key matched a model; higher variants take precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModelMatch {
+    /// The `others` wildcard
+    Others,
+    /// A glob (`gpt-4o*`) or regex (`re:^o[13]-.*`) pattern
+    Pattern,
+    /// The model is listed by name
+    Exact,
+}
+
+#[derive(Debug, Clone)]
+enum ModelPattern {
+    Exact(String),
+    Pattern(Regex),
+}
+
+impl ModelPattern {
+    fn parse(entry: &str) -> Result<Self, regex::Error> {
+        if let Some(pattern) = entry.strip_prefix("re:") {
+            Regex::new(pattern).map(ModelPattern::Pattern)
+        } else if entry.contains(['*', '?']) {
+            Regex::new(&glob_to_regex(entry)).map(ModelPattern::Pattern)
+        } else {
+            Ok(ModelPattern::Exact(entry.to_string()))
+        }
+    }
+
+    fn matches(&self, model: &str) -> Option<ModelMatch> {
+        match self {
+            ModelPattern::Exact(name) => (name == model).then_some(ModelMatch::Exact),
+            ModelPattern::Pattern(regex) => regex.is_match(model).then_some(ModelMatch::Pattern),
+        }
+    }
+}
+
+/// Compiled form of a key's `models` list.
+///
+/// Entries are exact names, globs (`gpt-4o*`), regexes (`re:^o[13]-.*`) or the `others`
+/// wildcard. Entries prefixed with `!` exclude matching models, even from `others`.
+#[derive(Debug, Clone, Default)]
+pub struct ModelMatcher {
+    include: Vec<ModelPattern>,
+    exclude: Vec<ModelPattern>,
+    others: bool,
+}
+
+impl ModelMatcher {
+    pub fn parse(models: &[String]) -> Result<Self, regex::Error> {
+        let mut matcher = ModelMatcher::default();
+        for entry in models {
+            if entry == "others" {
+                matcher.others = true;
+            } else if let Some(excluded) = entry.strip_prefix('!') {
+                matcher.exclude.push(ModelPattern::parse(excluded)?);
+            } else {
+                matcher.include.push(ModelPattern::parse(entry)?);
+            }
+        }
+        Ok(matcher)
+    }
+
+    /// Like [`ModelMatcher::parse`], but skips invalid patterns with a warning
+    pub fn parse_lossy(models: &[String]) -> Self {
+        let valid: Vec<String> = models
+            .iter()
+            .filter(
+                |entry| match ModelMatcher::parse(std::slice::from_ref(entry)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("Ignoring invalid model pattern '{}': {}", entry, e);
+                        false
+                    }
+                },
+            )
+            .cloned()
+            .collect();
+        ModelMatcher::parse(&valid).unwrap_or_default()
+    }
+
+    /// Models listed by name
+    pub fn exact_names(&self) -> impl Iterator<Item = &str> {
+        self.include.iter().filter_map(|p| match p {
+            ModelPattern::Exact(name) => Some(name.as_str()),
+            ModelPattern::Pattern(_) => None,
+        })
+    }
+
+    /// Whether models not listed by name anywhere still need matching against this list,
+    /// because of a glob, regex or exclusion
+    pub fn has_patterns(&self) -> bool {
+        !self.exclude.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| matches!(p, ModelPattern::Pattern(_)))
+    }
+
+    /// Whether the `others` wildcard is listed
+    pub fn serves_others(&self) -> bool {
+        self.others
+    }
+
+    /// Best match of `model` against this list, or `None` if it is not served
+    pub fn matches(&self, model: &str) -> Option<ModelMatch> {
+        if self.exclude.iter().any(|p| p.matches(model).is_some()) {
+            return None;
+        }
+        self.include
+            .iter()
+            .filter_map(|p| p.matches(model))
+            .max()
+            .or(self.others.then_some(ModelMatch::Others))
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(models: &[&str]) -> ModelMatcher {
+        ModelMatcher::parse(&models.iter().map(|m| m.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let fallbacks = ModelFallbacks::new(HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![
+                "gpt-4o-mini".to_string(),
+                "gpt-4o".to_string(),
+                "gpt-3.5-turbo".to_string(),
+            ],
+        )]));
+
+        assert_eq!(
+            fallbacks.chain("gpt-4o"),
+            vec!["gpt-4o", "gpt-4o-mini", "gpt-3.5-turbo"]
+        );
+        assert_eq!(fallbacks.chain("gpt-4"), vec!["gpt-4"]);
+    }
+
+    #[test]
+    fn test_model_patterns() {
+        let m = matcher(&["gpt-4o", "gpt-4o*", "re:^o[13]-.*", "!gpt-4o-audio*"]);
+
+        assert_eq!(m.matches("gpt-4o"), Some(ModelMatch::Exact));
+        assert_eq!(
+            m.matches("gpt-4o-mini-2024-07-18"),
+            Some(ModelMatch::Pattern)
+        );
+        assert_eq!(m.matches("o1-preview"), Some(ModelMatch::Pattern));
+        assert_eq!(m.matches("o3-mini"), Some(ModelMatch::Pattern));
+        assert_eq!(m.matches("o2-mini"), None);
+        assert_eq!(m.matches("gpt-4o-audio-preview"), None);
+        // Globs are anchored and escape regex metacharacters
+        assert_eq!(m.matches("xgpt-4o"), None);
+        assert_eq!(matcher(&["gpt-3.5*"]).matches("gpt-305"), None);
+    }
+
+    #[test]
+    fn test_others_and_exclusions() {
+        let m = matcher(&["others", "!dall-e*"]);
+        assert_eq!(m.matches("claude-2"), Some(ModelMatch::Others));
+        assert_eq!(m.matches("dall-e-3"), None);
+
+        assert!(ModelMatcher::parse(&["re:(".to_string()]).is_err());
+        let lossy = ModelMatcher::parse_lossy(&["re:(".to_string(), "gpt-4".to_string()]);
+        assert_eq!(lossy.matches("gpt-4"), Some(ModelMatch::Exact));
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        let aliases = ModelAliases::new(HashMap::from([
+            ("gpt-4".to_string(), "gpt-4o-2024-08-06".to_string()),
+            (
+                "gpt-4o-2024-08-06".to_string(),
+                "should-not-chain".to_string(),
+            ),
+        ]));
+
+        assert_eq!(aliases.resolve("gpt-4"), "gpt-4o-2024-08-06");
+        assert_eq!(aliases.resolve("gpt-3.5-turbo"), "gpt-3.5-turbo");
+        assert_eq!(aliases.len(), 2);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config::ProbesConfig;
+use crate::proxy::health::AttemptOutcome;
+use crate::proxy::key_pool::KeyPool;
+use crate::proxy::upstream::UpstreamClient;
+use anyhow::Context;
+use bytes::Bytes;
+use reqwest::Method;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Sends an authenticated request with every key on a timer so dead keys leave the rotation
+/// before user traffic finds them
+pub struct HealthProber {
+    key_pool: Arc<KeyPool>,
+    upstream_client: UpstreamClient,
+    method: Method,
+    path: String,
+    body: Option<Bytes>,
+    interval: Duration,
+    jitter: Duration,
+    timeout: Duration,
+}
+
+impl HealthProber {
+    pub fn new(
+        key_pool: Arc<KeyPool>,
+        upstream_client: UpstreamClient,
+        config: &ProbesConfig,
+    ) -> anyhow::Result<Self> {
+        let method = Method::from_bytes(config.method.to_uppercase().as_bytes())
+            .with_context(|| format!("Invalid probe method: {}", config.method))?;
+        if let Some(body) = &config.body {
+            serde_json::from_str::<serde_json::Value>(body).context("Probe body is not JSON")?;
+        }
+
+        Ok(Self {
+            key_pool,
+            upstream_client,
+            method,
+            path: config.path.clone(),
+            body: config.body.clone().map(Bytes::from),
+            interval: config.interval(),
+            jitter: config.jitter(),
+            timeout: config.timeout(),
+        })
+    }
+
+    /// Run one probe loop per key; the first round is spread over an interval
+    pub fn spawn(self) {
+        let prober = Arc::new(self);
+        for index in 0..prober.key_pool.get_all_keys().len() {
+            let prober = prober.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(random_below(prober.interval)).await;
+                loop {
+                    prober.probe_key(index).await;
+                    tokio::time::sleep(prober.interval + random_below(prober.jitter)).await;
+                }
+            });
+        }
+    }
+
+    /// Once per interval, probe only the keys that served no traffic during it.
+    ///
+    /// Keeps latency-based rotation current for idle keys without probing busy ones, whose
+    /// latency is measured from real traffic.
+    pub fn spawn_idle(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                self.probe_idle_keys().await;
+            }
+        });
+    }
+
+    /// Probe the keys that have seen no traffic for an interval
+    pub async fn probe_idle_keys(&self) {
+        let idle: Vec<usize> = (0..self.key_pool.get_all_keys().len())
+            .filter(|&index| self.key_pool.is_idle(index, self.interval))
+            .collect();
+        if idle.is_empty() {
+            return;
+        }
+        info!("Probing latency of {} idle keys", idle.len());
+        futures::future::join_all(idle.into_iter().map(|index| self.probe_key(index))).await;
+        self.key_pool.log_latency_summary();
+    }
+
+    /// Probe one key and feed the result into its health
+    pub async fn probe_key(&self, index: usize) -> Option<AttemptOutcome> {
+        let key = self.key_pool.get_all_keys()[index].clone();
+        let started = Instant::now();
+        let result = self
+            .upstream_client
+            .probe(
+                self.method.clone(),
+                &key,
+                &self.path,
+                self.body.clone(),
+                self.timeout,
+            )
+            .await;
+
+        let outcome = match result {
+            Ok(response) => {
+                let outcome = AttemptOutcome::from_response(&response);
+                if outcome.is_none() {
+                    // A 404 or 400 points at the probe config, not at the key
+                    warn!(
+                        "Health probe for {} returned {}; check [probes] path and body",
+                        key.url,
+                        response.status()
+                    );
+                }
+                if outcome == Some(AttemptOutcome::Success) {
+                    self.key_pool.update_latency(index, started.elapsed());
+                }
+                outcome
+            }
+            Err(e) => {
+                debug!("Health probe for {} failed: {}", key.url, e);
+                Some(AttemptOutcome::Failure)
+            }
+        };
+
+        if let Some(outcome) = outcome {
+            debug!("Health probe for {}: {:?}", key.url, outcome);
+            self.key_pool.record_outcome(&key, outcome);
+        }
+        outcome
+    }
+}
+
+/// Uniformly random duration in `[0, max)`
+fn random_below(max: Duration) -> Duration {
+    let nanos = max.as_nanos() as u64;
+    if nanos == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(RandomState::new().hash_one(Instant::now()) % nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_below_stays_in_range() {
+        assert_eq!(random_below(Duration::ZERO), Duration::ZERO);
+        for _ in 0..100 {
+            assert!(random_below(Duration::from_millis(10)) < Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config::{ApiKeyInfo, AzureSettings};
+use crate::proxy::error::ProxyResult;
+use crate::proxy::provider::{header_value, UpstreamAuth, UpstreamTarget};
+use crate::types::OpenAIRequest;
+use reqwest::header::HeaderName;
+use secrecy::ExposeSecret;
+
+/// Map an OpenAI-style path onto the Azure deployment layout.
+///
+/// `/v1/chat/completions` with model `gpt-4o` becomes
+/// `{url}/openai/deployments/{deployment}/chat/completions?api-version=...`.
+/// Requests without a model (e.g. `/v1/models`) go to `{url}/openai/...`.
+pub fn resolve_target(
+    key_info: &ApiKeyInfo,
+    settings: &AzureSettings,
+    path: &str,
+    body: Option<bytes::Bytes>,
+) -> ProxyResult<UpstreamTarget> {
+    let operation = path.strip_prefix("/v1").unwrap_or(path);
+    let model = body
+        .as_ref()
+        .filter(|body| !body.is_empty())
+        .and_then(|body| serde_json::from_slice::<OpenAIRequest>(body).ok())
+        .map(|request| request.model);
+
+    let base = key_info.url.trim_end_matches('/');
+    let path = match model {
+        Some(model) => format!(
+            "/openai/deployments/{}{}",
+            settings.deployment_for(&model),
+            operation
+        ),
+        None => format!("/openai{}", operation),
+    };
+
+    Ok(UpstreamTarget {
+        url: format!("{}{}?api-version={}", base, path, settings.api_version),
+        auth: UpstreamAuth::Header(
+            HeaderName::from_static("api-key"),
+            header_value(key_info.key.expose_secret())?,
+        ),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Provider;
+    use bytes::Bytes;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+
+    fn create_azure_key() -> (ApiKeyInfo, AzureSettings) {
+        let settings = AzureSettings {
+            api_version: "2024-06-01".to_string(),
+            deployments: HashMap::from([("gpt-4o".to_string(), "prod-gpt4o".to_string())]),
+        };
+        let key = ApiKeyInfo {
+            key: SecretString::new("azure-secret".to_string()),
+            url: "https://example.openai.azure.com/".to_string(),
+            models: vec!["gpt-4o".to_string()],
+            provider: Provider::Azure(settings.clone()),
+            ..Default::default()
+        };
+        (key, settings)
+    }
+
+    #[test]
+    fn test_deployment_path_rewrite() {
+        let (key, settings) = create_azure_key();
+        let body = Bytes::from(r#"{"model": "gpt-4o", "messages": []}"#);
+
+        let target = resolve_target(&key, &settings, "/v1/chat/completions", Some(body)).unwrap();
+        assert_eq!(
+            target.url,
+            "https://example.openai.azure.com/openai/deployments/prod-gpt4o/chat/completions?api-version=2024-06-01"
+        );
+        match target.auth {
+            UpstreamAuth::Header(name, value) => {
+                assert_eq!(name.as_str(), "api-key");
+                assert_eq!(value, "azure-secret");
+            }
+            other => panic!("expected header auth, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unmapped_model_uses_model_as_deployment() {
+        let (key, settings) = create_azure_key();
+        let body = Bytes::from(r#"{"model": "text-embedding-3-small", "input": "hi"}"#);
+
+        let target = resolve_target(&key, &settings, "/v1/embeddings", Some(body)).unwrap();
+        assert!(target.url.ends_with(
+            "/openai/deployments/text-embedding-3-small/embeddings?api-version=2024-06-01"
+        ));
+    }
+
+    #[test]
+    fn test_path_without_model() {
+        let (key, settings) = create_azure_key();
+
+        let target = resolve_target(&key, &settings, "/v1/models", None).unwrap();
+        assert_eq!(
+            target.url,
+            "https://example.openai.azure.com/openai/models?api-version=2024-06-01"
+        );
+    }
+}
@@ -0,0 +1,582 @@
+use crate::config::{ApiKeyInfo, GeminiAuth, GeminiSettings};
+use crate::proxy::error::{ProxyError, ProxyResult};
+use crate::proxy::provider::{header_value, UpstreamAuth, UpstreamTarget};
+use crate::proxy::stream::{collect, next_event, BodyStream};
+use crate::util::unix_timestamp;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use reqwest::header::HeaderName;
+use secrecy::ExposeSecret;
+use serde_json::{json, Map, Value};
+
+/// Map an OpenAI chat completion onto `generateContent` / `streamGenerateContent`.
+///
+/// The key `url` is the API root including its version, e.g.
+/// `https://generativelanguage.googleapis.com/v1beta`.
+pub fn resolve_target(
+    key_info: &ApiKeyInfo,
+    settings: &GeminiSettings,
+    path: &str,
+    body: Option<Bytes>,
+) -> ProxyResult<UpstreamTarget> {
+    let base = key_info.url.trim_end_matches('/');
+    let auth = match settings.auth {
+        GeminiAuth::Header => UpstreamAuth::Header(
+            HeaderName::from_static("x-goog-api-key"),
+            header_value(key_info.key.expose_secret())?,
+        ),
+        GeminiAuth::Query => UpstreamAuth::Query("key", key_info.key.expose_secret().clone()),
+    };
+
+    match path {
+        // Listing models is only used to check the key, so it is passed through untranslated
+        "/v1/models" => Ok(UpstreamTarget {
+            url: format!("{}/models", base),
+            auth,
+            body: None,
+        }),
+        "/v1/chat/completions" => {
+            let request: Value = serde_json::from_slice(body.as_deref().unwrap_or_default())?;
+            check_supported(&request)?;
+            let model = request
+                .get("model")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim_start_matches("models/");
+            let url = if is_streaming(&request) {
+                format!("{}/models/{}:streamGenerateContent?alt=sse", base, model)
+            } else {
+                format!("{}/models/{}:generateContent", base, model)
+            };
+
+            Ok(UpstreamTarget {
+                url,
+                auth,
+                body: Some(Bytes::from(serde_json::to_vec(&translate_request(
+                    &request,
+                ))?)),
+            })
+        }
+        _ => Err(ProxyError::UnsupportedOperation {
+            provider: "gemini".to_string(),
+            path: path.to_string(),
+        }),
+    }
+}
+
+/// Refuse parameters [`translate_request`] cannot carry over, rather than letting Gemini
+/// answer a different request, e.g. with plain text where a tool call was expected
+fn check_supported(request: &Value) -> ProxyResult<()> {
+    let unsupported = ["tools", "tool_choice", "functions", "function_call"]
+        .into_iter()
+        .find(|param| request.get(*param).is_some_and(|value| !value.is_null()))
+        .or_else(|| {
+            (request
+                .pointer("/response_format/type")
+                .and_then(Value::as_str)
+                == Some("json_schema"))
+            .then_some("response_format")
+        });
+    match unsupported {
+        Some(param) => Err(ProxyError::InvalidRequest {
+            message: format!(
+                "Unsupported parameter: '{}' is not supported on Gemini keys.",
+                param
+            ),
+            param: Some(param.to_string()),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Translate an OpenAI chat completion request into a Gemini `GenerateContentRequest`
+pub fn translate_request(request: &Value) -> Value {
+    let mut contents = Vec::new();
+    let mut system_parts = Vec::new();
+
+    for message in request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let parts = content_parts(message.get("content"));
+        match message.get("role").and_then(Value::as_str) {
+            Some("system") | Some("developer") => system_parts.extend(parts),
+            Some("assistant") => contents.push(json!({"role": "model", "parts": parts})),
+            _ => contents.push(json!({"role": "user", "parts": parts})),
+        }
+    }
+
+    let mut generation_config = Map::new();
+    for (openai, gemini) in [
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+        ("max_tokens", "maxOutputTokens"),
+        ("max_completion_tokens", "maxOutputTokens"),
+        ("n", "candidateCount"),
+        ("presence_penalty", "presencePenalty"),
+        ("frequency_penalty", "frequencyPenalty"),
+        ("seed", "seed"),
+    ] {
+        if let Some(value) = request.get(openai).filter(|v| !v.is_null()) {
+            generation_config.insert(gemini.to_string(), value.clone());
+        }
+    }
+    match request.get("stop") {
+        Some(Value::String(stop)) => {
+            generation_config.insert("stopSequences".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) => {
+            generation_config.insert("stopSequences".to_string(), json!(stops));
+        }
+        _ => {}
+    }
+    if request
+        .pointer("/response_format/type")
+        .and_then(Value::as_str)
+        == Some("json_object")
+    {
+        generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+    }
+
+    let mut translated = json!({ "contents": contents });
+    if !system_parts.is_empty() {
+        translated["systemInstruction"] = json!({ "parts": system_parts });
+    }
+    if !generation_config.is_empty() {
+        translated["generationConfig"] = Value::Object(generation_config);
+    }
+    translated
+}
+
+/// Rewrite a Gemini response body (JSON or SSE) into OpenAI chat completion format
+pub fn translate_response(request_body: &Bytes, stream: BodyStream) -> BodyStream {
+    let request: Value = serde_json::from_slice(request_body).unwrap_or_default();
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+
+    if is_streaming(&request) {
+        translate_event_stream(stream, ChunkTranslator::new(id, model))
+    } else {
+        futures::stream::once(async move {
+            let body = collect(stream).await?;
+            let response: Value = serde_json::from_slice(&body).map_err(std::io::Error::other)?;
+            let completion = translate_completion(&response, &id, &model);
+            Ok(Bytes::from(
+                serde_json::to_vec(&completion).map_err(std::io::Error::other)?,
+            ))
+        })
+        .boxed()
+    }
+}
+
+/// Translate a complete `GenerateContentResponse` into a `chat.completion`
+pub fn translate_completion(response: &Value, id: &str, model: &str) -> Value {
+    let choices: Vec<Value> = candidates(response)
+        .enumerate()
+        .map(|(index, candidate)| {
+            json!({
+                "index": candidate.get("index").and_then(Value::as_u64).unwrap_or(index as u64),
+                "message": {"role": "assistant", "content": candidate_text(candidate)},
+                "finish_reason": finish_reason(candidate),
+            })
+        })
+        .collect();
+
+    let mut completion = json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": unix_timestamp(),
+        "model": model,
+        "choices": choices,
+    });
+    if let Some(usage) = usage(response) {
+        completion["usage"] = usage;
+    }
+    completion
+}
+
+/// Stateful translation of Gemini stream events into `chat.completion.chunk` events
+pub struct ChunkTranslator {
+    id: String,
+    model: String,
+    created: u64,
+    sent_role: bool,
+}
+
+impl ChunkTranslator {
+    pub fn new(id: String, model: String) -> Self {
+        Self {
+            id,
+            model,
+            created: unix_timestamp(),
+            sent_role: false,
+        }
+    }
+
+    /// Translate the `data:` payload of one Gemini SSE event
+    pub fn translate(&mut self, data: &str) -> Option<Value> {
+        let response: Value = serde_json::from_str(data).ok()?;
+        let include_role = !self.sent_role;
+        self.sent_role = true;
+
+        let choices: Vec<Value> = candidates(&response)
+            .enumerate()
+            .map(|(index, candidate)| {
+                let mut delta = json!({ "content": candidate_text(candidate) });
+                if include_role {
+                    delta["role"] = json!("assistant");
+                }
+                json!({
+                    "index": candidate.get("index").and_then(Value::as_u64).unwrap_or(index as u64),
+                    "delta": delta,
+                    "finish_reason": finish_reason(candidate),
+                })
+            })
+            .collect();
+
+        let mut chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        });
+        let finished = chunk["choices"]
+            .as_array()
+            .is_some_and(|choices| choices.iter().any(|c| !c["finish_reason"].is_null()));
+        if let (true, Some(usage)) = (finished, usage(&response)) {
+            chunk["usage"] = usage;
+        }
+        Some(chunk)
+    }
+}
+
+fn translate_event_stream(stream: BodyStream, translator: ChunkTranslator) -> BodyStream {
+    struct State {
+        stream: BodyStream,
+        translator: ChunkTranslator,
+        buffer: BytesMut,
+        done: bool,
+    }
+
+    let state = State {
+        stream,
+        translator,
+        buffer: BytesMut::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        loop {
+            match state.stream.next().await {
+                Some(Ok(chunk)) => {
+                    state.buffer.extend_from_slice(&chunk);
+                    let out = drain_events(&mut state.buffer, &mut state.translator, false);
+                    if !out.is_empty() {
+                        return Some((Ok(out.freeze()), state));
+                    }
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.done = true;
+                    let mut out = drain_events(&mut state.buffer, &mut state.translator, true);
+                    out.extend_from_slice(b"data: [DONE]\n\n");
+                    return Some((Ok(out.freeze()), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Translate every complete SSE event in `buffer`; with `flush` the remainder counts as one
+fn drain_events(buffer: &mut BytesMut, translator: &mut ChunkTranslator, flush: bool) -> BytesMut {
+    let mut out = BytesMut::new();
+    while let Some(event) = next_event(buffer, flush) {
+        let event = String::from_utf8_lossy(&event);
+        let data: Vec<&str> = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(str::trim_start)
+            .collect();
+        if data.is_empty() {
+            continue;
+        }
+        if let Some(chunk) = translator.translate(&data.join("\n")) {
+            out.extend_from_slice(b"data: ");
+            out.extend_from_slice(chunk.to_string().as_bytes());
+            out.extend_from_slice(b"\n\n");
+        }
+    }
+    out
+}
+
+fn is_streaming(request: &Value) -> bool {
+    request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+fn content_parts(content: Option<&Value>) -> Vec<Value> {
+    match content {
+        Some(Value::String(text)) => vec![json!({ "text": text })],
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") => Some(json!({ "text": part.get("text")? })),
+                Some("image_url") => {
+                    let url = part.pointer("/image_url/url")?.as_str()?;
+                    let (header, data) = url.strip_prefix("data:")?.split_once(",")?;
+                    let mime_type = header.strip_suffix(";base64")?;
+                    Some(json!({ "inlineData": { "mimeType": mime_type, "data": data } }))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn candidates(response: &Value) -> impl Iterator<Item = &Value> {
+    response
+        .get("candidates")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn candidate_text(candidate: &Value) -> String {
+    candidate
+        .pointer("/content/parts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.get("text").and_then(Value::as_str))
+        .collect()
+}
+
+fn finish_reason(candidate: &Value) -> Value {
+    match candidate.get("finishReason").and_then(Value::as_str) {
+        None | Some("FINISH_REASON_UNSPECIFIED") => Value::Null,
+        Some("STOP") => json!("stop"),
+        Some("MAX_TOKENS") => json!("length"),
+        Some("SAFETY")
+        | Some("RECITATION")
+        | Some("BLOCKLIST")
+        | Some("PROHIBITED_CONTENT")
+        | Some("SPII") => json!("content_filter"),
+        Some(_) => json!("stop"),
+    }
+}
+
+fn usage(response: &Value) -> Option<Value> {
+    let metadata = response.get("usageMetadata")?;
+    let prompt = metadata
+        .get("promptTokenCount")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let completion = metadata
+        .get("candidatesTokenCount")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let total = metadata
+        .get("totalTokenCount")
+        .and_then(Value::as_u64)
+        .unwrap_or(prompt + completion);
+    Some(json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": total,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Provider;
+    use secrecy::SecretString;
+
+    fn create_gemini_key(auth: GeminiAuth) -> (ApiKeyInfo, GeminiSettings) {
+        let settings = GeminiSettings { auth };
+        let key = ApiKeyInfo {
+            key: SecretString::new("gemini-secret".to_string()),
+            url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            models: vec!["gemini-1.5-pro".to_string()],
+            provider: Provider::Gemini(settings.clone()),
+            ..Default::default()
+        };
+        (key, settings)
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        let (key, settings) = create_gemini_key(GeminiAuth::Query);
+        let body = Bytes::from(r#"{"model": "gemini-1.5-pro", "stream": true, "messages": []}"#);
+
+        let target = resolve_target(&key, &settings, "/v1/chat/completions", Some(body)).unwrap();
+        assert_eq!(
+            target.url,
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-pro:streamGenerateContent?alt=sse"
+        );
+        assert!(matches!(target.auth, UpstreamAuth::Query("key", ref v) if v == "gemini-secret"));
+
+        let target = resolve_target(&key, &settings, "/v1/models", None).unwrap();
+        assert_eq!(
+            target.url,
+            "https://generativelanguage.googleapis.com/v1beta/models"
+        );
+        assert!(target.body.is_none());
+
+        let result = resolve_target(&key, &settings, "/v1/embeddings", None);
+        assert!(matches!(
+            result,
+            Err(ProxyError::UnsupportedOperation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_untranslatable_parameters_are_refused() {
+        let (key, settings) = create_gemini_key(GeminiAuth::Header);
+        let resolve = |request: Value| {
+            let body = Bytes::from(request.to_string());
+            match resolve_target(&key, &settings, "/v1/chat/completions", Some(body)) {
+                Err(ProxyError::InvalidRequest { param, .. }) => param,
+                other => panic!("unexpected result {:?}", other.map(|t| t.url)),
+            }
+        };
+        let messages = json!([{"role": "user", "content": "Weather?"}]);
+
+        assert_eq!(
+            resolve(json!({
+                "model": "gemini-1.5-pro",
+                "messages": messages,
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}]
+            })),
+            Some("tools".to_string())
+        );
+        assert_eq!(
+            resolve(json!({
+                "model": "gemini-1.5-pro",
+                "messages": messages,
+                "response_format": {"type": "json_schema", "json_schema": {"name": "w"}}
+            })),
+            Some("response_format".to_string())
+        );
+
+        // Explicit nulls and plain JSON mode still translate
+        let body = json!({
+            "model": "gemini-1.5-pro",
+            "messages": messages,
+            "tools": null,
+            "response_format": {"type": "json_object"}
+        });
+        let body = Bytes::from(body.to_string());
+        assert!(resolve_target(&key, &settings, "/v1/chat/completions", Some(body)).is_ok());
+    }
+
+    #[test]
+    fn test_translate_request() {
+        let request = json!({
+            "model": "gemini-1.5-pro",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hello"},
+                {"role": "assistant", "content": "Hi!"},
+                {"role": "user", "content": [{"type": "text", "text": "How are you?"}]}
+            ],
+            "temperature": 0.2,
+            "max_tokens": 64,
+            "stop": "END"
+        });
+
+        let translated = translate_request(&request);
+        assert_eq!(
+            translated["systemInstruction"]["parts"][0]["text"],
+            "Be brief."
+        );
+        assert_eq!(translated["contents"].as_array().unwrap().len(), 3);
+        assert_eq!(translated["contents"][1]["role"], "model");
+        assert_eq!(
+            translated["contents"][2]["parts"][0]["text"],
+            "How are you?"
+        );
+        assert_eq!(translated["generationConfig"]["temperature"], 0.2);
+        assert_eq!(translated["generationConfig"]["maxOutputTokens"], 64);
+        assert_eq!(
+            translated["generationConfig"]["stopSequences"],
+            json!(["END"])
+        );
+    }
+
+    #[test]
+    fn test_translate_completion() {
+        let response = json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Hello "}, {"text": "there"}]},
+                "finishReason": "MAX_TOKENS"
+            }],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 2, "totalTokenCount": 6}
+        });
+
+        let completion = translate_completion(&response, "chatcmpl-1", "gemini-1.5-pro");
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "Hello there"
+        );
+        assert_eq!(completion["choices"][0]["finish_reason"], "length");
+        assert_eq!(completion["usage"]["total_tokens"], 6);
+    }
+
+    #[tokio::test]
+    async fn test_translate_event_stream_across_chunk_boundaries() {
+        let upstream = concat!(
+            "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Hel\"}]}}]}\r\n\r\n",
+            "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"lo\"}]},\"finishReason\":\"STOP\"}],",
+            "\"usageMetadata\":{\"promptTokenCount\":1,\"candidatesTokenCount\":2}}\r\n\r\n"
+        );
+        // Split mid-event to exercise buffering
+        let (first, second) = upstream.split_at(50);
+        let stream: BodyStream = futures::stream::iter(vec![
+            Ok(Bytes::from(first.to_string())),
+            Ok(Bytes::from(second.to_string())),
+        ])
+        .boxed();
+
+        let request = Bytes::from(r#"{"model": "gemini-1.5-pro", "stream": true}"#);
+        let output = collect(translate_response(&request, stream)).await.unwrap();
+        let output = String::from_utf8(output.to_vec()).unwrap();
+
+        let events: Vec<&str> = output
+            .split("\n\n")
+            .filter(|e| !e.is_empty())
+            .map(|e| e.strip_prefix("data: ").unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], "[DONE]");
+
+        let first: Value = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(first["object"], "chat.completion.chunk");
+        assert_eq!(first["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(first["choices"][0]["delta"]["content"], "Hel");
+
+        let last: Value = serde_json::from_str(events[1]).unwrap();
+        assert!(last["choices"][0]["delta"].get("role").is_none());
+        assert_eq!(last["choices"][0]["finish_reason"], "stop");
+        assert_eq!(last["usage"]["total_tokens"], 3);
+        assert_eq!(first["id"], last["id"]);
+    }
+}
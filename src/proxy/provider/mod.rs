@@ -0,0 +1,67 @@
+pub mod azure;
+pub mod gemini;
+
+use crate::config::{ApiKeyInfo, Provider};
+use crate::proxy::error::{ProxyError, ProxyResult};
+use crate::proxy::stream::BodyStream;
+use bytes::Bytes;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use secrecy::ExposeSecret;
+
+/// Where and how a request for a given key is sent upstream
+#[derive(Debug, Clone)]
+pub struct UpstreamTarget {
+    pub url: String,
+    pub auth: UpstreamAuth,
+    /// Body to send, translated for the provider when needed
+    pub body: Option<Bytes>,
+}
+
+/// Credential placement for the upstream request
+#[derive(Debug, Clone)]
+pub enum UpstreamAuth {
+    Header(HeaderName, HeaderValue),
+    /// Query parameter name and value; kept out of `url` so it never ends up in logs
+    Query(&'static str, String),
+}
+
+/// Build the upstream target for a key, rewriting OpenAI paths for other providers
+pub fn resolve_target(
+    key_info: &ApiKeyInfo,
+    path: &str,
+    body: Option<Bytes>,
+) -> ProxyResult<UpstreamTarget> {
+    match &key_info.provider {
+        Provider::OpenAI => Ok(UpstreamTarget {
+            url: format!("{}{}", key_info.url, path),
+            auth: UpstreamAuth::Header(
+                AUTHORIZATION,
+                header_value(&format!("Bearer {}", key_info.key.expose_secret()))?,
+            ),
+            body,
+        }),
+        Provider::Azure(settings) => azure::resolve_target(key_info, settings, path, body),
+        Provider::Gemini(settings) => gemini::resolve_target(key_info, settings, path, body),
+    }
+}
+
+/// Whether successful responses from this key need translating to the OpenAI format
+pub fn translates_response(key_info: &ApiKeyInfo) -> bool {
+    matches!(key_info.provider, Provider::Gemini(_))
+}
+
+/// Rewrite a successful upstream body into the OpenAI wire format where needed
+pub fn translate_response(
+    key_info: &ApiKeyInfo,
+    request_body: &Bytes,
+    stream: BodyStream,
+) -> BodyStream {
+    match &key_info.provider {
+        Provider::Gemini(_) => gemini::translate_response(request_body, stream),
+        Provider::OpenAI | Provider::Azure(_) => stream,
+    }
+}
+
+pub(crate) fn header_value(value: &str) -> ProxyResult<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_| ProxyError::InvalidApiKey)
+}
@@ -0,0 +1,225 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{BoxStream, Stream};
+use futures::StreamExt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Response body stream handed back to the client
+pub type BodyStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// How a response body stream ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamOutcome {
+    /// Every chunk was delivered
+    Completed,
+    /// The upstream body failed mid-stream
+    Failed,
+    /// The stream was dropped early, usually because the client went away
+    Aborted,
+}
+
+/// Observes a body stream as it is forwarded to the client
+pub trait StreamObserver: Send + Unpin + 'static {
+    fn on_chunk(&mut self, _chunk: &Bytes) {}
+
+    /// Called exactly once, including when the stream is dropped before finishing
+    fn on_end(&mut self, outcome: StreamOutcome);
+}
+
+/// Attach an observer to a body stream without buffering it
+pub fn tap(stream: BodyStream, observer: impl StreamObserver) -> BodyStream {
+    Box::pin(Tapped {
+        inner: stream,
+        observer,
+        finished: false,
+    })
+}
+
+struct Tapped<O: StreamObserver> {
+    inner: BodyStream,
+    observer: O,
+    finished: bool,
+}
+
+impl<O: StreamObserver> Tapped<O> {
+    fn finish(&mut self, outcome: StreamOutcome) {
+        if !self.finished {
+            self.finished = true;
+            self.observer.on_end(outcome);
+        }
+    }
+}
+
+impl<O: StreamObserver> Stream for Tapped<O> {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match this.inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                this.observer.on_chunk(&chunk);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.finish(StreamOutcome::Failed);
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                this.finish(StreamOutcome::Completed);
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<O: StreamObserver> Drop for Tapped<O> {
+    fn drop(&mut self) {
+        self.finish(StreamOutcome::Aborted);
+    }
+}
+
+/// Position and length of the first blank line ending an SSE event
+fn event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    [&b"\r\n\r\n"[..], &b"\n\n"[..]]
+        .into_iter()
+        .filter_map(|sep| {
+            buffer
+                .windows(sep.len())
+                .position(|window| window == sep)
+                .map(|position| (position, sep.len()))
+        })
+        .min()
+}
+
+/// Split the next blank-line terminated SSE event off the front of `buffer`
+pub fn next_event(buffer: &mut BytesMut, flush: bool) -> Option<Bytes> {
+    match event_end(buffer) {
+        Some((position, len)) => {
+            let event = buffer.split_to(position).freeze();
+            let _ = buffer.split_to(len);
+            Some(event)
+        }
+        None if flush && !buffer.is_empty() => Some(buffer.split().freeze()),
+        None => None,
+    }
+}
+
+/// Split a whole SSE body into one chunk per event, each keeping its separator
+pub fn split_events(body: &Bytes) -> Vec<Bytes> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while let Some((position, len)) = event_end(&body[start..]) {
+        let end = start + position + len;
+        chunks.push(body.slice(start..end));
+        start = end;
+    }
+    if start < body.len() {
+        chunks.push(body.slice(start..));
+    }
+    chunks
+}
+
+/// Buffer a whole body stream
+pub async fn collect(mut stream: BodyStream) -> std::io::Result<Bytes> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    Ok(body.freeze())
+}
+
+/// A response whose body is still a stream, so observers can be attached before it is sent
+pub struct ProxiedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BodyStream,
+}
+
+impl ProxiedResponse {
+    pub fn tap(mut self, observer: impl StreamObserver) -> Self {
+        self.body = tap(self.body, observer);
+        self
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let mut response = Response::new(Body::from_stream(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StreamObserver for Recorder {
+        fn on_chunk(&mut self, chunk: &Bytes) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(String::from_utf8_lossy(chunk).to_string());
+        }
+
+        fn on_end(&mut self, outcome: StreamOutcome) {
+            self.events.lock().unwrap().push(format!("{:?}", outcome));
+        }
+    }
+
+    fn chunks(parts: &[&'static str]) -> BodyStream {
+        let parts: Vec<_> = parts
+            .iter()
+            .map(|p| Ok(Bytes::from_static(p.as_bytes())))
+            .collect();
+        futures::stream::iter(parts).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_tap_reports_completion_once() {
+        let recorder = Recorder::default();
+        let events = recorder.events.clone();
+
+        let mut stream = tap(chunks(&["a", "b"]), recorder);
+        while stream.next().await.is_some() {}
+        drop(stream);
+
+        assert_eq!(*events.lock().unwrap(), vec!["a", "b", "Completed"]);
+    }
+
+    #[tokio::test]
+    async fn test_tap_reports_abort_on_drop() {
+        let recorder = Recorder::default();
+        let events = recorder.events.clone();
+
+        let mut stream = tap(chunks(&["a", "b"]), recorder);
+        stream.next().await;
+        drop(stream);
+
+        assert_eq!(*events.lock().unwrap(), vec!["a", "Aborted"]);
+    }
+
+    #[test]
+    fn test_split_events_keeps_separators() {
+        let body = Bytes::from_static(b"data: 1\r\n\r\ndata: 2\n\ndata: [DONE]");
+        let chunks = split_events(&body);
+        assert_eq!(
+            chunks,
+            [
+                &b"data: 1\r\n\r\n"[..],
+                &b"data: 2\n\n"[..],
+                &b"data: [DONE]"[..]
+            ]
+        );
+    }
+}
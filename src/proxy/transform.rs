@@ -0,0 +1,540 @@
+use crate::config::{
+    RequestTransformConfig, ResponseTransformConfig, TransformScopeConfig, TransformsConfig,
+};
+use crate::proxy::error::ProxyResult;
+use crate::proxy::models::ModelMatcher;
+use crate::proxy::stream::{collect, next_event, BodyStream, ProxiedResponse};
+use crate::types::OpenAIRequest;
+use anyhow::{Context, Result};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::HeaderMap;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Which requests a transform applies to
+#[derive(Debug)]
+struct Scope {
+    paths: Vec<String>,
+    models: Option<ModelMatcher>,
+    clients: Vec<String>,
+}
+
+impl Scope {
+    fn new(config: &TransformScopeConfig) -> Result<Self> {
+        let models = if config.models.is_empty() {
+            None
+        } else {
+            Some(ModelMatcher::parse(&config.models).context("Invalid transform model pattern")?)
+        };
+        Ok(Self {
+            paths: config.paths.clone(),
+            models,
+            clients: config.clients.clone(),
+        })
+    }
+
+    fn matches(&self, path: &str, model: &str, client: Option<&str>) -> bool {
+        let path_matches = self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|entry| match entry.strip_suffix('*') {
+                    Some(prefix) => path.starts_with(prefix),
+                    None => entry == path,
+                });
+        let model_matches = self
+            .models
+            .as_ref()
+            .is_none_or(|models| models.matches(model).is_some());
+        let client_matches =
+            self.clients.is_empty() || client.is_some_and(|c| self.clients.iter().any(|e| e == c));
+        path_matches && model_matches && client_matches
+    }
+}
+
+/// Configured request and response rewrites, each scoped by path, model and client
+#[derive(Debug)]
+pub struct Transforms {
+    client_header: String,
+    request: Vec<(Scope, RequestTransformConfig)>,
+    response: Vec<(Scope, Arc<ResponseTransformConfig>)>,
+}
+
+impl Transforms {
+    pub fn new(config: &TransformsConfig) -> Result<Self> {
+        let request = config
+            .request
+            .iter()
+            .map(|rule| {
+                reject_model_field(rule.remove.iter().chain(rule.set.keys()))?;
+                reject_model_field(rule.defaults.keys())?;
+                Ok((Scope::new(&rule.scope)?, rule.clone()))
+            })
+            .collect::<Result<_>>()?;
+        let response = config
+            .response
+            .iter()
+            .map(|rule| Ok((Scope::new(&rule.scope)?, Arc::new(rule.clone()))))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            client_header: config.client_header.to_ascii_lowercase(),
+            request,
+            response,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.request.is_empty() && self.response.is_empty()
+    }
+
+    fn client<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        headers
+            .get(&self.client_header)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// Rewrite a JSON request body with every matching request transform, in order.
+    ///
+    /// Returns `None` when no transform applies or the body is not a JSON request.
+    pub fn transform_request(
+        &self,
+        path: &str,
+        model: &str,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> ProxyResult<Option<Bytes>> {
+        let client = self.client(headers);
+        let mut rules = self
+            .request
+            .iter()
+            .filter(|(scope, _)| scope.matches(path, model, client))
+            .peekable();
+        if rules.peek().is_none() {
+            return Ok(None);
+        }
+        let Ok(mut request) = serde_json::from_slice::<OpenAIRequest>(body) else {
+            return Ok(None);
+        };
+
+        for (_, rule) in rules {
+            apply_request_rule(rule, path, &mut request);
+        }
+        Ok(Some(Bytes::from(serde_json::to_vec(&request)?)))
+    }
+
+    /// The response transforms matching a request, if any
+    pub fn response_transform(
+        &self,
+        path: &str,
+        model: &str,
+        headers: &HeaderMap,
+    ) -> Option<ResponseTransform> {
+        let client = self.client(headers);
+        let rules: Vec<_> = self
+            .response
+            .iter()
+            .filter(|(scope, _)| scope.matches(path, model, client))
+            .map(|(_, rule)| rule.clone())
+            .collect();
+        (!rules.is_empty()).then_some(ResponseTransform { rules })
+    }
+}
+
+/// `model` drives routing, so transforms must not touch it
+fn reject_model_field<'a>(mut fields: impl Iterator<Item = &'a String>) -> Result<()> {
+    if fields.any(|field| field == "model") {
+        anyhow::bail!("Request transforms cannot change 'model'; use [models.aliases]");
+    }
+    Ok(())
+}
+
+fn apply_request_rule(rule: &RequestTransformConfig, path: &str, request: &mut OpenAIRequest) {
+    let fields = &mut request.other;
+    for field in &rule.remove {
+        fields.remove(field);
+    }
+    for (field, value) in &rule.defaults {
+        fields.entry(field.clone()).or_insert_with(|| value.clone());
+    }
+    for (field, value) in &rule.set {
+        fields.insert(field.clone(), value.clone());
+    }
+
+    if let Some(cap) = rule.max_tokens {
+        let mut capped = false;
+        for field in ["max_tokens", "max_completion_tokens"] {
+            if let Some(value) = fields.get_mut(field) {
+                if value.as_u64().is_none_or(|tokens| tokens > cap) {
+                    *value = Value::from(cap);
+                }
+                capped = true;
+            }
+        }
+        // Only generation endpoints take a limit; chat gets the field reasoning models accept
+        let field = match path {
+            "/v1/chat/completions" => Some("max_completion_tokens"),
+            "/v1/completions" => Some("max_tokens"),
+            _ => None,
+        };
+        if let Some(field) = field.filter(|_| !capped) {
+            fields.insert(field.to_string(), Value::from(cap));
+        }
+    }
+
+    if let Some(prompt) = &rule.system_prompt {
+        if let Some(Value::Array(messages)) = fields.get_mut("messages") {
+            let has_system = messages.iter().any(|message| {
+                matches!(message["role"].as_str(), Some("system") | Some("developer"))
+            });
+            if !has_system {
+                messages.insert(0, json!({"role": "system", "content": prompt}));
+            }
+        }
+    }
+}
+
+/// Response rewrites for one request, applied to the whole JSON body or to every SSE chunk
+#[derive(Debug, Clone)]
+pub struct ResponseTransform {
+    rules: Vec<Arc<ResponseTransformConfig>>,
+}
+
+impl ResponseTransform {
+    /// Rewrite a successful JSON or event-stream response; anything else passes through
+    pub fn apply(self, mut response: ProxiedResponse) -> ProxiedResponse {
+        if !response.status.is_success() {
+            return response;
+        }
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let events = content_type.starts_with("text/event-stream");
+        if !events && !content_type.starts_with("application/json") {
+            return response;
+        }
+
+        let body = std::mem::replace(&mut response.body, futures::stream::empty().boxed());
+        response.body = if events {
+            self.apply_to_events(body)
+        } else {
+            self.apply_to_json(body)
+        };
+        // The rewritten body no longer matches the upstream length
+        response.headers.remove(CONTENT_LENGTH);
+        response
+    }
+
+    fn apply_to_value(&self, value: &mut Value) {
+        let Some(fields) = value.as_object_mut() else {
+            return;
+        };
+        for rule in &self.rules {
+            for field in &rule.remove {
+                fields.remove(field);
+            }
+            for (field, value) in &rule.set {
+                fields.insert(field.clone(), value.clone());
+            }
+        }
+    }
+
+    fn apply_to_json(self, body: BodyStream) -> BodyStream {
+        futures::stream::once(async move {
+            let body = collect(body).await?;
+            let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+                return Ok(body);
+            };
+            self.apply_to_value(&mut value);
+            Ok(Bytes::from(
+                serde_json::to_vec(&value).map_err(std::io::Error::other)?,
+            ))
+        })
+        .boxed()
+    }
+
+    fn apply_to_events(self, body: BodyStream) -> BodyStream {
+        struct State {
+            body: BodyStream,
+            transform: ResponseTransform,
+            buffer: BytesMut,
+            done: bool,
+        }
+
+        let state = State {
+            body,
+            transform: self,
+            buffer: BytesMut::new(),
+            done: false,
+        };
+
+        futures::stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+            loop {
+                match state.body.next().await {
+                    Some(Ok(chunk)) => {
+                        state.buffer.extend_from_slice(&chunk);
+                        let out = state.transform.drain_events(&mut state.buffer, false);
+                        if !out.is_empty() {
+                            return Some((Ok(out.freeze()), state));
+                        }
+                    }
+                    Some(Err(e)) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                    None => {
+                        state.done = true;
+                        let out = state.transform.drain_events(&mut state.buffer, true);
+                        return Some((Ok(out.freeze()), state));
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Rewrite every complete SSE event in `buffer`; `[DONE]` and non-JSON events pass through
+    fn drain_events(&self, buffer: &mut BytesMut, flush: bool) -> BytesMut {
+        let mut out = BytesMut::new();
+        while let Some(event) = next_event(buffer, flush) {
+            let text = String::from_utf8_lossy(&event);
+            let (data, other): (Vec<&str>, Vec<&str>) =
+                text.lines().partition(|line| line.starts_with("data:"));
+            let data: Vec<&str> = data
+                .iter()
+                .map(|line| line["data:".len()..].trim_start())
+                .collect();
+            match serde_json::from_str::<Value>(&data.join("\n")) {
+                Ok(mut value) if !data.is_empty() => {
+                    self.apply_to_value(&mut value);
+                    for line in other {
+                        out.extend_from_slice(line.as_bytes());
+                        out.extend_from_slice(b"\n");
+                    }
+                    out.extend_from_slice(b"data: ");
+                    out.extend_from_slice(value.to_string().as_bytes());
+                }
+                _ => out.extend_from_slice(&event),
+            }
+            out.extend_from_slice(b"\n\n");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, StatusCode};
+
+    fn transforms(config: &str) -> Transforms {
+        let config: TransformsConfig = toml::from_str(config).unwrap();
+        Transforms::new(&config).unwrap()
+    }
+
+    fn transform_request(
+        transforms: &Transforms,
+        path: &str,
+        headers: &HeaderMap,
+        body: Value,
+    ) -> Value {
+        let body = Bytes::from(body.to_string());
+        let request: OpenAIRequest = serde_json::from_slice(&body).unwrap();
+        let rewritten = transforms
+            .transform_request(path, &request.model, headers, &body)
+            .unwrap()
+            .unwrap_or(body);
+        serde_json::from_slice(&rewritten).unwrap()
+    }
+
+    #[test]
+    fn test_request_policy_actions() {
+        let transforms = transforms(
+            r#"
+            [[request]]
+            paths = ["/v1/chat/*"]
+            remove = ["logit_bias"]
+            set = { user = "org-user" }
+            defaults = { temperature = 0.2 }
+            max_tokens = 256
+            system_prompt = "Follow the org policy."
+            "#,
+        );
+
+        let body = transform_request(
+            &transforms,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "hi"}],
+                "logit_bias": {"50256": -100},
+                "user": "someone-else",
+                "max_tokens": 4096
+            }),
+        );
+        assert_eq!(body["model"], "gpt-4o");
+        assert!(body.get("logit_bias").is_none());
+        assert_eq!(body["user"], "org-user");
+        assert_eq!(body["temperature"], 0.2);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "hi");
+
+        // An existing system message and a lower limit are kept
+        let body = transform_request(
+            &transforms,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "system", "content": "mine"}],
+                "max_completion_tokens": 100,
+                "temperature": 1.0
+            }),
+        );
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["max_completion_tokens"], 100);
+        assert!(body.get("max_tokens").is_none());
+        assert_eq!(body["temperature"], 1.0);
+
+        // Out of scope
+        let body = transform_request(
+            &transforms,
+            "/v1/embeddings",
+            &HeaderMap::new(),
+            json!({"model": "text-embedding-3-small", "input": "x", "user": "kept"}),
+        );
+        assert_eq!(body["user"], "kept");
+    }
+
+    #[test]
+    fn test_unscoped_token_cap_only_adds_limits_to_generation() {
+        let transforms = transforms(
+            r#"
+            [[request]]
+            max_tokens = 256
+            "#,
+        );
+
+        let body = transform_request(
+            &transforms,
+            "/v1/embeddings",
+            &HeaderMap::new(),
+            json!({"model": "text-embedding-3-small", "input": "x"}),
+        );
+        assert!(body.get("max_tokens").is_none());
+        assert!(body.get("max_completion_tokens").is_none());
+
+        let body = transform_request(
+            &transforms,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            json!({"model": "o3-mini", "messages": [{"role": "user", "content": "hi"}]}),
+        );
+        assert_eq!(body["max_completion_tokens"], 256);
+        assert!(body.get("max_tokens").is_none());
+
+        let body = transform_request(
+            &transforms,
+            "/v1/completions",
+            &HeaderMap::new(),
+            json!({"model": "gpt-3.5-turbo-instruct", "prompt": "hi"}),
+        );
+        assert_eq!(body["max_tokens"], 256);
+    }
+
+    #[test]
+    fn test_scope_by_model_and_client() {
+        let transforms = transforms(
+            r#"
+            [[request]]
+            models = ["gpt-4o*"]
+            clients = ["chat-ui"]
+            set = { user = "chat-ui" }
+            "#,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-kcp-client", HeaderValue::from_static("chat-ui"));
+        let request = |model: &str, headers: &HeaderMap| {
+            transform_request(
+                &transforms,
+                "/v1/chat/completions",
+                headers,
+                json!({"model": model}),
+            )
+        };
+
+        assert_eq!(request("gpt-4o-mini", &headers)["user"], "chat-ui");
+        assert!(request("gpt-3.5-turbo", &headers).get("user").is_none());
+        assert!(request("gpt-4o-mini", &HeaderMap::new())
+            .get("user")
+            .is_none());
+    }
+
+    #[test]
+    fn test_model_field_cannot_be_transformed() {
+        let config: TransformsConfig = toml::from_str(
+            r#"
+            [[request]]
+            set = { model = "gpt-4o" }
+            "#,
+        )
+        .unwrap();
+        assert!(Transforms::new(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_response_transform_json_and_events() {
+        let transforms = transforms(
+            r#"
+            [[response]]
+            remove = ["system_fingerprint"]
+            set = { provider = "kcp" }
+            "#,
+        );
+        let transform = transforms
+            .response_transform("/v1/chat/completions", "gpt-4o", &HeaderMap::new())
+            .unwrap();
+
+        let response = |content_type: &'static str, parts: Vec<&'static str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            headers.insert(CONTENT_LENGTH, HeaderValue::from_static("10"));
+            ProxiedResponse {
+                status: StatusCode::OK,
+                headers,
+                body: futures::stream::iter(parts.into_iter().map(|p| Ok(Bytes::from(p)))).boxed(),
+            }
+        };
+
+        let json = transform.clone().apply(response(
+            "application/json",
+            vec![r#"{"id": "1", "system_fi"#, r#"ngerprint": "fp"}"#],
+        ));
+        assert!(!json.headers.contains_key(CONTENT_LENGTH));
+        let body = collect(json.body).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"id": "1", "provider": "kcp"}));
+
+        let events = transform.apply(response(
+            "text/event-stream",
+            vec![
+                "data: {\"id\": \"1\", \"system_fingerprint\": \"fp\"}\n\nda",
+                "ta: [DONE]\n\n",
+            ],
+        ));
+        let body = collect(events.body).await.unwrap();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            "data: {\"id\":\"1\",\"provider\":\"kcp\"}\n\ndata: [DONE]\n\n"
+        );
+    }
+}
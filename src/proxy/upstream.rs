@@ -1,9 +1,10 @@
 use crate::config::{ApiKeyInfo, UpstreamConfig};
 use crate::proxy::error::{ProxyError, ProxyResult};
-use reqwest::{Client, Method, Response};
-use secrecy::ExposeSecret;
+use crate::proxy::provider::{self, UpstreamAuth, UpstreamTarget};
+use reqwest::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_TYPE};
+use reqwest::{Client, Method, RequestBuilder, Response};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::timeout;
 use tracing::{debug, warn};
 
@@ -24,7 +25,15 @@ impl UpstreamClient {
         Ok(Self { client, config })
     }
 
-    /// Make a request to the upstream API with simple retry logic
+    /// Make a request to the upstream API with simple retry logic.
+    ///
+    /// Also returns when the answering attempt was sent, so callers timing the
+    /// upstream do not count the backoff between retries.
+    #[tracing::instrument(
+        name = "upstream_request",
+        skip_all,
+        fields(request_id = request_id(headers.as_ref()), path = %path)
+    )]
     pub async fn request(
         &self,
         method: Method,
@@ -32,8 +41,9 @@ impl UpstreamClient {
         path: &str,
         body: Option<bytes::Bytes>,
         headers: Option<reqwest::header::HeaderMap>,
-    ) -> ProxyResult<Response> {
-        let url = format!("{}{}", key_info.url, path);
+    ) -> ProxyResult<(Response, Instant)> {
+        let target = provider::resolve_target(&key_info, path, body)?;
+        let url = target.url.clone();
 
         debug!(
             "Making {} request to {} with API key (redacted)",
@@ -42,24 +52,8 @@ impl UpstreamClient {
 
         // Try the request with simple retry logic
         for attempt in 0..=self.config.max_retries {
-            let mut request = self.client.request(method.clone(), &url);
-
-            // Add authorization header
-            request = request.header(
-                "Authorization",
-                format!("Bearer {}", key_info.key.expose_secret()),
-            );
-
-            // Add body if provided
-            if let Some(body) = body.as_ref() {
-                request = request.body(body.clone());
-                request = request.header("Content-Type", "application/json");
-            }
-
-            // Add custom headers if provided
-            if let Some(headers) = headers.as_ref() {
-                request = request.headers(headers.clone());
-            }
+            let request = self.build_request(method.clone(), &key_info, &target, headers.as_ref());
+            let sent = Instant::now();
 
             // Execute request with timeout
             match timeout(self.config.request_timeout(), request.send()).await {
@@ -84,7 +78,7 @@ impl UpstreamClient {
                         continue;
                     }
 
-                    return Ok(response);
+                    return Ok((response, sent));
                 }
                 Ok(Err(e)) => {
                     if self.should_retry_error(&e) && attempt < self.config.max_retries {
@@ -129,7 +123,7 @@ impl UpstreamClient {
         Err(ProxyError::AllRetriesExhausted)
     }
 
-    /// Forward a request as-is (streaming)
+    /// Forward a request as-is (streaming), with the send time of the attempt that answered
     pub async fn forward_request(
         &self,
         method: Method,
@@ -137,10 +131,66 @@ impl UpstreamClient {
         path: &str,
         body: Option<bytes::Bytes>,
         headers: Option<reqwest::header::HeaderMap>,
-    ) -> ProxyResult<Response> {
+    ) -> ProxyResult<(Response, Instant)> {
         self.request(method, key_info, path, body, headers).await
     }
 
+    /// Send a single attempt with the key's credentials, without retries
+    pub async fn probe(
+        &self,
+        method: Method,
+        key_info: &ApiKeyInfo,
+        path: &str,
+        body: Option<bytes::Bytes>,
+        probe_timeout: Duration,
+    ) -> ProxyResult<Response> {
+        let target = provider::resolve_target(key_info, path, body)?;
+        let request = self.build_request(method, key_info, &target, None);
+        match timeout(probe_timeout, request.send()).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(ProxyError::UpstreamFailed { source: e }),
+            Err(_) => Err(ProxyError::Timeout),
+        }
+    }
+
+    fn build_request(
+        &self,
+        method: Method,
+        key_info: &ApiKeyInfo,
+        target: &UpstreamTarget,
+        headers: Option<&reqwest::header::HeaderMap>,
+    ) -> RequestBuilder {
+        let mut request = self.client.request(method, &target.url);
+
+        // Client headers first, so the headers the proxy sets below always replace them
+        let mut request_headers = headers.cloned().unwrap_or_default();
+
+        // Add body if provided
+        if let Some(body) = target.body.as_ref() {
+            request = request.body(body.clone());
+            request_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+
+        // Per-key headers replace client ones; credentials still come last
+        for (name, value) in &key_info.extra_headers {
+            request_headers.insert(name.clone(), value.clone());
+        }
+
+        // Response bodies are read for batching, transforms, caching and auditing, and the
+        // client has no decompression, so never let the client or a key ask for compression
+        request_headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+
+        // Add provider-specific credentials
+        match &target.auth {
+            UpstreamAuth::Header(name, value) => {
+                request_headers.insert(name.clone(), value.clone());
+            }
+            UpstreamAuth::Query(name, value) => request = request.query(&[(*name, value)]),
+        }
+
+        request.headers(request_headers)
+    }
+
     fn should_retry_error(&self, error: &reqwest::Error) -> bool {
         // Retry on connection errors, timeouts, and server errors
         error.is_connect() || error.is_timeout() || error.is_request()
@@ -159,6 +209,14 @@ impl UpstreamClient {
     }
 }
 
+/// The proxy request id forwarded upstream, for log spans
+fn request_id(headers: Option<&reqwest::header::HeaderMap>) -> &str {
+    headers
+        .and_then(|h| h.get("x-request-id"))
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+}
+
 /// Check if the upstream response indicates an error that should trigger a key rotation
 pub fn should_rotate_key(status: reqwest::StatusCode) -> bool {
     matches!(
@@ -0,0 +1,354 @@
+use crate::proxy::error::{ProxyError, ProxyResult};
+use serde_json::{Map, Value};
+
+/// Roles accepted in chat `messages`
+const CHAT_ROLES: &[&str] = &[
+    "system",
+    "developer",
+    "user",
+    "assistant",
+    "tool",
+    "function",
+];
+
+/// JSON type expected of a request field
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Bool,
+    Number,
+    /// Integer of at least 0
+    Integer,
+    /// Integer of at least 1
+    Count,
+    Array,
+    Object,
+    /// A string or an array
+    StringOrArray,
+}
+
+impl Kind {
+    fn accepts(self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::Bool => value.is_boolean(),
+            Kind::Number => value.is_number(),
+            Kind::Integer => value.is_u64(),
+            Kind::Count => value.as_u64().is_some_and(|n| n >= 1),
+            Kind::Array => value.is_array(),
+            Kind::Object => value.is_object(),
+            Kind::StringOrArray => value.is_string() || value.is_array(),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Bool => "a boolean",
+            Kind::Number => "a number",
+            Kind::Integer => "a non-negative integer",
+            Kind::Count => "an integer of at least 1",
+            Kind::Array => "an array",
+            Kind::Object => "an object",
+            Kind::StringOrArray => "a string or an array",
+        }
+    }
+}
+
+/// Field requirements of one endpoint; fields not listed are passed through unchecked.
+///
+/// An explicit `null` counts as leaving the field out, as it does upstream.
+struct Schema {
+    required: &'static [&'static str],
+    fields: &'static [(&'static str, Kind)],
+}
+
+const SAMPLING_FIELDS: &[(&str, Kind)] = &[
+    ("stream", Kind::Bool),
+    ("temperature", Kind::Number),
+    ("top_p", Kind::Number),
+    ("n", Kind::Count),
+    ("presence_penalty", Kind::Number),
+    ("frequency_penalty", Kind::Number),
+    ("logit_bias", Kind::Object),
+    ("stop", Kind::StringOrArray),
+    ("user", Kind::String),
+];
+
+fn schema_for(path: &str) -> Option<Schema> {
+    let schema = match path {
+        "/v1/chat/completions" => Schema {
+            required: &["messages"],
+            fields: &[
+                ("messages", Kind::Array),
+                ("max_tokens", Kind::Count),
+                ("max_completion_tokens", Kind::Count),
+                ("tools", Kind::Array),
+                ("response_format", Kind::Object),
+                ("stream_options", Kind::Object),
+            ],
+        },
+        "/v1/completions" => Schema {
+            required: &[],
+            fields: &[
+                ("prompt", Kind::StringOrArray),
+                ("echo", Kind::Bool),
+                // 0 with `echo` scores the prompt without generating anything
+                ("max_tokens", Kind::Integer),
+            ],
+        },
+        "/v1/embeddings" => Schema {
+            required: &["input"],
+            fields: &[
+                ("input", Kind::StringOrArray),
+                ("encoding_format", Kind::String),
+                ("dimensions", Kind::Count),
+                ("user", Kind::String),
+            ],
+        },
+        "/v1/images/generations" => Schema {
+            required: &["prompt"],
+            fields: &[
+                ("prompt", Kind::String),
+                ("n", Kind::Count),
+                ("size", Kind::String),
+                ("quality", Kind::String),
+                ("response_format", Kind::String),
+                ("user", Kind::String),
+            ],
+        },
+        "/v1/audio/speech" => Schema {
+            required: &["input", "voice"],
+            fields: &[
+                ("input", Kind::String),
+                ("voice", Kind::String),
+                ("speed", Kind::Number),
+                ("response_format", Kind::String),
+            ],
+        },
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// Reject obviously malformed requests to the main JSON endpoints before any key is spent.
+///
+/// Only shapes the upstream would refuse anyway are checked: required fields, field types
+/// and chat message roles. Other paths and non-JSON bodies are left to the upstream.
+pub fn validate_request(path: &str, body: &[u8]) -> ProxyResult<()> {
+    let Some(schema) = schema_for(path) else {
+        return Ok(());
+    };
+    let Ok(Value::Object(request)) = serde_json::from_slice::<Value>(body) else {
+        return Ok(());
+    };
+
+    let present = |field: &str| request.get(field).filter(|value| !value.is_null());
+    for field in schema.required {
+        if present(field).is_none() {
+            return Err(invalid(
+                format!("Missing required parameter: '{}'.", field),
+                field,
+            ));
+        }
+    }
+    let sampling = match path {
+        "/v1/chat/completions" | "/v1/completions" => SAMPLING_FIELDS,
+        _ => &[],
+    };
+    for (field, kind) in schema.fields.iter().chain(sampling) {
+        if let Some(value) = present(field) {
+            if !kind.accepts(value) {
+                return Err(invalid(
+                    format!(
+                        "Invalid type for '{}': expected {}.",
+                        field,
+                        kind.describe()
+                    ),
+                    field,
+                ));
+            }
+        }
+    }
+
+    if path == "/v1/chat/completions" {
+        validate_messages(&request)?;
+    }
+    Ok(())
+}
+
+fn validate_messages(request: &Map<String, Value>) -> ProxyResult<()> {
+    let messages = request["messages"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+    if messages.is_empty() {
+        return Err(invalid(
+            "'messages' must contain at least one message.",
+            "messages",
+        ));
+    }
+
+    for (index, message) in messages.iter().enumerate() {
+        let param = |field: &str| format!("messages.[{}].{}", index, field);
+        let Some(message) = message.as_object() else {
+            let param = format!("messages.[{}]", index);
+            return Err(invalid(
+                format!("Invalid type for '{}': expected an object.", param),
+                &param,
+            ));
+        };
+        let Some(role) = message.get("role").and_then(Value::as_str) else {
+            return Err(invalid(
+                format!("Missing required parameter: '{}'.", param("role")),
+                &param("role"),
+            ));
+        };
+        if !CHAT_ROLES.contains(&role) {
+            return Err(invalid(
+                format!(
+                    "Invalid value: '{}'. Supported values are: {}.",
+                    role,
+                    CHAT_ROLES
+                        .iter()
+                        .map(|role| format!("'{}'", role))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                &param("role"),
+            ));
+        }
+        if let Some(content) = message.get("content") {
+            if !(content.is_string() || content.is_array() || content.is_null()) {
+                return Err(invalid(
+                    format!(
+                        "Invalid type for '{}': expected a string or an array.",
+                        param("content")
+                    ),
+                    &param("content"),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>, param: &str) -> ProxyError {
+    ProxyError::InvalidRequest {
+        message: message.into(),
+        param: Some(param.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn check(path: &str, body: Value) -> Result<(), Option<String>> {
+        validate_request(path, body.to_string().as_bytes()).map_err(|e| match e {
+            ProxyError::InvalidRequest { param, .. } => param,
+            other => panic!("unexpected error {:?}", other),
+        })
+    }
+
+    #[test]
+    fn test_chat_completions() {
+        let path = "/v1/chat/completions";
+        assert!(check(
+            path,
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}]})
+        )
+        .is_ok());
+        assert_eq!(
+            check(path, json!({"model": "gpt-4o"})),
+            Err(Some("messages".to_string()))
+        );
+        assert_eq!(
+            check(path, json!({"model": "gpt-4o", "messages": []})),
+            Err(Some("messages".to_string()))
+        );
+        assert_eq!(
+            check(
+                path,
+                json!({"model": "gpt-4o", "messages": [{"role": "robot", "content": "hi"}]})
+            ),
+            Err(Some("messages.[0].role".to_string()))
+        );
+        assert_eq!(
+            check(
+                path,
+                json!({"model": "gpt-4o", "messages": [{"role": "user", "content": 1}]})
+            ),
+            Err(Some("messages.[0].content".to_string()))
+        );
+        assert_eq!(
+            check(
+                path,
+                json!({
+                    "model": "gpt-4o",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "temperature": "hot"
+                })
+            ),
+            Err(Some("temperature".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_null_fields_count_as_absent() {
+        let path = "/v1/chat/completions";
+        assert!(check(
+            path,
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "hi"}],
+                "temperature": null,
+                "stop": null,
+                "max_tokens": null,
+                "response_format": null,
+                "tools": null
+            })
+        )
+        .is_ok());
+        assert_eq!(
+            check("/v1/embeddings", json!({"model": "m", "input": null})),
+            Err(Some("input".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_other_endpoints() {
+        assert!(check("/v1/embeddings", json!({"model": "m", "input": ["a", "b"]})).is_ok());
+        assert_eq!(
+            check("/v1/embeddings", json!({"model": "m", "input": 3})),
+            Err(Some("input".to_string()))
+        );
+        assert_eq!(
+            check(
+                "/v1/images/generations",
+                json!({"model": "dall-e-3", "n": 0, "prompt": "x"})
+            ),
+            Err(Some("n".to_string()))
+        );
+        assert_eq!(
+            check("/v1/audio/speech", json!({"model": "tts-1", "input": "hi"})),
+            Err(Some("voice".to_string()))
+        );
+        assert!(check("/v1/completions", json!({"model": "m", "prompt": "x"})).is_ok());
+
+        // Scoring a prompt generates no tokens
+        assert!(check(
+            "/v1/completions",
+            json!({"model": "m", "prompt": "x", "echo": true, "logprobs": 1, "max_tokens": 0})
+        )
+        .is_ok());
+        assert_eq!(
+            check("/v1/completions", json!({"model": "m", "max_tokens": -1})),
+            Err(Some("max_tokens".to_string()))
+        );
+
+        // Unknown paths and non-JSON bodies are not checked
+        assert!(check("/v1/moderations", json!({"model": "m"})).is_ok());
+        assert!(validate_request("/v1/embeddings", b"not json").is_ok());
+    }
+}
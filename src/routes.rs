@@ -1,4 +1,5 @@
-use crate::proxy::ProxyHandler;
+use crate::proxy::{ProxyError, ProxyHandler};
+use crate::telemetry;
 use axum::{
     body::Body,
     extract::{Request, State},
@@ -7,10 +8,13 @@ use axum::{
     routing::{any, get},
     Router,
 };
+use http_body_util::LengthLimitError;
+use std::error::Error as _;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
 
@@ -35,7 +39,26 @@ pub fn create_router(
         )
         .layer(RequestBodyLimitLayer::new(body_limit))
         .layer(TimeoutLayer::new(request_timeout))
-        .layer(TraceLayer::new_for_http())
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request| {
+                let request_id = request
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("-");
+                let span = tracing::info_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    request_id = %request_id,
+                );
+                telemetry::set_parent_from_headers(&span, request.headers());
+                span
+            }),
+        )
+        // Accept the client's x-request-id or generate one, and echo it on every response
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
 
 /// Handler that processes all proxy requests
@@ -49,9 +72,17 @@ async fn proxy_request_handler(
     // Extract body from request
     let body = match axum::body::to_bytes(request.into_body(), usize::MAX).await {
         Ok(bytes) => bytes,
+        Err(e) if exceeds_body_limit(&e) => {
+            tracing::warn!("Request body exceeds the configured limit");
+            return Ok(ProxyError::PayloadTooLarge.into_response());
+        }
         Err(e) => {
             tracing::error!("Failed to read request body: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+            return Ok(ProxyError::InvalidRequest {
+                message: format!("Failed to read request body: {}", e),
+                param: None,
+            }
+            .into_response());
         }
     };
 
@@ -65,6 +96,19 @@ async fn proxy_request_handler(
     }
 }
 
+/// Whether reading the body failed because `RequestBodyLimitLayer` cut it off, which happens
+/// when a body without a `Content-Length` grows past the limit
+fn exceeds_body_limit(error: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = error.source();
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,6 +126,7 @@ mod tests {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         };
 
         let key_pool = Arc::new(KeyPool::new(vec![key], "round_robin"));
@@ -120,4 +165,50 @@ mod tests {
         // Should return method not allowed since we only accept POST
         assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[tokio::test]
+    async fn test_oversized_streamed_body_returns_payload_too_large() {
+        let app = create_test_app();
+
+        // Streamed without a Content-Length, so the limit is only hit while reading
+        let chunks: Vec<Result<bytes::Bytes, std::io::Error>> =
+            vec![Ok(bytes::Bytes::from(vec![b' '; 1024 * 1024 + 1]))];
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "kcp_payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_body_returns_error_envelope() {
+        let app = create_test_app();
+
+        let chunks: Vec<Result<bytes::Bytes, std::io::Error>> = vec![
+            Ok(bytes::Bytes::from_static(b"{\"model\": ")),
+            Err(std::io::Error::other("connection reset")),
+        ];
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(body["error"]["code"], "kcp_invalid_request");
+    }
 }
@@ -0,0 +1,178 @@
+use crate::config::{LogFormat, ObservabilityConfig};
+use anyhow::{Context, Result};
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// `RUST_LOG` when set, otherwise `tracing_level` for this crate, its HTTP layer and access logs.
+///
+/// `tracing_level` is either a bare level such as `debug`, or full filter directives.
+pub fn env_filter(config: &ObservabilityConfig) -> EnvFilter {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return filter;
+    }
+    let level = config.tracing_level.trim();
+    let directives = if level.parse::<tracing::Level>().is_ok() {
+        format!("key_cycle_proxy={0},tower_http={0},access_log=info", level)
+    } else {
+        level.to_string()
+    };
+    EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+        eprintln!("Invalid tracing_level '{}': {}", directives, e);
+        EnvFilter::new("key_cycle_proxy=info,tower_http=info,access_log=info")
+    })
+}
+
+/// Log line formatter for the configured `log_format`
+pub fn fmt_layer<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    match format {
+        LogFormat::Full => fmt::layer().boxed(),
+        LogFormat::Compact => fmt::layer().compact().boxed(),
+        LogFormat::Pretty => fmt::layer().pretty().boxed(),
+        LogFormat::Json => fmt::layer().json().flatten_event(true).boxed(),
+    }
+}
+
+/// OTLP trace export, or `None` when no collector endpoint is configured.
+///
+/// Also installs the W3C trace-context propagator, so `traceparent` is read from clients
+/// and sent upstream only while traces are being exported.
+pub fn tracer_provider(config: &ObservabilityConfig) -> Result<Option<SdkTracerProvider>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .context("Failed to create OTLP span exporter")?;
+    let sampler = Sampler::TraceIdRatioBased(config.trace_sample_ratio.clamp(0.0, 1.0));
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(sampler)))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
+}
+
+/// Tracing layer exporting every span through `provider`
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("key-cycle-proxy"))
+}
+
+/// Continue the client's trace, if its request carries a `traceparent`
+pub fn set_parent_from_headers(span: &Span, headers: &axum::http::HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&AxumHeaders(headers)));
+    // Fails only when no tracing subscriber is installed
+    let _ = span.set_parent(parent);
+}
+
+/// Add the current span's `traceparent` to an upstream request
+pub fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut ReqwestHeaders(headers))
+    });
+}
+
+struct AxumHeaders<'a>(&'a axum::http::HeaderMap);
+
+impl Extractor for AxumHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct ReqwestHeaders<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for ReqwestHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_exported_and_propagated() {
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+
+        let config = ObservabilityConfig {
+            otlp_endpoint: Some(collector.uri()),
+            ..Default::default()
+        };
+        let provider = tracer_provider(&config).unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // A client traceparent is continued, and handed on to the upstream
+        let mut client_headers = axum::http::HeaderMap::new();
+        client_headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let span = tracing::info_span!("request");
+        set_parent_from_headers(&span, &client_headers);
+        let mut upstream_headers = reqwest::header::HeaderMap::new();
+        span.in_scope(|| {
+            tracing::info_span!("upstream_attempt")
+                .in_scope(|| inject_trace_context(&mut upstream_headers))
+        });
+        drop(span);
+
+        let traceparent = upstream_headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap()
+            .unwrap();
+        let requests = collector.received_requests().await.unwrap();
+        assert!(requests.iter().any(|r| !r.body.is_empty()));
+    }
+}
@@ -10,30 +10,16 @@ pub struct OpenAIRequest {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[allow(dead_code)]
 pub struct OpenAIError {
     pub error: OpenAIErrorDetails,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[allow(dead_code)]
 pub struct OpenAIErrorDetails {
     pub message: String,
     #[serde(rename = "type")]
     pub error_type: Option<String>,
+    #[serde(default)]
+    pub param: Option<String>,
     pub code: Option<String>,
 }
-
-/// Standard error response format
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
-}
-
-impl ErrorResponse {
-    pub fn new(message: impl Into<String>) -> Self {
-        Self {
-            error: message.into(),
-        }
-    }
-}
@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub fn convert_axum_method_to_reqwest(method: &axum::http::Method) -> reqwest::Method {
     match *method {
         axum::http::Method::GET => reqwest::Method::GET,
@@ -22,7 +24,7 @@ pub fn convert_axum_headers_to_reqwest(
             reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes()),
             reqwest::header::HeaderValue::from_bytes(value.as_bytes()),
         ) {
-            reqwest_headers.insert(req_name, req_value);
+            reqwest_headers.append(req_name, req_value);
         }
     }
 
@@ -39,9 +41,25 @@ pub fn convert_reqwest_headers_to_axum(
             axum::http::HeaderName::from_bytes(name.as_str().as_bytes()),
             axum::http::HeaderValue::from_bytes(value.as_bytes()),
         ) {
-            axum_headers.insert(axum_name, axum_value);
+            axum_headers.append(axum_name, axum_value);
         }
     }
 
     axum_headers
 }
+
+/// Seconds since the Unix epoch
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Milliseconds since the Unix epoch
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
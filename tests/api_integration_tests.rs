@@ -4,16 +4,31 @@ use axum::{
     Router,
 };
 use key_cycle_proxy::{
-    config::{ApiKeyInfo, UpstreamConfig},
-    proxy::{KeyPool, ProxyEngine, ProxyHandler, UpstreamClient},
+    config::{
+        AdmissionConfig, ApiKeyInfo, AuditConfig, AzureSettings, BatchingConfig, CacheConfig,
+        GeminiAuth, GeminiSettings, ProbesConfig, Provider, StickyTarget, TransformsConfig,
+        UpstreamConfig,
+    },
+    proxy::{
+        admission::AdmissionQueue,
+        audit::AuditLog,
+        batch::EmbeddingBatcher,
+        cache::ResponseCache,
+        coalesce::Coalescer,
+        health::AttemptOutcome,
+        models::{ModelAliases, ModelFallbacks},
+        probe::HealthProber,
+        transform::Transforms,
+        KeyPool, ProxyEngine, ProxyHandler, UpstreamClient,
+    },
     routes::create_router,
 };
 use secrecy::SecretString;
 use serde_json::json;
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tower::ServiceExt;
 use wiremock::{
-    matchers::{header, method, path},
+    matchers::{body_partial_json, header, method, path, query_param},
     Mock, MockServer, ResponseTemplate,
 };
 
@@ -31,6 +46,7 @@ async fn create_test_app_with_mocks() -> (Router, MockServer, MockServer) {
             models: vec!["gpt-3.5-turbo".to_string(), "gpt-4".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
         ApiKeyInfo {
             key: SecretString::new("sk-test-key-2".to_string()),
@@ -38,6 +54,7 @@ async fn create_test_app_with_mocks() -> (Router, MockServer, MockServer) {
             models: vec!["others".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
     ];
 
@@ -191,6 +208,8 @@ async fn test_api_key_rotation_on_rate_limit() {
 
     // Should succeed due to key rotation
     assert_eq!(response.status(), StatusCode::OK);
+    // Attempts are only reported when enabled
+    assert!(!response.headers().contains_key("x-kcp-attempts"));
 
     let body = axum::body::to_bytes(response.into_body(), usize::MAX)
         .await
@@ -399,10 +418,55 @@ async fn test_api_malformed_json() {
         .await
         .unwrap();
     let response_json: serde_json::Value = serde_json::from_slice(&body).unwrap();
-    assert!(response_json["error"]
+    assert!(response_json["error"]["message"]
         .as_str()
         .unwrap()
         .contains("Invalid JSON"));
+    assert_eq!(response_json["error"]["type"], "invalid_request_error");
+    assert_eq!(response_json["error"]["code"], "kcp_invalid_json");
+}
+
+#[tokio::test]
+async fn test_api_last_upstream_error_passes_through() {
+    let (app, mock_server_1, mock_server_2) = create_test_app_with_mocks().await;
+
+    let upstream_error = json!({
+        "error": {
+            "message": "Rate limit reached for gpt-3.5-turbo",
+            "type": "requests",
+            "param": null,
+            "code": "rate_limit_exceeded"
+        }
+    });
+    for server in [&mock_server_1, &mock_server_2] {
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(upstream_error.clone()))
+            .mount(server)
+            .await;
+    }
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [{"role": "user", "content": "hi"}]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response_json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response_json, upstream_error);
 }
 
 #[tokio::test]
@@ -482,3 +546,1387 @@ async fn test_api_concurrent_requests() {
         assert_eq!(response.status(), StatusCode::OK);
     }
 }
+
+#[tokio::test]
+async fn test_api_azure_deployment_routing() {
+    let mock_server = MockServer::start().await;
+
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("azure-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["gpt-4o".to_string()],
+        provider: Provider::Azure(AzureSettings {
+            api_version: "2024-06-01".to_string(),
+            deployments: HashMap::from([("gpt-4o".to_string(), "prod-gpt4o".to_string())]),
+        }),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 1));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/openai/deployments/prod-gpt4o/chat/completions"))
+        .and(query_param("api-version", "2024-06-01"))
+        .and(header("api-key", "azure-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "chatcmpl-azure",
+            "object": "chat.completion",
+            "model": "gpt-4o",
+            "choices": []
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello Azure"}]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_gemini_chat_completion_translation() {
+    let mock_server = MockServer::start().await;
+
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("gemini-key".to_string()),
+        url: format!("{}/v1beta", mock_server.uri()),
+        models: vec!["gemini-1.5-flash".to_string()],
+        provider: Provider::Gemini(GeminiSettings {
+            auth: GeminiAuth::Header,
+        }),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 1));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/v1beta/models/gemini-1.5-flash:generateContent"))
+        .and(header("x-goog-api-key", "gemini-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Hello from Gemini"}]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 4, "totalTokenCount": 7}
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gemini-1.5-flash",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response_json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response_json["object"], "chat.completion");
+    assert_eq!(response_json["model"], "gemini-1.5-flash");
+    assert_eq!(
+        response_json["choices"][0]["message"]["content"],
+        "Hello from Gemini"
+    );
+    assert_eq!(response_json["usage"]["total_tokens"], 7);
+}
+
+#[tokio::test]
+async fn test_api_model_alias_rewrite() {
+    let mock_server = MockServer::start().await;
+
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-alias-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["gpt-4o-2024-08-06".to_string()],
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 1).with_model_aliases(ModelAliases::new(
+            HashMap::from([("gpt-4".to_string(), "gpt-4o-2024-08-06".to_string())]),
+        )),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({"model": "gpt-4o-2024-08-06"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "chatcmpl-alias",
+            "object": "chat.completion",
+            "model": "gpt-4o-2024-08-06",
+            "choices": []
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gpt-4",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_per_key_model_aliases() {
+    let openai = MockServer::start().await;
+    let other = MockServer::start().await;
+
+    // Both keys serve `gpt-4` but forward it under different names
+    let keys = vec![
+        ApiKeyInfo {
+            key: SecretString::new("sk-openai".to_string()),
+            url: openai.uri(),
+            models: vec!["gpt-4".to_string()],
+            aliases: HashMap::from([("gpt-4".to_string(), "gpt-4o-2024-08-06".to_string())]),
+            ..Default::default()
+        },
+        ApiKeyInfo {
+            key: SecretString::new("sk-other".to_string()),
+            url: other.uri(),
+            models: vec!["gpt-4".to_string()],
+            aliases: HashMap::from([("gpt-4".to_string(), "gpt-4-turbo".to_string())]),
+            ..Default::default()
+        },
+    ];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 1));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    for (server, model) in [(&openai, "gpt-4o-2024-08-06"), (&other, "gpt-4-turbo")] {
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"model": model})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "chat.completion",
+                "model": model,
+                "choices": []
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    let mut served = Vec::new();
+    for _ in 0..2 {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({
+                    "model": "gpt-4",
+                    "messages": [{"role": "user", "content": "Hello"}]
+                })
+                .to_string(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        served.push(
+            response.headers()["x-kcp-model"]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+    }
+    served.sort();
+    assert_eq!(served, ["gpt-4-turbo", "gpt-4o-2024-08-06"]);
+}
+
+#[tokio::test]
+async fn test_api_model_fallback_chain() {
+    let primary = MockServer::start().await;
+    let fallback = MockServer::start().await;
+    let unrelated = MockServer::start().await;
+
+    let keys = vec![
+        ApiKeyInfo {
+            key: SecretString::new("sk-primary".to_string()),
+            url: primary.uri(),
+            models: vec!["gpt-4o".to_string()],
+            ..Default::default()
+        },
+        ApiKeyInfo {
+            key: SecretString::new("sk-unrelated".to_string()),
+            url: unrelated.uri(),
+            models: vec!["gpt-3.5-turbo".to_string()],
+            ..Default::default()
+        },
+        ApiKeyInfo {
+            key: SecretString::new("sk-fallback".to_string()),
+            url: fallback.uri(),
+            models: vec!["gpt-4o-mini".to_string()],
+            ..Default::default()
+        },
+    ];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig {
+        max_retries: 0,
+        ..Default::default()
+    })
+    .unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 3).with_model_fallbacks(ModelFallbacks::new(
+            HashMap::from([("gpt-4o".to_string(), vec!["gpt-4o-mini".to_string()])]),
+        )),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(429))
+        .expect(1)
+        .mount(&primary)
+        .await;
+    // Retries must stay on keys that serve the requested model
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&unrelated)
+        .await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({"model": "gpt-4o-mini"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "object": "chat.completion",
+            "model": "gpt-4o-mini",
+            "choices": []
+        })))
+        .expect(1)
+        .mount(&fallback)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-kcp-model"], "gpt-4o-mini");
+}
+
+#[tokio::test]
+async fn test_api_response_cache_hit() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-cache".to_string()),
+        url: mock_server.uri(),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let cache = ResponseCache::new(&CacheConfig {
+        enabled: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 3).with_cache(cache));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    // Only the first request may reach the upstream
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "object": "chat.completion",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "cached"}}]
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let send = |body: serde_json::Value| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let cache_status = response.headers()["x-kcp-cache"].clone();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (cache_status, body)
+        }
+    };
+
+    let (status, first) = send(json!({
+        "model": "gpt-4o",
+        "temperature": 0,
+        "messages": [{"role": "user", "content": "Hello"}]
+    }))
+    .await;
+    assert_eq!(status, "miss");
+
+    // The entry is stored once the body has been fully streamed
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Same request with a different key order hits the cache
+    let (status, second) = send(json!({
+        "messages": [{"content": "Hello", "role": "user"}],
+        "temperature": 0,
+        "model": "gpt-4o"
+    }))
+    .await;
+    assert_eq!(status, "hit");
+    assert_eq!(first, second);
+}
+
+#[tokio::test]
+async fn test_api_coalesces_identical_embeddings() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-coalesce".to_string()),
+        url: mock_server.uri(),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 3)
+            .with_coalescer(Coalescer::new(["/v1/embeddings".to_string()])),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    // Concurrent identical requests share a single upstream call
+    Mock::given(method("POST"))
+        .and(path("/v1/embeddings"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(200))
+                .set_body_json(json!({
+                    "object": "list",
+                    "data": [{"object": "embedding", "index": 0, "embedding": [0.1, 0.2]}],
+                    "model": "text-embedding-3-small"
+                })),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let requests = (0..4).map(|_| {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/embeddings")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"model": "text-embedding-3-small", "input": "hello"}).to_string(),
+            ))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        }
+    });
+    let bodies = futures::future::join_all(requests).await;
+
+    let first: serde_json::Value = serde_json::from_slice(&bodies[0]).unwrap();
+    assert_eq!(first["data"][0]["embedding"][1], 0.2);
+    assert!(bodies.iter().all(|body| body == &bodies[0]));
+}
+
+/// Answers embeddings requests with `[len(input)]` per input, in order
+struct EchoEmbeddings;
+
+impl wiremock::Respond for EchoEmbeddings {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let inputs = body["input"].as_array().cloned().unwrap_or_default();
+        let data: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                json!({
+                    "object": "embedding",
+                    "index": index,
+                    "embedding": [input.as_str().unwrap().len()]
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(json!({
+            "object": "list",
+            "data": data,
+            "model": body["model"],
+            "usage": {"prompt_tokens": 12, "total_tokens": 12}
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_api_batches_concurrent_embeddings() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-batch".to_string()),
+        url: mock_server.uri(),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 3).with_batcher(
+        EmbeddingBatcher::new(&BatchingConfig {
+            enabled: true,
+            window_ms: 100,
+            max_inputs: 16,
+        }),
+    ));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/v1/embeddings"))
+        .respond_with(EchoEmbeddings)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let inputs = ["a", "bb", "cccccc"];
+    let requests = inputs.iter().map(|input| {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/v1/embeddings")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"model": "text-embedding-3-small", "input": input}).to_string(),
+            ))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    });
+    let responses = futures::future::join_all(requests).await;
+
+    // Each caller gets back only its own embedding, re-indexed from zero
+    let mut prompt_tokens = 0;
+    for (input, response) in inputs.iter().zip(&responses) {
+        assert_eq!(response["data"].as_array().unwrap().len(), 1);
+        assert_eq!(response["data"][0]["index"], 0);
+        assert_eq!(response["data"][0]["embedding"][0], input.len());
+        prompt_tokens += response["usage"]["prompt_tokens"].as_u64().unwrap();
+    }
+    assert_eq!(prompt_tokens, 12);
+}
+
+fn embeddings_app(mock_server: &MockServer) -> axum::Router {
+    let keys = ["sk-batch-1", "sk-batch-2"]
+        .into_iter()
+        .map(|key| ApiKeyInfo {
+            key: SecretString::new(key.to_string()),
+            url: mock_server.uri(),
+            ..Default::default()
+        })
+        .collect();
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig {
+        max_retries: 0,
+        ..Default::default()
+    })
+    .unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 3).with_batcher(
+        EmbeddingBatcher::new(&BatchingConfig {
+            enabled: true,
+            window_ms: 100,
+            max_inputs: 16,
+        }),
+    ));
+    create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    )
+}
+
+fn embeddings_request(input: &str, organization: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri("/v1/embeddings")
+        .header("content-type", "application/json")
+        .header("openai-organization", organization)
+        .body(Body::from(
+            json!({"model": "text-embedding-3-small", "input": input}).to_string(),
+        ))
+        .unwrap()
+}
+
+fn single_embedding() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "object": "list",
+        "data": [{"object": "embedding", "index": 0, "embedding": [0.5]}],
+        "model": "text-embedding-3-small",
+        "usage": {"prompt_tokens": 1, "total_tokens": 1}
+    }))
+}
+
+#[tokio::test]
+async fn test_api_rejected_batch_is_sent_one_by_one() {
+    let mock_server = MockServer::start().await;
+    let app = embeddings_app(&mock_server);
+
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({"input": "good"})))
+        .respond_with(single_embedding())
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    // On its own the bad input is an ordinary 400, retried on the other key
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({"input": "bad"})))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+    // The merged call is rejected once, without trying the other key
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let statuses = futures::future::join_all(["good", "bad"].map(|input| {
+        let app = app.clone();
+        async move {
+            app.oneshot(embeddings_request(input, "org-a"))
+                .await
+                .unwrap()
+                .status()
+        }
+    }))
+    .await;
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+}
+
+#[tokio::test]
+async fn test_api_batches_only_matching_forwarded_headers() {
+    let mock_server = MockServer::start().await;
+    let app = embeddings_app(&mock_server);
+
+    for (input, organization) in [("a", "org-a"), ("b", "org-b")] {
+        Mock::given(method("POST"))
+            .and(header("openai-organization", organization))
+            .and(body_partial_json(json!({"input": input})))
+            .respond_with(single_embedding())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+    }
+
+    let statuses = futures::future::join_all([("a", "org-a"), ("b", "org-b")].map(
+        |(input, organization)| {
+            let app = app.clone();
+            async move {
+                app.oneshot(embeddings_request(input, organization))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        },
+    ))
+    .await;
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::OK]);
+}
+
+#[tokio::test]
+async fn test_api_client_accept_encoding_is_not_forwarded() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-encoding".to_string()),
+        url: mock_server.uri(),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let cache = ResponseCache::new(&CacheConfig {
+        enabled: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 3)
+            .with_cache(cache)
+            .with_batcher(EmbeddingBatcher::new(&BatchingConfig {
+                enabled: true,
+                window_ms: 100,
+                max_inputs: 16,
+            })),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    // Only an uncompressed request is answered, and only once
+    Mock::given(method("POST"))
+        .and(path("/v1/embeddings"))
+        .and(header("accept-encoding", "identity"))
+        .respond_with(EchoEmbeddings)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let send = |input: &'static str| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/v1/embeddings")
+                .header("content-type", "application/json")
+                .header("accept-encoding", "gzip, deflate, br")
+                .body(Body::from(
+                    json!({"model": "text-embedding-3-small", "input": input}).to_string(),
+                ))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get("content-encoding").is_none());
+            let cache_status = response.headers()["x-kcp-cache"].clone();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (cache_status, body)
+        }
+    };
+
+    // Merged into one upstream call, and split back per caller
+    let responses = futures::future::join_all([send("a"), send("bb")]).await;
+    for ((status, body), input) in responses.iter().zip(["a", "bb"]) {
+        assert_eq!(status, "miss");
+        assert_eq!(body["data"][0]["embedding"][0], input.len());
+    }
+
+    // The entry is stored once the body has been fully streamed
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let (status, body) = send("bb").await;
+    assert_eq!(status, "hit");
+    assert_eq!(body["data"][0]["embedding"][0], 2);
+}
+
+#[tokio::test]
+async fn test_api_admission_sheds_when_saturated() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-admission".to_string()),
+        url: mock_server.uri(),
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 3));
+    let mut admission = AdmissionConfig {
+        enabled: true,
+        max_concurrent: 1,
+        max_wait_ms: 50,
+        ..Default::default()
+    };
+    admission.classes.get_mut("batch").unwrap().max_queued = 0;
+    let handler =
+        ProxyHandler::new(engine).with_admission(AdmissionQueue::new(&admission).unwrap());
+    let app = create_router(Arc::new(handler), 1024 * 1024, Duration::from_secs(30));
+
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(300))
+                .set_body_json(json!({"object": "chat.completion", "choices": []})),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let request = |priority: &str| {
+        Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("x-kcp-priority", priority)
+            .body(Body::from(
+                json!({"model": "gpt-4o", "messages": []}).to_string(),
+            ))
+            .unwrap()
+    };
+
+    let first = tokio::spawn(app.clone().oneshot(request("interactive")));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Batch has no queue room at all; interactive waits longer than max_wait_ms
+    let batch = app.clone().oneshot(request("batch")).await.unwrap();
+    assert_eq!(batch.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let interactive = app.clone().oneshot(request("interactive")).await.unwrap();
+    assert_eq!(interactive.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let first = first.await.unwrap().unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_sticky_session_routing() {
+    let servers = vec![
+        MockServer::start().await,
+        MockServer::start().await,
+        MockServer::start().await,
+    ];
+    let keys = servers
+        .iter()
+        .enumerate()
+        .map(|(i, server)| ApiKeyInfo {
+            key: SecretString::new(format!("sk-sticky-{}", i)),
+            url: server.uri(),
+            ..Default::default()
+        })
+        .collect();
+    for server in &servers {
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+            .mount(server)
+            .await;
+    }
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 3)
+            .with_sticky_routing("x-kcp-session", StickyTarget::Key),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    let send = |session: Option<&'static str>, user: &'static str| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json");
+        if let Some(session) = session {
+            request = request.header("x-kcp-session", session);
+        }
+        let request = request
+            .body(Body::from(
+                json!({"model": "gpt-4o", "messages": [], "user": user}).to_string(),
+            ))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    };
+
+    async fn hits(servers: &[MockServer]) -> Vec<usize> {
+        let mut hits = Vec::new();
+        for server in servers {
+            hits.push(server.received_requests().await.unwrap().len());
+        }
+        hits
+    }
+
+    // The header wins over the body `user` field, which varies here
+    for user in ["a", "b", "c", "d", "e", "f"] {
+        send(Some("conversation-1"), user).await;
+    }
+    let after_header = hits(&servers).await;
+    assert_eq!(after_header.iter().filter(|&&n| n == 6).count(), 1);
+
+    // Without the header the body `user` field pins the session
+    for _ in 0..6 {
+        send(None, "user-7").await;
+    }
+    let after_user = hits(&servers).await;
+    let moved: Vec<_> = after_user
+        .iter()
+        .zip(&after_header)
+        .map(|(after, before)| after - before)
+        .collect();
+    assert_eq!(moved.iter().filter(|&&n| n == 6).count(), 1);
+}
+
+#[tokio::test]
+async fn test_api_health_probe_takes_rejected_key_out() {
+    let revoked = MockServer::start().await;
+    let healthy = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .and(header("authorization", "Bearer sk-revoked"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&revoked)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .and(header("authorization", "Bearer sk-healthy"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"data": []})))
+        .expect(1)
+        .mount(&healthy)
+        .await;
+
+    let keys = vec![
+        ApiKeyInfo {
+            key: SecretString::new("sk-revoked".to_string()),
+            url: revoked.uri(),
+            ..Default::default()
+        },
+        ApiKeyInfo {
+            key: SecretString::new("sk-healthy".to_string()),
+            url: healthy.uri(),
+            ..Default::default()
+        },
+    ];
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin_health_weighted"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let prober =
+        HealthProber::new(key_pool.clone(), upstream_client, &ProbesConfig::default()).unwrap();
+
+    assert_eq!(prober.probe_key(0).await, Some(AttemptOutcome::Rejected));
+    assert_eq!(prober.probe_key(1).await, Some(AttemptOutcome::Success));
+
+    let all_keys = key_pool.get_all_keys();
+    assert!(!key_pool.is_available(&all_keys[0]));
+    assert!(key_pool.is_available(&all_keys[1]));
+    for _ in 0..4 {
+        let key = key_pool.get_key_for_model("gpt-4").unwrap();
+        assert_eq!(key.url, healthy.uri());
+    }
+}
+
+#[tokio::test]
+async fn test_api_idle_keys_are_probed_with_their_own_credentials() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v1/models"))
+        .and(header("authorization", "Bearer sk-idle"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"data": []})))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    // A key with recent traffic is measured passively, not probed
+    Mock::given(method("GET"))
+        .and(header("authorization", "Bearer sk-busy"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let keys = ["sk-busy", "sk-idle"]
+        .into_iter()
+        .map(|key| ApiKeyInfo {
+            key: SecretString::new(key.to_string()),
+            url: mock_server.uri(),
+            ..Default::default()
+        })
+        .collect();
+    let key_pool = Arc::new(KeyPool::new(keys, "least_latency"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let prober =
+        HealthProber::new(key_pool.clone(), upstream_client, &ProbesConfig::default()).unwrap();
+
+    let busy = key_pool.get_all_keys()[0].clone();
+    key_pool.record_first_byte(&busy, "gpt-4", Duration::from_millis(500));
+    prober.probe_idle_keys().await;
+
+    // The probed key now has a number of its own, ahead of the busy key's slower one
+    let key = key_pool.get_key_for_model("gpt-4").unwrap();
+    assert_eq!(key.key_id(), "...idle");
+}
+
+#[tokio::test]
+async fn test_api_header_filtering_keeps_pool_key() {
+    let (app, mock_server_1, _mock_server_2) = create_test_app_with_mocks().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer sk-test-key-1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"id": "chatcmpl-test", "choices": []}))
+                .append_header("set-cookie", "a=1")
+                .append_header("set-cookie", "b=2"),
+        )
+        .expect(1)
+        .mount(&mock_server_1)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .header("authorization", "Bearer client-token")
+        .header("cookie", "session=abc")
+        .header("connection", "x-hop")
+        .header("x-hop", "1")
+        .header("openai-beta", "assistants=v2")
+        .body(Body::from(
+            json!({"model": "gpt-3.5-turbo", "messages": []}).to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies: Vec<_> = response.headers().get_all("set-cookie").iter().collect();
+    assert_eq!(cookies, ["a=1", "b=2"]);
+
+    let received = mock_server_1.received_requests().await.unwrap();
+    let upstream = &received[0].headers;
+    assert_eq!(upstream.get_all("authorization").iter().count(), 1);
+    assert!(upstream.get("cookie").is_none());
+    assert!(upstream.get("x-hop").is_none());
+    assert_eq!(upstream["openai-beta"], "assistants=v2");
+}
+
+#[tokio::test]
+async fn test_api_per_key_extra_headers() {
+    let mock_server = MockServer::start().await;
+    let mut extra_headers = reqwest::header::HeaderMap::new();
+    extra_headers.insert("openai-organization", "org-pool".parse().unwrap());
+    extra_headers.insert("openai-project", "proj_pool".parse().unwrap());
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-org-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["others".to_string()],
+        extra_headers,
+        ..Default::default()
+    }];
+
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 0));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer sk-org-key"))
+        .and(header("openai-organization", "org-pool"))
+        .and(header("openai-project", "proj_pool"))
+        .and(header("openai-beta", "assistants=v2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    // The key's organization replaces the client's; other client headers pass through
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .header("openai-organization", "org-client")
+        .header("openai-beta", "assistants=v2")
+        .body(Body::from(
+            json!({"model": "gpt-4o", "messages": []}).to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_request_and_response_transforms() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-test-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["others".to_string()],
+        ..Default::default()
+    }];
+
+    let transforms: TransformsConfig = toml::from_str(
+        r#"
+        [[request]]
+        paths = ["/v1/chat/completions"]
+        remove = ["logit_bias"]
+        set = { user = "org-default" }
+        max_tokens = 256
+
+        [[response]]
+        remove = ["system_fingerprint"]
+        "#,
+    )
+    .unwrap();
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(
+        ProxyEngine::new(key_pool, upstream_client, 0)
+            .with_transforms(Transforms::new(&transforms).unwrap()),
+    );
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(
+            json!({"user": "org-default", "max_tokens": 256}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "chatcmpl-1",
+            "system_fingerprint": "fp_123",
+            "choices": []
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "model": "gpt-4o",
+                "messages": [],
+                "logit_bias": {"50256": -100},
+                "max_tokens": 8192
+            })
+            .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body, json!({"id": "chatcmpl-1", "choices": []}));
+
+    let received = mock_server.received_requests().await.unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+    assert!(sent.get("logit_bias").is_none());
+}
+
+#[tokio::test]
+async fn test_api_validation_rejects_locally() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-test-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["others".to_string()],
+        ..Default::default()
+    }];
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 2).with_validation());
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"model": "gpt-4o", "messages": [{"role": "robot", "content": "hi"}]})
+                .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert_eq!(body["error"]["param"], "messages.[0].role");
+}
+
+#[tokio::test]
+async fn test_api_exhausted_retries_keep_upstream_headers() {
+    let mock_server = MockServer::start().await;
+    let keys = ["sk-test-key-1", "sk-test-key-2"]
+        .into_iter()
+        .map(|key| ApiKeyInfo {
+            key: SecretString::new(key.to_string()),
+            url: mock_server.uri(),
+            models: vec!["others".to_string()],
+            ..Default::default()
+        })
+        .collect();
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 2).with_attempts_header());
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(429)
+                .insert_header("retry-after", "20")
+                .set_body_json(
+                    json!({"error": {"message": "slow down", "code": "rate_limit_exceeded"}}),
+                ),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}]}).to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "20");
+    let mut attempts: Vec<_> = response.headers()["x-kcp-attempts"]
+        .to_str()
+        .unwrap()
+        .split(", ")
+        .map(String::from)
+        .collect();
+    attempts.sort();
+    assert_eq!(attempts, ["...ey-1=429", "...ey-2=429"]);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "rate_limit_exceeded");
+}
+
+#[tokio::test]
+async fn test_api_request_id_round_trip() {
+    let (app, mock_server_1, _mock_server_2) = create_test_app_with_mocks().await;
+
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("x-request-id", "req-client-1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("x-request-id", "req-upstream-1")
+                .set_body_json(json!({"object": "chat.completion", "choices": []})),
+        )
+        .expect(1)
+        .mount(&mock_server_1)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .header("x-request-id", "req-client-1")
+        .body(Body::from(
+            json!({"model": "gpt-3.5-turbo", "messages": [{"role": "user", "content": "hi"}]})
+                .to_string(),
+        ))
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-request-id"], "req-client-1");
+    assert_eq!(
+        response.headers()["x-upstream-request-id"],
+        "req-upstream-1"
+    );
+
+    // Without a client id one is generated, and proxy errors carry it too
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from("invalid json"))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let generated = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(generated).is_ok());
+}
+
+#[tokio::test]
+async fn test_api_audit_log_redacts_key_secrets() {
+    let mock_server = MockServer::start().await;
+    let keys = vec![ApiKeyInfo {
+        key: SecretString::new("sk-test-key".to_string()),
+        url: mock_server.uri(),
+        models: vec!["others".to_string()],
+        ..Default::default()
+    }];
+    let dir = tempfile::tempdir().unwrap();
+    let audit = AuditLog::new(
+        &AuditConfig {
+            enabled: true,
+            dir: dir.path().display().to_string(),
+            ..Default::default()
+        },
+        vec!["sk-test-key".to_string()],
+    )
+    .unwrap();
+    let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let engine = Arc::new(ProxyEngine::new(key_pool, upstream_client, 2).with_audit(audit));
+    let app = create_router(
+        Arc::new(ProxyHandler::new(engine)),
+        1024 * 1024,
+        Duration::from_secs(30),
+    );
+
+    // An upstream that echoes the key it was sent
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "key sk-test-key"}}]
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .header("authorization", "Bearer client-token")
+        .header("x-request-id", "req-audit-1")
+        .body(Body::from(
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}]}).to_string(),
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    let mut record = None;
+    for _ in 0..100 {
+        if let Some(entry) = std::fs::read_dir(dir.path()).unwrap().next() {
+            let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            if let Some(line) = text.lines().next() {
+                record = Some(serde_json::from_str::<serde_json::Value>(line).unwrap());
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let record = record.expect("audit record written");
+    assert_eq!(record["request_id"], "req-audit-1");
+    assert_eq!(record["status"], 200);
+    assert_eq!(record["request"]["headers"]["authorization"], "[REDACTED]");
+    assert_eq!(
+        record["response"]["body"]["choices"][0]["message"]["content"],
+        "key [REDACTED]"
+    );
+    assert!(!record.to_string().contains("sk-test-key"));
+}
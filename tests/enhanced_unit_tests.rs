@@ -1,7 +1,10 @@
 use key_cycle_proxy::{
-    config::{load_config, ApiKeyInfo, Config, UpstreamConfig},
-    proxy::{KeyPool, ProxyEngine, ProxyError, UpstreamClient},
-    types::{ErrorResponse, OpenAIRequest},
+    config::{
+        load_config, ApiKeyInfo, Config, GeminiAuth, GeminiSettings, LegacyApiKeyInfo,
+        ProbesConfig, Provider, UpstreamConfig,
+    },
+    proxy::{probe::HealthProber, KeyPool, ProxyEngine, ProxyError, UpstreamClient},
+    types::{OpenAIError, OpenAIRequest},
 };
 use secrecy::SecretString;
 use std::io::Write;
@@ -89,6 +92,7 @@ fn test_api_key_model_support() {
         models: vec!["gpt-3.5-turbo".to_string(), "gpt-4".to_string()],
         latency: None,
         health_score: 1.0,
+        ..Default::default()
     };
 
     assert!(key_info.supports_model("gpt-3.5-turbo"));
@@ -101,6 +105,7 @@ fn test_api_key_model_support() {
         models: vec!["others".to_string()],
         latency: None,
         health_score: 1.0,
+        ..Default::default()
     };
 
     assert!(fallback_key.supports_model("any-model"));
@@ -133,6 +138,7 @@ fn test_key_pool_rotation_strategies() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
         ApiKeyInfo {
             key: SecretString::new("key2".to_string()),
@@ -140,6 +146,7 @@ fn test_key_pool_rotation_strategies() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
         ApiKeyInfo {
             key: SecretString::new("key3".to_string()),
@@ -147,6 +154,7 @@ fn test_key_pool_rotation_strategies() {
             models: vec!["gpt-4".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
     ];
 
@@ -176,6 +184,7 @@ fn test_key_pool_latency_tracking() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
         ApiKeyInfo {
             key: SecretString::new("slow-key".to_string()),
@@ -183,6 +192,7 @@ fn test_key_pool_latency_tracking() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
     ];
 
@@ -193,15 +203,26 @@ fn test_key_pool_latency_tracking() {
     pool.update_latency(1, Duration::from_millis(200)); // Slow key
 
     // Should prefer the faster key in least_latency mode
-    // Note: This tests the latency tracking mechanism
+    for _ in 0..3 {
+        let key = pool.get_key_for_model("gpt-3.5-turbo").unwrap();
+        assert_eq!(key.url, "https://fast-api.com");
+    }
+
+    // Passive measurements for the model take precedence over probes
+    let slow = pool.get_all_keys()[1].clone();
+    pool.record_first_byte(&slow, "gpt-3.5-turbo", Duration::from_millis(10));
+    let key = pool.get_key_for_model("gpt-3.5-turbo").unwrap();
+    assert_eq!(key.url, "https://slow-api.com");
 }
 
 #[test]
 fn test_error_response_serialization() {
-    let error = ErrorResponse::new("Test error message");
+    let error = ProxyError::RateLimited.to_openai_error();
     let json = serde_json::to_string(&error).unwrap();
-    let parsed: ErrorResponse = serde_json::from_str(&json).unwrap();
-    assert_eq!(parsed.error, "Test error message");
+    let parsed: OpenAIError = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.error.message, "Rate limit exceeded");
+    assert_eq!(parsed.error.error_type.as_deref(), Some("rate_limit_error"));
+    assert_eq!(parsed.error.code.as_deref(), Some("kcp_rate_limited"));
 }
 
 #[test]
@@ -283,6 +304,7 @@ fn test_proxy_engine_creation() {
         models: vec!["gpt-3.5-turbo".to_string()],
         latency: None,
         health_score: 1.0,
+        ..Default::default()
     }];
 
     let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));
@@ -294,6 +316,54 @@ fn test_proxy_engine_creation() {
     // This is a smoke test for the constructor
 }
 
+#[test]
+fn test_legacy_key_provider_parsing() {
+    let azure: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{
+            "key": "azure-key",
+            "url": "https://example.openai.azure.com",
+            "models": ["gpt-4o"],
+            "provider": "azure",
+            "apiVersion": "2024-06-01",
+            "deployments": {"gpt-4o": "prod-gpt4o"}
+        }"#,
+    )
+    .unwrap();
+    let key = ApiKeyInfo::try_from(azure).unwrap();
+    match &key.provider {
+        Provider::Azure(settings) => {
+            assert_eq!(settings.api_version, "2024-06-01");
+            assert_eq!(settings.deployment_for("gpt-4o"), "prod-gpt4o");
+        }
+        other => panic!("expected Azure provider, got {:?}", other),
+    }
+
+    let missing_version: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{"key": "k", "url": "https://example.openai.azure.com", "models": [], "provider": "azure"}"#,
+    )
+    .unwrap();
+    assert!(ApiKeyInfo::try_from(missing_version).is_err());
+
+    let gemini: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{"key": "k", "url": "https://generativelanguage.googleapis.com/v1beta", "models": ["gemini-1.5-pro"], "provider": "gemini", "auth": "query"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        ApiKeyInfo::try_from(gemini).unwrap().provider,
+        Provider::Gemini(GeminiSettings {
+            auth: GeminiAuth::Query
+        })
+    );
+
+    let openai: LegacyApiKeyInfo =
+        serde_json::from_str(r#"{"key": "k", "url": "https://api.openai.com", "models": []}"#)
+            .unwrap();
+    assert_eq!(
+        ApiKeyInfo::try_from(openai).unwrap().provider,
+        Provider::OpenAI
+    );
+}
+
 #[test]
 fn test_json_error_handling() {
     let invalid_json = "{ invalid json }";
@@ -325,6 +395,7 @@ fn test_key_pool_concurrent_access() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
         ApiKeyInfo {
             key: SecretString::new("key2".to_string()),
@@ -332,6 +403,7 @@ fn test_key_pool_concurrent_access() {
             models: vec!["gpt-3.5-turbo".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         },
     ];
 
@@ -366,13 +438,16 @@ async fn test_latency_measurement_timeout() {
         models: vec!["gpt-3.5-turbo".to_string()],
         latency: None,
         health_score: 1.0,
+        ..Default::default()
     }];
 
-    let pool = KeyPool::new(keys, "round_robin");
+    let pool = Arc::new(KeyPool::new(keys, "round_robin"));
+    let upstream_client = UpstreamClient::new(UpstreamConfig::default()).unwrap();
+    let prober = HealthProber::new(pool, upstream_client, &ProbesConfig::default()).unwrap();
 
     // This should complete without hanging, even though the URL is unreachable
     let start = std::time::Instant::now();
-    pool.update_all_latencies().await;
+    prober.probe_idle_keys().await;
     let duration = start.elapsed();
 
     // Should complete within reasonable time (timeout mechanism working)
@@ -381,3 +456,47 @@ async fn test_latency_measurement_timeout() {
         "Latency measurement should timeout quickly"
     );
 }
+
+#[test]
+fn test_legacy_key_tier_and_weight() {
+    let key: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{"key": "k", "url": "https://api.openai.com", "models": [], "tier": 1, "weight": 70}"#,
+    )
+    .unwrap();
+    let key = ApiKeyInfo::try_from(key).unwrap();
+    assert_eq!((key.tier, key.weight), (1, 70));
+
+    let defaults: LegacyApiKeyInfo =
+        serde_json::from_str(r#"{"key": "k", "url": "https://api.openai.com", "models": []}"#)
+            .unwrap();
+    let defaults = ApiKeyInfo::try_from(defaults).unwrap();
+    assert_eq!((defaults.tier, defaults.weight), (0, 1));
+
+    let zero: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{"key": "k", "url": "https://api.openai.com", "models": [], "weight": 0}"#,
+    )
+    .unwrap();
+    assert!(ApiKeyInfo::try_from(zero).is_err());
+}
+
+#[test]
+fn test_legacy_key_extra_headers() {
+    let key: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{
+            "key": "k",
+            "url": "https://api.openai.com",
+            "models": [],
+            "headers": {"OpenAI-Organization": "org-a", "OpenAI-Project": "proj_1"}
+        }"#,
+    )
+    .unwrap();
+    let key = ApiKeyInfo::try_from(key).unwrap();
+    assert_eq!(key.extra_headers["openai-organization"], "org-a");
+    assert_eq!(key.extra_headers["openai-project"], "proj_1");
+
+    let invalid: LegacyApiKeyInfo = serde_json::from_str(
+        r#"{"key": "k", "url": "https://api.openai.com", "models": [], "headers": {"bad header": "x"}}"#,
+    )
+    .unwrap();
+    assert!(ApiKeyInfo::try_from(invalid).is_err());
+}
@@ -58,6 +58,7 @@ async fn create_performance_test_app() -> (Router, Vec<MockServer>) {
             models: vec!["gpt-3.5-turbo".to_string(), "others".to_string()],
             latency: None,
             health_score: 1.0,
+            ..Default::default()
         });
 
         mock_servers.push(mock_server);
@@ -356,6 +357,7 @@ async fn test_timeout_handling() {
         models: vec!["gpt-3.5-turbo".to_string()],
         latency: None,
         health_score: 1.0,
+        ..Default::default()
     }];
 
     let key_pool = Arc::new(KeyPool::new(keys, "round_robin"));